@@ -1,4 +1,11 @@
+use crate::output::Format;
+use crate::selection::Selection;
+use chrono::NaiveDateTime;
 use clap::{Parser, Subcommand};
+use list_rs::due::parse_due;
+use list_rs::sort::SortOrder;
+use list_rs::{Priority, Recurrence};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -6,21 +13,123 @@ use clap::{Parser, Subcommand};
 pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Commands>,
+
+    /// Output format for listings and command results
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    /// Sort order for listings, comma separated keys out of priority, due,
+    /// created, id and text, prefixed with - for descending, e.g. priority,-due
+    #[arg(long, global = true, allow_hyphen_values = true, value_parser = parse_sort_arg)]
+    pub sort: Option<SortOrder>,
+
+    /// Database file to use instead of the one of the active profile
+    #[arg(long, global = true)]
+    pub db: Option<PathBuf>,
+
+    /// Profile to use instead of the active one, as listed by `profile list`
+    #[arg(long, global = true, conflicts_with = "db")]
+    pub profile: Option<String>,
+
+    /// Config file to use instead of $LIST_RS_CONFIG or
+    /// $XDG_CONFIG_HOME/list-rs/config.toml
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Adds a task
-    Add { text: String },
+    /// Adds a task, words starting with + are added as tags
+    Add {
+        #[arg(required = true)]
+        text: Vec<String>,
+        /// Due date, e.g. 2023-08-25, tomorrow, "next fri", "in 3 days" or eod
+        #[arg(long, value_parser = parse_due_arg)]
+        due: Option<NaiveDateTime>,
+        /// Priority: high, medium or low (or h, m, l)
+        #[arg(long, value_parser = parse_priority_arg)]
+        priority: Option<Priority>,
+        /// Repeats the task when it is done: daily, weekly, weekly:mon,thu,
+        /// monthly, monthly:15 or after:3d (3 days after it was done)
+        #[arg(long, value_parser = parse_recurrence_arg)]
+        repeat: Option<Recurrence>,
+        /// Adds the task as a subtask of the task with this number or uuid
+        #[arg(long)]
+        parent: Option<String>,
+        /// Adds the task to this project instead of the active one
+        #[arg(long)]
+        project: Option<String>,
+    },
 
-    /// Removes a task with a given id
-    Remove { id: i32 },
+    /// Removes tasks by number, range, uuid or filter
+    Remove {
+        #[command(flatten)]
+        selection: Selection,
+        /// Also removes the subtasks of the selected tasks
+        #[arg(long)]
+        cascade: bool,
+    },
 
-    /// Updates a task with a given id
-    Update { id: i32, text: String },
+    /// Updates a task with a given number or uuid
+    Update {
+        task: String,
+        text: Option<String>,
+        /// New due date, e.g. 2023-08-25, tomorrow, "next fri", "in 3 days" or eod
+        #[arg(long, value_parser = parse_due_arg)]
+        due: Option<NaiveDateTime>,
+        /// Removes the due date
+        #[arg(long, conflicts_with = "due")]
+        no_due: bool,
+        /// New priority: high, medium or low (or h, m, l)
+        #[arg(long, value_parser = parse_priority_arg)]
+        priority: Option<Priority>,
+        /// Removes the priority
+        #[arg(long, conflicts_with = "priority")]
+        no_priority: bool,
+        /// New recurrence: daily, weekly, weekly:mon,thu, monthly, monthly:15
+        /// or after:3d
+        #[arg(long, value_parser = parse_recurrence_arg)]
+        repeat: Option<Recurrence>,
+        /// Stops repeating the task
+        #[arg(long, conflicts_with = "repeat")]
+        no_repeat: bool,
+        /// Makes the task a subtask of the task with this number or uuid
+        #[arg(long)]
+        parent: Option<String>,
+        /// Makes the task a top level task
+        #[arg(long, conflicts_with = "parent")]
+        no_parent: bool,
+        /// Moves the task to this project
+        #[arg(long)]
+        project: Option<String>,
+        /// Takes the task out of its project
+        #[arg(long, conflicts_with = "project")]
+        no_project: bool,
+    },
 
-    /// Lists all pending tasks
-    List {},
+    /// Lists the pending tasks of the active project, or the tasks matching a
+    /// query
+    List {
+        /// Filter query, e.g. status:done tag:work "due<friday". Archived tasks
+        /// are only listed if the query tests the status, and other projects
+        /// if it tests the project
+        query: Vec<String>,
+        /// Only show tasks with this tag, can be repeated
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Only show tasks past their due date
+        #[arg(long)]
+        overdue: bool,
+        /// Only show tasks due on or before this date
+        #[arg(long, value_parser = parse_due_arg)]
+        due_before: Option<NaiveDateTime>,
+        /// Only show tasks due after this date
+        #[arg(long, value_parser = parse_due_arg)]
+        due_after: Option<NaiveDateTime>,
+        /// Only show tasks of this project instead of the active one
+        #[arg(long)]
+        project: Option<String>,
+    },
 
     /// List all tasks
     All {},
@@ -28,27 +137,237 @@ pub enum Commands {
     /// List archived tasks
     Archived {},
 
-    /// Sets a task with a given id to Archived
-    Archive { id: i32 },
+    /// Sets tasks to Archived by number, range, uuid or filter
+    Archive {
+        #[command(flatten)]
+        selection: Selection,
+        /// Also archives the subtasks of the selected tasks
+        #[arg(long)]
+        cascade: bool,
+    },
+
+    /// Sets tasks to Done by number, range, uuid or filter
+    Done {
+        #[command(flatten)]
+        selection: Selection,
+    },
+
+    /// Sets tasks to Undone by number, range, uuid or filter
+    Undone {
+        #[command(flatten)]
+        selection: Selection,
+    },
+
+    /// Makes a task wait for other tasks to be done, e.g. depends 7 on 3
+    Depends {
+        task: String,
+        /// The word "on"
+        #[arg(value_name = "on", value_parser = ["on"])]
+        on: String,
+        /// Numbers or uuids of the tasks that have to be done first
+        #[arg(required = true)]
+        dependencies: Vec<String>,
+        /// Removes these dependencies instead
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Lists pending tasks waiting for tasks that aren't done yet
+    Blocked {},
+
+    /// Lists pending tasks that aren't waiting for any other task
+    Ready {},
+
+    /// Adds tags to a task with a given number or uuid
+    Tag {
+        task: String,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// Removes tags from a task with a given number or uuid
+    Untag {
+        task: String,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// Lists all tags with the number of tasks using them
+    Tags {},
+
+    /// Renames a tag on every task
+    RenameTag { old: String, new: String },
+
+    /// Search task texts, best matches first
+    Search {
+        /// Words that must all appear, prefix* matches the start of a word
+        #[arg(required = true)]
+        terms: Vec<String>,
+        /// Take the terms as an SQLite FTS5 query: "a phrase", prefix* and
+        /// terms combined with OR, NOT and parentheses
+        #[arg(long, conflicts_with = "fuzzy")]
+        raw: bool,
+        /// Only search tasks matching this query, e.g. "status:undone"
+        #[arg(long = "where")]
+        query: Option<String>,
+        /// Match words with typos or left out letters, e.g. opeheimer
+        #[arg(long)]
+        fuzzy: bool,
+    },
+
+    /// Manages projects, named lists of tasks
+    Project {
+        #[command(subcommand)]
+        command: ProjectCommands,
+    },
+
+    /// Lists and switches profiles, task lists kept in separate databases
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+
+    /// Shows and changes preferences stored in the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// Lists the changes that can be undone, and the undone ones that can be
+    /// redone
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommands>,
+    },
+
+    /// Revert last change, or several at once
+    Undo {
+        /// Number of changes to revert
+        #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+        steps: Option<u32>,
+        /// Reverts the change with this id, as listed by `history`, and every
+        /// later one
+        #[arg(long, conflicts_with = "steps")]
+        to: Option<i64>,
+    },
+
+    /// Redo last change, or several at once
+    Redo {
+        /// Number of changes to reapply
+        #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+        steps: Option<u32>,
+        /// Switches to this branch, as listed by `history tree`, and reapplies
+        /// its first change or the given number of them
+        #[arg(long)]
+        branch: Option<i64>,
+    },
+
+    /// Opens the interactive mode
+    Tui {},
+}
+
+#[derive(Subcommand)]
+pub enum ProjectCommands {
+    /// Adds an empty project
+    Add { name: String },
+
+    /// Lists projects with the number of their tasks by status
+    List {},
+
+    /// Renames a project
+    Rename { old: String, new: String },
+
+    /// Removes a project without tasks
+    Remove { name: String },
+
+    /// Makes a project the active one, which new tasks are added to and
+    /// listings show. Without a name, listings show every project again
+    Use { name: Option<String> },
+}
+
+#[derive(Subcommand)]
+pub enum ProfileCommands {
+    /// Lists the profiles with the paths of their databases
+    List {},
 
-    /// Sets a task with a given id to Done
-    Done { id: i32 },
+    /// Makes a profile the active one
+    Use { name: String },
 
-    /// Sets a task with a given id to Undone
-    Undone { id: i32 },
+    /// Adds a profile storing its tasks in the given database file
+    Add { name: String, db: PathBuf },
 
-    /// Search for a task by its contents
-    Search { content: String },
+    /// Removes a profile from the config file, keeping its database
+    Remove { name: String },
+}
 
-    /// Revert last change
-    Undo {},
+#[derive(Subcommand)]
+pub enum HistoryCommands {
+    /// Shows the history with the branches of undone changes that were left
+    /// behind by new ones
+    Tree {},
 
-    /// Redo last change
-    Redo {},
+    /// Forgets old changes as the history_limit and history_max_age
+    /// preferences say, which also happens whenever list-rs starts
+    Prune {
+        /// Number of changes to keep, instead of history_limit
+        #[arg(long)]
+        keep: Option<usize>,
+        /// Forgets changes older than this number of days, instead of
+        /// history_max_age
+        #[arg(long)]
+        max_age: Option<u32>,
+        /// Rebuilds the database file afterwards to give the freed space back
+        #[arg(long)]
+        vacuum: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Lists every preference with its value
+    List {},
+
+    /// Shows the value of a preference
+    Get { key: String },
+
+    /// Changes a preference: default_command (list, all, archived, blocked or
+    /// ready), sort, date_format (e.g. %d.%m.%Y), color (true or false),
+    /// markers (emoji or ascii), default_project, default_tags (e.g. work,home),
+    /// history_limit (number of steps), history_max_age (number of days) or
+    /// busy_timeout (milliseconds)
+    Set {
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+
+    /// Resets a preference to its default
+    Unset { key: String },
 }
 
 impl Cli {
     pub fn parse_arguments() -> Self {
         Cli::parse()
     }
+
+    /// Parses a command given without arguments, e.g. `ready`.
+    pub fn parse_command(name: &str) -> Option<Commands> {
+        Cli::try_parse_from(["list-rs", name]).ok()?.command
+    }
+}
+
+fn parse_due_arg(value: &str) -> Result<NaiveDateTime, String> {
+    parse_due(value, chrono::Local::now().naive_local())
+}
+
+fn parse_priority_arg(value: &str) -> Result<Priority, String> {
+    value.parse().map_err(|e: list_rs::Error| e.to_string())
+}
+
+fn parse_recurrence_arg(value: &str) -> Result<Recurrence, String> {
+    value.parse().map_err(|e: list_rs::Error| e.to_string())
+}
+
+fn parse_sort_arg(value: &str) -> Result<SortOrder, String> {
+    value.parse().map_err(|e: list_rs::Error| e.to_string())
 }
@@ -0,0 +1,494 @@
+use list_rs::due::check_date_format;
+use list_rs::sort::SortOrder;
+use list_rs::task::{parse_tag, DisplayStyle, Markers};
+use list_rs::{Error, Result, DEFAULT_BUSY_TIMEOUT};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The profile that is used when no other one is picked. It stores its tasks
+/// at [`default_database_path`] unless the config file defines it.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The preferences read and changed by `config get` and `config set`, in the
+/// order `config list` shows them.
+pub const KEYS: [&str; 10] = [
+    "default_command",
+    "sort",
+    "date_format",
+    "color",
+    "markers",
+    "default_project",
+    "default_tags",
+    "history_limit",
+    "history_max_age",
+    "busy_timeout",
+];
+
+/// The keys of the config file besides the preferences.
+const FIELDS: [&str; 2] = ["profile", "profiles"];
+
+/// The listings that can be run when no command is given.
+const DEFAULT_COMMANDS: [&str; 5] = ["list", "all", "archived", "blocked", "ready"];
+
+/// The settings read from `config.toml`, see [`config_path`].
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The listing run when no command is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_command: Option<String>,
+    /// The sort order of listings when `--sort` isn't given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    /// The strftime format of due dates more than a week away.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_format: Option<String>,
+    /// Turns colors on or off, by default they are used on terminals.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<bool>,
+    /// The status markers of tasks, emoji or ascii.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub markers: Option<String>,
+    /// The project of new tasks when no project is active.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_project: Option<String>,
+    /// Tags added to every new task.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub default_tags: Vec<String>,
+    /// The number of steps kept in the undo history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_limit: Option<usize>,
+    /// The number of days steps are kept in the undo history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_max_age: Option<u32>,
+    /// Milliseconds to wait for other processes writing to the database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub busy_timeout: Option<u64>,
+    /// The profile used when neither `--db` nor `--profile` is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named task list stored in its own database.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Path of the database, `~` stands for the home directory and relative
+    /// paths start at the directory of the config file.
+    pub db: String,
+}
+
+impl Config {
+    /// Reads the config file at `path`, or returns the default settings if
+    /// there is none.
+    pub fn load(path: &Path) -> Result<Config> {
+        match Config::load_lenient(path)? {
+            (config, problems) if problems.is_empty() => Ok(config),
+            (_, problems) => Err(Error::InvalidInput(format!(
+                "invalid config file {}: {}",
+                path.display(),
+                problems[0]
+            ))),
+        }
+    }
+
+    /// Reads the config file at `path` like [`Config::load`], but skips
+    /// unknown keys and invalid preferences, returned as problems naming
+    /// them. Only a file that isn't TOML at all fails.
+    pub fn load_lenient(path: &Path) -> Result<(Config, Vec<String>)> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((Config::default(), Vec::new()))
+            }
+            Err(e) => return Err(Error::Io(e)),
+        };
+        Config::parse(&text).map_err(|e| {
+            Error::InvalidInput(format!("invalid config file {}: {}", path.display(), e))
+        })
+    }
+
+    /// Reads the settings from TOML, checking the preferences like
+    /// [`Config::set`] does so problems name the offending key.
+    fn parse(text: &str) -> std::result::Result<(Config, Vec<String>), String> {
+        let mut table: toml::Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| e.message().to_string())?;
+        let preferences: Vec<(&str, toml::Value)> = KEYS
+            .iter()
+            .filter_map(|&key| table.remove(key).map(|value| (key, value)))
+            .collect();
+        let mut problems: Vec<String> = table
+            .keys()
+            .filter(|key| !FIELDS.contains(&key.as_str()))
+            .map(|key| format!("unknown key `{}`", key))
+            .collect();
+        table.retain(|key, _| FIELDS.contains(&key));
+
+        let mut config: Config = table
+            .try_into()
+            .map_err(|e: toml::de::Error| e.message().to_string())?;
+        for (key, value) in preferences {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Array(values) => values
+                    .into_iter()
+                    .map(|value| match value {
+                        toml::Value::String(value) => value,
+                        value => value.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+                value => value.to_string(),
+            };
+            if let Err(e) = config.set(key, &value) {
+                problems.push(e.to_string());
+            }
+        }
+        Ok((config, problems))
+    }
+
+    /// Writes the settings to `path`, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string_pretty(self)
+            .map_err(|e| Error::InvalidInput(format!("can't write the config file: {}", e)))?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// The value of a preference as text, or `None` if it isn't set.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let value = match key {
+            "default_command" => self.default_command.clone(),
+            "sort" => self.sort.clone(),
+            "date_format" => self.date_format.clone(),
+            "color" => self.color.map(|color| color.to_string()),
+            "markers" => self.markers.clone(),
+            "default_project" => self.default_project.clone(),
+            "default_tags" if self.default_tags.is_empty() => None,
+            "default_tags" => Some(self.default_tags.join(",")),
+            "history_limit" => self.history_limit.map(|limit| limit.to_string()),
+            "history_max_age" => self.history_max_age.map(|days| days.to_string()),
+            "busy_timeout" => self.busy_timeout.map(|timeout| timeout.to_string()),
+            _ => return Err(unknown_key(key)),
+        };
+        Ok(value)
+    }
+
+    /// Sets a preference given as text, after checking the value.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let invalid = |reason: &str| {
+            Error::InvalidInput(format!(
+                "invalid value '{}' for config key '{}': {}",
+                value, key, reason
+            ))
+        };
+        match key {
+            "default_command" => {
+                if !DEFAULT_COMMANDS.contains(&value) {
+                    return Err(invalid(&format!(
+                        "expected one of {}",
+                        DEFAULT_COMMANDS.join(", ")
+                    )));
+                }
+                self.default_command = Some(value.to_string());
+            }
+            "sort" => {
+                value
+                    .parse::<SortOrder>()
+                    .map_err(|e| invalid(&e.to_string()))?;
+                self.sort = Some(value.to_string());
+            }
+            "date_format" => {
+                check_date_format(value).map_err(|e| invalid(&e))?;
+                self.date_format = Some(value.to_string());
+            }
+            "color" => {
+                self.color = Some(match value.to_lowercase().as_str() {
+                    "true" | "yes" | "on" => true,
+                    "false" | "no" | "off" => false,
+                    _ => return Err(invalid("expected true or false")),
+                });
+            }
+            "markers" => {
+                let markers: Markers = value
+                    .parse()
+                    .map_err(|_| invalid("expected emoji or ascii"))?;
+                self.markers = Some(markers.to_string());
+            }
+            "default_project" => {
+                let name = value.trim();
+                if name.is_empty() || name.contains(',') {
+                    return Err(invalid("project names can't be empty or contain commas"));
+                }
+                self.default_project = Some(name.to_string());
+            }
+            "default_tags" => {
+                let mut tags = Vec::new();
+                for tag in value.split([',', ' ']).filter(|tag| !tag.is_empty()) {
+                    let tag = parse_tag(tag)
+                        .ok_or_else(|| invalid(&format!("'{}' isn't a valid tag", tag)))?;
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                }
+                self.default_tags = tags;
+            }
+            "history_limit" => {
+                let limit = value
+                    .parse()
+                    .map_err(|_| invalid("expected a number of steps"))?;
+                self.history_limit = Some(limit);
+            }
+            "history_max_age" => {
+                let days = value
+                    .parse()
+                    .map_err(|_| invalid("expected a number of days"))?;
+                self.history_max_age = Some(days);
+            }
+            "busy_timeout" => {
+                let timeout = value
+                    .parse()
+                    .map_err(|_| invalid("expected a number of milliseconds"))?;
+                self.busy_timeout = Some(timeout);
+            }
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    /// Clears a preference so that its default is used again.
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        match key {
+            "default_command" => self.default_command = None,
+            "sort" => self.sort = None,
+            "date_format" => self.date_format = None,
+            "color" => self.color = None,
+            "markers" => self.markers = None,
+            "default_project" => self.default_project = None,
+            "default_tags" => self.default_tags.clear(),
+            "history_limit" => self.history_limit = None,
+            "history_max_age" => self.history_max_age = None,
+            "busy_timeout" => self.busy_timeout = None,
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    /// The sort order of listings when `--sort` isn't given.
+    pub fn sort_order(&self) -> Option<SortOrder> {
+        self.sort.as_ref().and_then(|sort| sort.parse().ok())
+    }
+
+    /// How tasks are displayed.
+    pub fn display_style(&self) -> DisplayStyle {
+        let default = DisplayStyle::default();
+        DisplayStyle {
+            markers: self
+                .markers
+                .as_ref()
+                .and_then(|markers| markers.parse().ok())
+                .unwrap_or(default.markers),
+            date_format: self.date_format.clone().unwrap_or(default.date_format),
+        }
+    }
+
+    /// How long to wait for other processes writing to the database.
+    pub fn busy_timeout(&self) -> Duration {
+        self.busy_timeout
+            .map_or(DEFAULT_BUSY_TIMEOUT, Duration::from_millis)
+    }
+
+    /// The name of the profile used by default.
+    pub fn active_profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+
+    /// Every profile by name with the path of its database, including the
+    /// default one.
+    pub fn profiles(&self, config_path: &Path) -> Vec<(String, PathBuf)> {
+        let mut profiles: Vec<(String, PathBuf)> = self
+            .profiles
+            .iter()
+            .map(|(name, profile)| (name.clone(), resolve_path(&profile.db, config_path)))
+            .collect();
+        if !self.profiles.contains_key(DEFAULT_PROFILE) {
+            profiles.insert(0, (DEFAULT_PROFILE.to_string(), default_database_path()));
+        }
+        profiles
+    }
+
+    /// The database path of the profile with the given name.
+    pub fn profile_path(&self, name: &str, config_path: &Path) -> Result<PathBuf> {
+        self.profiles(config_path)
+            .into_iter()
+            .find(|(profile, _)| profile == name)
+            .map(|(_, path)| path)
+            .ok_or_else(|| {
+                Error::InvalidInput(format!(
+                    "No profile named '{}', add it with `list-rs profile add {} PATH`",
+                    name, name
+                ))
+            })
+    }
+}
+
+fn unknown_key(key: &str) -> Error {
+    Error::InvalidInput(format!(
+        "unknown config key '{}', expected one of {}",
+        key,
+        KEYS.join(", ")
+    ))
+}
+
+/// The home directory, or the current one if `HOME` isn't set.
+fn home_dir() -> PathBuf {
+    env::var_os("HOME").map_or_else(|| PathBuf::from("."), PathBuf::from)
+}
+
+/// A base directory from the XDG spec: the value of `variable` if it is an
+/// absolute path, or `fallback` below the home directory.
+fn xdg_dir(variable: &str, fallback: &str) -> PathBuf {
+    match env::var_os(variable).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => home_dir().join(fallback),
+    }
+}
+
+/// The config file, `LIST_RS_CONFIG` or else
+/// `$XDG_CONFIG_HOME/list-rs/config.toml`.
+pub fn config_path() -> PathBuf {
+    match env::var_os("LIST_RS_CONFIG") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => xdg_dir("XDG_CONFIG_HOME", ".config").join("list-rs/config.toml"),
+    }
+}
+
+/// The database of the default profile, `$XDG_DATA_HOME/list-rs/tasks.db`.
+pub fn default_database_path() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", ".local/share").join("list-rs/tasks.db")
+}
+
+/// Expands a leading `~` and makes relative paths start at the directory of
+/// the config file.
+fn resolve_path(path: &str, config_path: &Path) -> PathBuf {
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => home_dir().join(rest),
+        None if path == "~" => home_dir(),
+        None => PathBuf::from(path),
+    };
+    match (path.is_absolute(), config_path.parent()) {
+        (false, Some(dir)) => dir.join(path),
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_path, Config, Profile, DEFAULT_PROFILE};
+    use list_rs::task::Markers;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    #[test]
+    fn profiles_should_be_read_from_toml() {
+        let config: Config =
+            toml::from_str("profile = \"work\"\n\n[profiles.work]\ndb = \"/data/work.db\"\n")
+                .unwrap();
+
+        assert_eq!("work", config.active_profile());
+        assert_eq!(
+            Some(&Profile {
+                db: String::from("/data/work.db")
+            }),
+            config.profiles.get("work")
+        );
+        assert_eq!(DEFAULT_PROFILE, Config::default().active_profile());
+    }
+
+    #[test]
+    fn preferences_should_be_read_from_toml() {
+        let config = Config::parse(
+            "sort = \"priority,-due\"\ncolor = false\nmarkers = \"ascii\"\ndefault_tags = [\"work\", \"+urgent\"]\nhistory_limit = 50\nhistory_max_age = 90\nbusy_timeout = 10000\n",
+        )
+        .unwrap()
+        .0;
+
+        assert_eq!(
+            Some(String::from("priority,-due")),
+            config.get("sort").unwrap()
+        );
+        assert_eq!(Some(false), config.color);
+        assert_eq!(Markers::Ascii, config.display_style().markers);
+        assert_eq!(vec!["work", "urgent"], config.default_tags);
+        assert_eq!(Some(50), config.history_limit);
+        assert_eq!(Some(90), config.history_max_age);
+        assert_eq!(Duration::from_secs(10), config.busy_timeout());
+        assert_eq!(None, config.get("date_format").unwrap());
+    }
+
+    #[test]
+    fn invalid_preferences_should_name_their_key() {
+        for (text, key) in [
+            ("sort = \"size\"", "'sort'"),
+            ("color = 3", "'color'"),
+            ("history_limit = -1", "'history_limit'"),
+            ("busy_timeout = \"1s\"", "'busy_timeout'"),
+            ("date_format = \"%Q\"", "'date_format'"),
+            ("colour = true", "`colour`"),
+        ] {
+            let (_, problems) = Config::parse(text).unwrap();
+            assert!(problems[0].contains(key), "{:?}", problems);
+        }
+
+        let mut config = Config::default();
+        assert!(config.set("default_command", "remove").is_err());
+        assert!(config.set("theme", "dark").is_err());
+        config.set("default_command", "ready").unwrap();
+        config.unset("default_command").unwrap();
+        assert_eq!(Config::default(), config);
+    }
+
+    #[test]
+    fn invalid_preferences_should_not_hide_the_others() {
+        let (config, problems) =
+            Config::parse("sort = \"size\"\ncolour = true\ncolor = false\nprofile = \"work\"\n")
+                .unwrap();
+
+        assert_eq!(2, problems.len());
+        assert_eq!(None, config.get("sort").unwrap());
+        assert_eq!(Some(false), config.color);
+        assert_eq!("work", config.active_profile());
+        assert!(Config::parse("sort = ").is_err());
+    }
+
+    #[test]
+    fn unknown_profiles_should_be_an_error() {
+        let config_path = Path::new("/config/list-rs/config.toml");
+        let config = Config::default();
+
+        assert!(config.profile_path(DEFAULT_PROFILE, config_path).is_ok());
+        assert!(config.profile_path("work", config_path).is_err());
+    }
+
+    #[test]
+    fn relative_paths_should_start_at_the_config_dir() {
+        let config_path = Path::new("/config/list-rs/config.toml");
+
+        assert_eq!(
+            PathBuf::from("/config/list-rs/work.db"),
+            resolve_path("work.db", config_path)
+        );
+        assert_eq!(
+            PathBuf::from("/data/work.db"),
+            resolve_path("/data/work.db", config_path)
+        );
+    }
+}
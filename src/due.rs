@@ -0,0 +1,269 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+/// The format of due dates more than a week away, unless a
+/// [`DisplayStyle`](crate::task::DisplayStyle) sets another one.
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// How a due date relates to the current time, used to pick its color.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DueState {
+    Overdue,
+    Today,
+    Upcoming,
+}
+
+/// Parses a due date given on the command line, relative to `now`.
+///
+/// Accepts absolute dates (`2023-08-25`, `2023-08-25 18:00`) and the phrases
+/// `today`/`eod`, `tomorrow`, `yesterday`, `eow`, `eom`, weekday names with an
+/// optional `next` (`fri`, `next friday`, always a day after today) and offsets
+/// such as `in 3 days`, `in 2 weeks`, `in 1 month` or `in 4 hours`. Dates without
+/// a time are due at the end of that day.
+pub fn parse_due(input: &str, now: NaiveDateTime) -> Result<NaiveDateTime, String> {
+    let input = input.trim().to_lowercase();
+    let today = now.date();
+
+    if let Some(due) = parse_absolute(&input) {
+        return Ok(due);
+    }
+
+    let due = match input.as_str() {
+        "today" | "eod" => end_of_day(today),
+        "tomorrow" | "tmr" => end_of_day(today + Duration::days(1)),
+        "yesterday" => end_of_day(today - Duration::days(1)),
+        "eow" => {
+            end_of_day(today + Duration::days(6 - today.weekday().num_days_from_monday() as i64))
+        }
+        "eom" => end_of_day((today.with_day(1).unwrap() + Months::new(1)) - Duration::days(1)),
+        _ => {
+            if let Some(offset) = input.strip_prefix("in ") {
+                parse_offset(offset, now)?
+            } else {
+                let weekday = input.strip_prefix("next ").unwrap_or(&input);
+                match parse_weekday(weekday) {
+                    Some(weekday) => end_of_day(next_weekday(today, weekday)),
+                    None => return Err(format!("can't understand due date '{}'", input)),
+                }
+            }
+        }
+    };
+
+    Ok(due)
+}
+
+/// Describes a due date relative to `now`, e.g. "due tomorrow" or
+/// "overdue by 2 days". Dates more than a week away are written with the
+/// strftime `date_format`.
+pub fn describe_due(
+    due: NaiveDateTime,
+    now: NaiveDateTime,
+    date_format: &str,
+) -> (String, DueState) {
+    let days = (due.date() - now.date()).num_days();
+    let time = if due.time() == end_of_day_time() {
+        String::new()
+    } else {
+        format!(" {}", due.format("%H:%M"))
+    };
+
+    if due < now {
+        let description = match days {
+            0 => format!("overdue since{}", time),
+            -1 => String::from("overdue since yesterday"),
+            _ => format!("overdue by {} days", -days),
+        };
+        (description, DueState::Overdue)
+    } else if days == 0 {
+        (format!("due today{}", time), DueState::Today)
+    } else {
+        let description = match days {
+            1 => format!("due tomorrow{}", time),
+            2..=7 => format!("due in {} days", days),
+            _ => format!("due {}", due.format(date_format)),
+        };
+        (description, DueState::Upcoming)
+    }
+}
+
+/// Checks a strftime format for dates, e.g. `%d.%m.%Y`.
+pub fn check_date_format(format: &str) -> Result<(), String> {
+    if format.is_empty() {
+        return Err(String::from("date formats can't be empty"));
+    }
+    match StrftimeItems::new(format).any(|item| item == Item::Error) {
+        true => Err(format!("'{}' isn't a valid date format", format)),
+        false => Ok(()),
+    }
+}
+
+pub(crate) fn end_of_day_time() -> NaiveTime {
+    NaiveTime::from_hms_opt(23, 59, 59).unwrap()
+}
+
+fn end_of_day(date: NaiveDate) -> NaiveDateTime {
+    date.and_time(end_of_day_time())
+}
+
+fn parse_absolute(input: &str) -> Option<NaiveDateTime> {
+    for format in ["%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(due) = NaiveDateTime::parse_from_str(input, format) {
+            return Some(due);
+        }
+    }
+
+    NaiveDate::parse_from_str(input, "%Y-%m-%d")
+        .ok()
+        .map(end_of_day)
+}
+
+fn parse_offset(offset: &str, now: NaiveDateTime) -> Result<NaiveDateTime, String> {
+    let invalid = || format!("can't understand due date 'in {}'", offset);
+
+    let (amount, unit) = offset.split_once(' ').ok_or_else(invalid)?;
+    let amount: u32 = match amount {
+        "a" | "an" => 1,
+        _ => amount.parse().map_err(|_| invalid())?,
+    };
+
+    let today = now.date();
+    let due = match unit.trim_end_matches('s') {
+        "hour" => now + Duration::hours(amount as i64),
+        "day" => end_of_day(today + Duration::days(amount as i64)),
+        "week" => end_of_day(today + Duration::weeks(amount as i64)),
+        "month" => end_of_day(today + Months::new(amount)),
+        _ => return Err(invalid()),
+    };
+
+    Ok(due)
+}
+
+pub(crate) fn parse_weekday(input: &str) -> Option<Weekday> {
+    let weekday = match input {
+        "mon" | "monday" => Weekday::Mon,
+        "tue" | "tues" | "tuesday" => Weekday::Tue,
+        "wed" | "wednesday" => Weekday::Wed,
+        "thu" | "thurs" | "thursday" => Weekday::Thu,
+        "fri" | "friday" => Weekday::Fri,
+        "sat" | "saturday" => Weekday::Sat,
+        "sun" | "sunday" => Weekday::Sun,
+        _ => return None,
+    };
+
+    Some(weekday)
+}
+
+/// The first date after `today` falling on `weekday`.
+fn next_weekday(today: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let days =
+        (weekday.num_days_from_monday() as i64 - today.weekday().num_days_from_monday() as i64 + 6)
+            % 7
+            + 1;
+    today + Duration::days(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_date_format, describe_due, parse_due, DueState, DEFAULT_DATE_FORMAT};
+    use chrono::NaiveDateTime;
+
+    /// A Wednesday afternoon
+    fn now() -> NaiveDateTime {
+        datetime("2023-08-16 14:30:00")
+    }
+
+    fn datetime(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn absolute_dates_should_be_parsed() {
+        assert_eq!(
+            datetime("2023-09-01 23:59:59"),
+            parse_due("2023-09-01", now()).unwrap()
+        );
+        assert_eq!(
+            datetime("2023-09-01 18:00:00"),
+            parse_due("2023-09-01 18:00", now()).unwrap()
+        );
+    }
+
+    #[test]
+    fn phrases_should_be_parsed() {
+        let cases = [
+            ("today", "2023-08-16 23:59:59"),
+            ("EOD", "2023-08-16 23:59:59"),
+            ("tomorrow", "2023-08-17 23:59:59"),
+            ("yesterday", "2023-08-15 23:59:59"),
+            ("eow", "2023-08-20 23:59:59"),
+            ("eom", "2023-08-31 23:59:59"),
+            ("fri", "2023-08-18 23:59:59"),
+            ("next fri", "2023-08-18 23:59:59"),
+            ("wednesday", "2023-08-23 23:59:59"),
+            ("in 3 days", "2023-08-19 23:59:59"),
+            ("in a week", "2023-08-23 23:59:59"),
+            ("in 2 months", "2023-10-16 23:59:59"),
+            ("in 4 hours", "2023-08-16 18:30:00"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                datetime(expected),
+                parse_due(input, now()).unwrap(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_dates_should_be_rejected() {
+        for input in ["someday", "in x days", "in 3 fortnights", "2023-13-01", ""] {
+            assert!(parse_due(input, now()).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn due_dates_should_be_described() {
+        let cases = [
+            (
+                "2023-08-14 23:59:59",
+                "overdue by 2 days",
+                DueState::Overdue,
+            ),
+            (
+                "2023-08-15 23:59:59",
+                "overdue since yesterday",
+                DueState::Overdue,
+            ),
+            (
+                "2023-08-16 09:00:00",
+                "overdue since 09:00",
+                DueState::Overdue,
+            ),
+            ("2023-08-16 23:59:59", "due today", DueState::Today),
+            ("2023-08-16 18:00:00", "due today 18:00", DueState::Today),
+            ("2023-08-17 23:59:59", "due tomorrow", DueState::Upcoming),
+            ("2023-08-20 23:59:59", "due in 4 days", DueState::Upcoming),
+            ("2023-09-20 23:59:59", "due 2023-09-20", DueState::Upcoming),
+        ];
+
+        for (due, description, state) in cases {
+            assert_eq!(
+                (String::from(description), state),
+                describe_due(datetime(due), now(), DEFAULT_DATE_FORMAT)
+            );
+        }
+    }
+
+    #[test]
+    fn date_formats_should_be_checked() {
+        assert_eq!(
+            "due 20.09.2023",
+            describe_due(datetime("2023-09-20 23:59:59"), now(), "%d.%m.%Y").0
+        );
+        assert!(check_date_format("%d.%m.%Y").is_ok());
+        assert!(check_date_format("%Q").is_err());
+        assert!(check_date_format("").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt::Display;
+
+/// Everything that can go wrong when working with a [`TaskStore`](crate::TaskStore).
+#[derive(Debug)]
+pub enum Error {
+    /// There is no task with the given id, or no pending task with the given
+    /// number.
+    NotFound(i32),
+    /// A value given by the user is not valid, e.g. an unknown task status.
+    InvalidInput(String),
+    /// A filter query can't be parsed. `position` is the character of `query`
+    /// the problem was found at.
+    InvalidQuery {
+        query: String,
+        position: usize,
+        message: String,
+    },
+    /// The database was written by a newer list-rs with a schema this version
+    /// doesn't know.
+    UnsupportedSchema { found: i32, supported: i32 },
+    /// The undo/redo history refers to data that doesn't exist or can't be
+    /// understood.
+    CorruptedHistory(String),
+    /// The underlying SQLite database failed.
+    Storage(rusqlite::Error),
+    /// Reading or writing outside of the database failed.
+    Io(std::io::Error),
+}
+
+/// Result type of the list-rs API.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound(id) => write!(f, "Task {} does not exist", id),
+            Error::InvalidInput(message) => write!(f, "{}", message),
+            Error::InvalidQuery {
+                query,
+                position,
+                message,
+            } => write!(
+                f,
+                "invalid query: {}\n  {}\n  {}^",
+                message,
+                query,
+                " ".repeat(*position)
+            ),
+            Error::UnsupportedSchema { found, supported } => write!(
+                f,
+                "database schema version {} is newer than the supported version {}, please upgrade list-rs",
+                found, supported
+            ),
+            Error::CorruptedHistory(message) => write!(f, "history is corrupted: {}", message),
+            Error::Storage(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Storage(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Storage(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
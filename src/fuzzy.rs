@@ -0,0 +1,258 @@
+//! Typo tolerant matching of search words against task texts, used by
+//! [`TaskStore::fuzzy_search`](crate::TaskStore::fuzzy_search).
+//!
+//! Every word of the pattern is compared with every word of the text and the
+//! best match counts: the same word scores highest, then a word starting with
+//! or containing it, then one containing its letters in order (`opnhmr` in
+//! `Oppenheimer`), then one a few typos away (`opeheimer`).
+
+use std::ops::Range;
+
+// Scores of the different kinds of word matches, a subsequence or typo match
+// is scaled down further by how close it is.
+const EXACT: f64 = 1.0;
+const PREFIX: f64 = 0.9;
+const SUBSTRING: f64 = 0.8;
+const SUBSEQUENCE: f64 = 0.7;
+const TYPO: f64 = 0.5;
+
+/// The characters of a word in lower case, with their byte offsets in the
+/// text.
+type Word = Vec<(usize, char)>;
+
+/// Matches every word of `pattern` against `text`. Returns a score between 0
+/// and 1 and the byte ranges of the matched characters in `text`, or `None` if
+/// a word of the pattern doesn't match.
+pub(crate) fn fuzzy_match(pattern: &str, text: &str) -> Option<(f64, Vec<Range<usize>>)> {
+    let pattern_words: Vec<Vec<char>> = words(pattern)
+        .into_iter()
+        .map(|word| word.into_iter().map(|(_, c)| c).collect())
+        .collect();
+    if pattern_words.is_empty() {
+        return None;
+    }
+    let text_words = words(text);
+
+    let mut total = 0.0;
+    let mut offsets = Vec::new();
+    for pattern_word in &pattern_words {
+        let (score, word_offsets) = text_words
+            .iter()
+            .filter_map(|text_word| match_word(pattern_word, text_word))
+            // The first of equally good matches
+            .reduce(|best, other| if other.0 > best.0 { other } else { best })?;
+        total += score;
+        offsets.extend(word_offsets);
+    }
+
+    Some((total / pattern_words.len() as f64, to_ranges(text, offsets)))
+}
+
+/// Splits `text` into words of letters and digits.
+fn words(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut word = Word::new();
+    for (offset, c) in text.char_indices() {
+        if c.is_alphanumeric() {
+            word.push((offset, c.to_lowercase().next().unwrap_or(c)));
+        } else if !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Scores a single word of the pattern against a word of the text, returning
+/// the offsets of the matched characters.
+fn match_word(pattern: &[char], word: &Word) -> Option<(f64, Vec<usize>)> {
+    let chars: Vec<char> = word.iter().map(|&(_, c)| c).collect();
+    let offsets = |indices: &mut dyn Iterator<Item = usize>| -> Vec<usize> {
+        indices.map(|index| word[index].0).collect()
+    };
+
+    if let Some(start) = chars
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+    {
+        let score = match start {
+            0 if chars.len() == pattern.len() => EXACT,
+            0 => PREFIX,
+            _ => SUBSTRING,
+        };
+        return Some((score, offsets(&mut (start..start + pattern.len()))));
+    }
+
+    if let Some(indices) = tightest_subsequence(pattern, &chars) {
+        let span = indices[indices.len() - 1] - indices[0] + 1;
+        let density = pattern.len() as f64 / span as f64;
+        if density >= 0.5 {
+            return Some((SUBSEQUENCE * density, offsets(&mut indices.into_iter())));
+        }
+    }
+
+    // Typos are only told apart from different words in longer words
+    if pattern.len() < 4 {
+        return None;
+    }
+    let distance = edit_distance(pattern, &chars);
+    if distance > pattern.len() / 4 + 1 {
+        return None;
+    }
+    let score = TYPO * (1.0 - distance as f64 / pattern.len() as f64);
+    Some((
+        score,
+        offsets(&mut common_subsequence(pattern, &chars).into_iter()),
+    ))
+}
+
+/// Finds the shortest stretch of `chars` containing `pattern` in order and
+/// returns the indices of the matched characters.
+fn tightest_subsequence(pattern: &[char], chars: &[char]) -> Option<Vec<usize>> {
+    (0..chars.len())
+        .filter(|&start| chars[start] == pattern[0])
+        .filter_map(|start| {
+            let mut indices = vec![start];
+            let mut position = start + 1;
+            for &c in &pattern[1..] {
+                let offset = chars[position..].iter().position(|&other| other == c)?;
+                position += offset;
+                indices.push(position);
+                position += 1;
+            }
+            Some(indices)
+        })
+        .min_by_key(|indices| indices[indices.len() - 1] - indices[0])
+}
+
+/// The number of insertions, deletions, substitutions and swaps of adjacent
+/// characters turning `a` into `b`.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+/// Indices into `chars` of a longest common subsequence with `pattern`, the
+/// characters a typo tolerant match highlights.
+fn common_subsequence(pattern: &[char], chars: &[char]) -> Vec<usize> {
+    let mut lengths = vec![vec![0; chars.len() + 1]; pattern.len() + 1];
+    for i in (0..pattern.len()).rev() {
+        for j in (0..chars.len()).rev() {
+            lengths[i][j] = if pattern[i] == chars[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut indices = Vec::new();
+    while i < pattern.len() && j < chars.len() {
+        if pattern[i] == chars[j] {
+            indices.push(j);
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    indices
+}
+
+/// Turns the byte offsets of matched characters into sorted, merged ranges.
+fn to_ranges(text: &str, mut offsets: Vec<usize>) -> Vec<Range<usize>> {
+    offsets.sort();
+    offsets.dedup();
+
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for offset in offsets {
+        let end = offset + text[offset..].chars().next().map_or(1, char::len_utf8);
+        match ranges.last_mut() {
+            Some(range) if range.end == offset => range.end = end,
+            _ => ranges.push(offset..end),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, fuzzy_match};
+
+    fn score(pattern: &str, text: &str) -> f64 {
+        fuzzy_match(pattern, text).map_or(0.0, |(score, _)| score)
+    }
+
+    fn highlighted(pattern: &str, text: &str) -> String {
+        let (_, ranges) = fuzzy_match(pattern, text).unwrap();
+        ranges.iter().map(|range| &text[range.clone()]).collect()
+    }
+
+    #[test]
+    fn typos_should_be_tolerated() {
+        assert!(score("opeheimer", "Watch Oppenheimer") > 0.0);
+        assert!(score("oppenhiemer", "Watch Oppenheimer") > 0.0);
+        assert!(score("oppenheinmer", "Watch Oppenheimer") > 0.0);
+        assert_eq!(0.0, score("barbie", "Watch Oppenheimer"));
+        assert_eq!(0.0, score("watch barbie", "Watch Oppenheimer"));
+    }
+
+    #[test]
+    fn closer_matches_should_score_higher() {
+        let exact = score("report", "Write the report");
+        let prefix = score("rep", "Write the report");
+        let subsequence = score("rprt", "Write the report");
+        let typo = score("reprot", "Write the report");
+
+        assert!(exact > prefix, "{} {}", exact, prefix);
+        assert!(prefix > subsequence, "{} {}", prefix, subsequence);
+        assert!(subsequence > typo, "{} {}", subsequence, typo);
+    }
+
+    #[test]
+    fn matched_characters_should_be_highlighted() {
+        assert_eq!(
+            "Oppenheimer",
+            highlighted("oppenheimer", "Watch Oppenheimer")
+        );
+        assert_eq!("rprt", highlighted("rprt", "Write the report"));
+        assert_eq!("Čaj", highlighted("čaj", "Uvařit Čaj"));
+
+        let (_, ranges) = fuzzy_match("rep", "Write the report").unwrap();
+        assert_eq!(vec![10..13], ranges);
+    }
+
+    #[test]
+    fn edit_distance_should_count_swaps_once() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+
+        assert_eq!(1, edit_distance(&chars("reprot"), &chars("report")));
+        assert_eq!(2, edit_distance(&chars("opeheimer"), &chars("oppenheimer")));
+        assert_eq!(3, edit_distance(&chars("kitten"), &chars("sitting")));
+    }
+}
@@ -0,0 +1,573 @@
+use crate::error::{Error, Result};
+use crate::task::{Task, TaskStatus};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use rusqlite::types::FromSql;
+use std::collections::HashMap;
+use std::{fmt::Display, str::FromStr};
+
+/// The kind of change recorded in the undo/redo log.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Create => write!(f, "Create"),
+            Operation::Update => write!(f, "Update"),
+            Operation::Delete => write!(f, "Delete"),
+        }
+    }
+}
+
+impl FromStr for Operation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Create" => Ok(Operation::Create),
+            "Update" => Ok(Operation::Update),
+            "Delete" => Ok(Operation::Delete),
+            _ => Err(Error::InvalidInput(format!("invalid operation '{}'", s))),
+        }
+    }
+}
+
+impl FromSql for Operation {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| rusqlite::types::FromSqlError::Other(Box::new(e)))
+    }
+}
+
+/// A single entry of the undo or redo log.
+///
+/// `before` is the task as it was prior to the operation (`None` for a
+/// create) and `after` is the task as the operation left it (`None` for a
+/// delete). Undoing an entry restores `before`, redoing it restores `after`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub operation: Operation,
+    pub task_id: i32,
+    pub before: Option<Task>,
+    pub after: Option<Task>,
+}
+
+/// A step of the history: everything a single command changed, undone and
+/// redone at once.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HistoryStep {
+    /// Identifies the step, e.g. for [`TaskStore::undo_to`](crate::TaskStore::undo_to).
+    pub id: i64,
+    /// When the step was made, undoing and redoing it keeps the time.
+    pub created_at: Option<NaiveDateTime>,
+    /// Whether the step was undone and can be redone.
+    pub undone: bool,
+    /// The changes of the step, oldest first.
+    pub entries: Vec<HistoryEntry>,
+}
+
+/// Undone steps that were left behind when a new change was made instead of
+/// redoing them. [`TaskStore::redo_branch`](crate::TaskStore::redo_branch)
+/// switches back to them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HistoryBranch {
+    pub id: i64,
+    /// The step the branch continues from, `None` if it starts at the
+    /// beginning of the history.
+    pub fork: Option<i64>,
+    /// When the branch was left behind.
+    pub created_at: Option<NaiveDateTime>,
+    /// The steps of the branch, the one following the fork first.
+    pub steps: Vec<HistoryStep>,
+}
+
+/// What the tasks and projects that changes refer to are called now, so that
+/// [`HistoryEntry::describe`] doesn't show database ids. Read with
+/// [`TaskStore::read_history_names`](crate::TaskStore::read_history_names).
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct HistoryNames {
+    /// Labels of the tasks by database id, see [`Task::label`]. Removed tasks
+    /// are named by the start of their uuid.
+    pub tasks: HashMap<i32, String>,
+    /// Names of the projects by database id.
+    pub projects: HashMap<i32, String>,
+}
+
+impl HistoryNames {
+    fn task(&self, id: i32) -> String {
+        self.tasks
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| String::from("unknown"))
+    }
+
+    fn project(&self, id: i32) -> String {
+        self.projects
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| String::from("removed"))
+    }
+}
+
+impl HistoryEntry {
+    /// The entry that reverts this one.
+    pub fn reverted(&self) -> HistoryEntry {
+        HistoryEntry {
+            id: self.id,
+            operation: match self.operation {
+                Operation::Create => Operation::Delete,
+                Operation::Update => Operation::Update,
+                Operation::Delete => Operation::Create,
+            },
+            task_id: self.task_id,
+            before: self.after.clone(),
+            after: self.before.clone(),
+        }
+    }
+
+    /// The version of the task the entry is named after: the one with a
+    /// number if either side of the change has one, else the latest one.
+    pub fn task(&self) -> Option<&Task> {
+        let numbered = [&self.before, &self.after]
+            .into_iter()
+            .flatten()
+            .find(|task| task.index.is_some());
+        numbered.or(self.after.as_ref()).or(self.before.as_ref())
+    }
+
+    /// Describes what the entry changed, e.g. `Changed task 3 "Buy milk":
+    /// status Undone → Done`. Tasks are named after [`HistoryEntry::task`],
+    /// see [`Task::label`], and the tasks and projects they refer to by
+    /// `names`.
+    pub fn describe(&self, names: &HistoryNames) -> String {
+        let text = match self.task() {
+            Some(task) => format!("task {} \"{}\"", task.label(), task.text),
+            None => format!("task {}", names.task(self.task_id)),
+        };
+        match (self.operation, &self.before, &self.after) {
+            (Operation::Create, _, _) => format!("Added {}", text),
+            (Operation::Delete, _, _) => format!("Removed {}", text),
+            (Operation::Update, Some(before), Some(after)) => {
+                let changes = field_changes(before, after, names);
+                match changes.is_empty() {
+                    true => format!("Changed {}", text),
+                    false => format!("Changed {}: {}", text, changes.join(", ")),
+                }
+            }
+            (Operation::Update, _, _) => format!("Changed {}", text),
+        }
+    }
+
+    /// The task as the operation left it, needed to apply it. Undoing applies
+    /// the [reverted](HistoryEntry::reverted) entry.
+    pub(crate) fn snapshot_after(&self) -> Result<&Task> {
+        self.after.as_ref().ok_or_else(|| self.missing_snapshot())
+    }
+
+    fn missing_snapshot(&self) -> Error {
+        Error::CorruptedHistory(format!(
+            "{} of task {} in entry {} has no snapshot",
+            self.operation, self.task_id, self.id
+        ))
+    }
+}
+
+/// The fields that differ between two versions of a task, e.g.
+/// `status Undone → Done`.
+fn field_changes(before: &Task, after: &Task, names: &HistoryNames) -> Vec<String> {
+    fn or_none(value: Option<String>) -> String {
+        value.unwrap_or_else(|| String::from("none"))
+    }
+    let tasks = |ids: &[i32]| {
+        let labels: Vec<String> = ids.iter().map(|&id| names.task(id)).collect();
+        Some(labels.join(", ")).filter(|labels| !labels.is_empty())
+    };
+
+    let fields = [
+        (
+            "text",
+            format!("\"{}\"", before.text),
+            format!("\"{}\"", after.text),
+        ),
+        (
+            "status",
+            before.status.to_string(),
+            after.status.to_string(),
+        ),
+        (
+            "tags",
+            or_none(Some(before.tags.join(", ")).filter(|tags| !tags.is_empty())),
+            or_none(Some(after.tags.join(", ")).filter(|tags| !tags.is_empty())),
+        ),
+        (
+            "due",
+            or_none(
+                before
+                    .due_date
+                    .map(|due| due.format("%Y-%m-%d %H:%M").to_string()),
+            ),
+            or_none(
+                after
+                    .due_date
+                    .map(|due| due.format("%Y-%m-%d %H:%M").to_string()),
+            ),
+        ),
+        (
+            "priority",
+            or_none(before.priority.map(|priority| priority.to_string())),
+            or_none(after.priority.map(|priority| priority.to_string())),
+        ),
+        (
+            "recurrence",
+            or_none(
+                before
+                    .recurrence
+                    .as_ref()
+                    .map(|recurrence| recurrence.to_string()),
+            ),
+            or_none(
+                after
+                    .recurrence
+                    .as_ref()
+                    .map(|recurrence| recurrence.to_string()),
+            ),
+        ),
+        (
+            "parent",
+            or_none(before.parent_id.map(|id| names.task(id))),
+            or_none(after.parent_id.map(|id| names.task(id))),
+        ),
+        (
+            "dependencies",
+            or_none(tasks(&before.depends_on)),
+            or_none(tasks(&after.depends_on)),
+        ),
+        (
+            "project",
+            or_none(before.project_id.map(|id| names.project(id))),
+            or_none(after.project_id.map(|id| names.project(id))),
+        ),
+    ];
+    fields
+        .into_iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(name, before, after)| format!("{} {} → {}", name, before, after))
+        .collect()
+}
+
+/// Formats the time a history entry is recorded. Times are kept in UTC with a
+/// fixed width, so that they compare as text.
+pub(crate) fn to_timestamp(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S%.6f +00:00").to_string()
+}
+
+/// The timestamp of a local time, see [`to_timestamp`].
+pub(crate) fn local_timestamp(time: NaiveDateTime) -> String {
+    to_timestamp(local_to_utc(time))
+}
+
+/// Rewrites the time a history entry was recorded as a timestamp, see
+/// [`to_timestamp`].
+pub(crate) fn normalize_timestamp(value: &str) -> Option<String> {
+    parse_utc_timestamp(value).map(to_timestamp)
+}
+
+/// Parses the time a history entry was recorded as local time.
+pub(crate) fn parse_timestamp(value: &str) -> Option<NaiveDateTime> {
+    parse_utc_timestamp(value).map(|time| time.with_timezone(&Local).naive_local())
+}
+
+/// Parses a timestamp, written in UTC by current versions, with the local
+/// offset by older ones and without one by the oldest.
+fn parse_utc_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f %:z")
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| parse_legacy_datetime(value).map(local_to_utc))
+}
+
+fn local_to_utc(time: NaiveDateTime) -> DateTime<Utc> {
+    match time.and_local_timezone(Local).earliest() {
+        Some(time) => time.with_timezone(&Utc),
+        // The time doesn't exist locally (skipped by a DST change)
+        None => time.and_utc(),
+    }
+}
+
+/// Converts a row of the old string-based history tables into an operation
+/// with its before/after snapshots.
+///
+/// Old rows stored the SQL that reverts the change in `command` together with
+/// the task as the change left it (or as it was before it was deleted). Only
+/// `UPDATE` commands carry information that is not in the stored task, so they
+/// are parsed to recover the previous state. Returns `None` for commands that
+/// cannot be understood.
+pub fn from_legacy_command(
+    command: &str,
+    task: Task,
+) -> Option<(Operation, Option<Task>, Option<Task>)> {
+    if command.starts_with("INSERT") {
+        // Reverting a delete re-inserts the task
+        Some((Operation::Delete, Some(task), None))
+    } else if command.starts_with("DELETE") {
+        // Reverting a create deletes the task
+        Some((Operation::Create, None, Some(task)))
+    } else if command.starts_with("UPDATE") {
+        let previous_task = Task {
+            uuid: task.uuid.clone(),
+            ..parse_legacy_update(command)?
+        };
+        Some((Operation::Update, Some(previous_task), Some(task)))
+    } else {
+        None
+    }
+}
+
+/// Parses an old `UPDATE Tasks SET ... WHERE id = N` undo command.
+///
+/// The values were interpolated without escaping, so the command is split
+/// from the right: every field after `text` has a well known shape, which
+/// keeps texts containing quotes recoverable.
+fn parse_legacy_update(command: &str) -> Option<Task> {
+    let rest = command.strip_prefix("UPDATE Tasks SET text = '")?;
+    let (rest, id) = rest.rsplit_once(" WHERE id = ")?;
+    let (rest, created_at) = rest.strip_suffix('\'')?.rsplit_once(", created_at = '")?;
+    let (rest, due_date) = rest.rsplit_once(", due_date = ")?;
+    let (rest, tag) = rest.rsplit_once(", tag = ")?;
+    let (text, status) = rest.strip_suffix('\'')?.rsplit_once("', status = '")?;
+
+    Some(Task::new_with_created_at(
+        id.trim().parse().ok()?,
+        text,
+        TaskStatus::from_str(status).ok()?,
+        parse_legacy_optional(tag)?.into_iter().collect(),
+        parse_legacy_optional(due_date)?.and_then(|due_date| parse_legacy_datetime(&due_date)),
+        parse_legacy_datetime(created_at)?,
+    ))
+}
+
+fn parse_legacy_datetime(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").ok()
+}
+
+fn parse_legacy_optional(value: &str) -> Option<Option<String>> {
+    if value == "NULL" {
+        Some(None)
+    } else {
+        let value = value.strip_prefix('\'')?.strip_suffix('\'')?;
+        Some(Some(value.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        from_legacy_command, normalize_timestamp, parse_timestamp, HistoryEntry, HistoryNames,
+        Operation,
+    };
+    use crate::task::{Task, TaskStatus};
+    use chrono::{Local, NaiveDateTime};
+    use std::collections::HashMap;
+
+    fn created_at() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2023-08-01 10:20:30.123456", "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    #[test]
+    fn legacy_update_should_be_parsed() {
+        let command = format!(
+            "UPDATE Tasks SET text = 'Go to the gym', status = 'Undone', tag = 'health', due_date = NULL, created_at = '{}' WHERE id = 2",
+            created_at()
+        );
+        let current = Task::new(2, "Go to the gym today", TaskStatus::Done, vec![], None);
+
+        let (operation, before, after) = from_legacy_command(&command, current.clone()).unwrap();
+
+        assert_eq!(Operation::Update, operation);
+        assert_eq!(
+            Some(Task {
+                uuid: current.uuid.clone(),
+                ..Task::new_with_created_at(
+                    2,
+                    "Go to the gym",
+                    TaskStatus::Undone,
+                    vec![String::from("health")],
+                    None,
+                    created_at()
+                )
+            }),
+            before
+        );
+        assert_eq!(Some(current), after);
+    }
+
+    #[test]
+    fn legacy_update_with_quotes_should_be_parsed() {
+        let command = format!(
+            "UPDATE Tasks SET text = 'Call Bob's dentist', status = 'Done', tag = NULL, due_date = NULL, created_at = '{}' WHERE id = 7",
+            created_at()
+        );
+        let current = Task::new(7, "Call Bob", TaskStatus::Done, vec![], None);
+
+        let (_, before, _) = from_legacy_command(&command, current).unwrap();
+
+        assert_eq!("Call Bob's dentist", before.unwrap().text);
+    }
+
+    #[test]
+    fn legacy_insert_and_delete_should_be_converted() {
+        let task = Task::new(3, "Meditate", TaskStatus::Undone, vec![], None);
+
+        let (operation, before, after) =
+            from_legacy_command("INSERT INTO Tasks (id) VALUES (3)", task.clone()).unwrap();
+        assert_eq!(
+            (Operation::Delete, Some(task.clone()), None),
+            (operation, before, after)
+        );
+
+        let (operation, before, after) = from_legacy_command(
+            "DELETE FROM Tasks WHERE id = (SELECT MAX(id) FROM Tasks)",
+            task.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            (Operation::Create, None, Some(task)),
+            (operation, before, after)
+        );
+    }
+
+    #[test]
+    fn entries_should_describe_their_changes() {
+        let before = Task {
+            uuid: String::from("0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a"),
+            index: Some(3),
+            ..Task::new_with_created_at(
+                12,
+                "Buy milk",
+                TaskStatus::Undone,
+                vec![],
+                None,
+                created_at(),
+            )
+        };
+        let after = Task {
+            status: TaskStatus::Done,
+            tags: vec![String::from("home")],
+            index: None,
+            ..before.clone()
+        };
+        let names = HistoryNames::default();
+        let entry = HistoryEntry {
+            id: 1,
+            operation: Operation::Update,
+            task_id: 12,
+            before: Some(before.clone()),
+            after: Some(after.clone()),
+        };
+
+        assert_eq!(
+            "Changed task 3 \"Buy milk\": status Undone → Done, tags none → home",
+            entry.describe(&names)
+        );
+        assert_eq!(
+            "Changed task 3 \"Buy milk\": status Done → Undone, tags home → none",
+            entry.reverted().describe(&names)
+        );
+
+        let entry = HistoryEntry {
+            id: 2,
+            operation: Operation::Create,
+            task_id: 12,
+            before: None,
+            after: Some(before),
+        };
+        assert_eq!("Added task 3 \"Buy milk\"", entry.describe(&names));
+        assert_eq!(
+            "Removed task 3 \"Buy milk\"",
+            entry.reverted().describe(&names)
+        );
+
+        let entry = HistoryEntry {
+            id: 3,
+            operation: Operation::Delete,
+            task_id: 12,
+            before: Some(after),
+            after: None,
+        };
+        assert_eq!("Removed task 0b5c7a2e \"Buy milk\"", entry.describe(&names));
+    }
+
+    #[test]
+    fn references_should_be_described_by_name() {
+        let before = Task {
+            index: Some(3),
+            parent_id: Some(57),
+            project_id: Some(2),
+            ..Task::new(12, "Buy milk", TaskStatus::Undone, vec![], None)
+        };
+        let after = Task {
+            parent_id: None,
+            depends_on: vec![57, 60],
+            project_id: Some(9),
+            ..before.clone()
+        };
+        let names = HistoryNames {
+            tasks: HashMap::from([(57, String::from("1")), (60, String::from("0b5c7a2e"))]),
+            projects: HashMap::from([(2, String::from("Home"))]),
+        };
+        let entry = HistoryEntry {
+            id: 1,
+            operation: Operation::Update,
+            task_id: 12,
+            before: Some(before),
+            after: Some(after),
+        };
+
+        assert_eq!(
+            "Changed task 3 \"Buy milk\": parent 1 → none, dependencies none → 1, 0b5c7a2e, project Home → removed",
+            entry.describe(&names)
+        );
+    }
+
+    #[test]
+    fn timestamps_should_be_parsed_with_and_without_offset() {
+        let utc = created_at().and_utc();
+        assert_eq!(
+            Some(utc.with_timezone(&Local).naive_local()),
+            parse_timestamp("2023-08-01 12:20:30.123456 +02:00")
+        );
+        assert_eq!(
+            Some(created_at()),
+            parse_timestamp("2023-08-01 10:20:30.123456")
+        );
+        assert_eq!(None, parse_timestamp("yesterday"));
+    }
+
+    #[test]
+    fn timestamps_should_be_normalized_to_utc() {
+        assert_eq!(
+            Some(String::from("2023-08-01 08:20:30.100000 +00:00")),
+            normalize_timestamp("2023-08-01 10:20:30.1 +02:00")
+        );
+        // Fixed width, so they sort as text
+        assert!(
+            normalize_timestamp("2023-08-01 09:00:00 +00:00")
+                < normalize_timestamp("2023-08-01 10:20:30.1 +00:00")
+        );
+    }
+
+    #[test]
+    fn unknown_legacy_command_should_be_rejected() {
+        let task = Task::default();
+
+        assert_eq!(None, from_legacy_command("DROP TABLE Tasks", task.clone()));
+        assert_eq!(None, from_legacy_command("UPDATE Tasks SET", task));
+    }
+}
@@ -0,0 +1,40 @@
+//! A simple todo list stored in SQLite, with tags, due dates and an undo/redo
+//! history. This crate is the library behind the `list-rs` cli and can be used
+//! to embed the same task lists in other tools.
+//!
+//! ```
+//! use list_rs::{Task, TaskStatus, TaskStore};
+//!
+//! # fn main() -> list_rs::Result<()> {
+//! let store = TaskStore::open_in_memory()?;
+//!
+//! let id = store.create_task(Task::new(1, "Watch Oppenheimer", TaskStatus::Undone, vec![], None))?;
+//! let mut task = store.read_task(id)?;
+//! task.status = TaskStatus::Done;
+//! store.update_task(id, &task)?;
+//!
+//! store.undo()?;
+//! assert_eq!(TaskStatus::Undone, store.read_task(id)?.status);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod due;
+mod error;
+mod fuzzy;
+mod history;
+mod migrations;
+mod project;
+pub mod query;
+mod recurrence;
+pub mod search;
+pub mod sort;
+pub mod task;
+mod task_store;
+
+pub use error::{Error, Result};
+pub use history::{HistoryBranch, HistoryEntry, HistoryNames, HistoryStep, Operation};
+pub use project::Project;
+pub use recurrence::Recurrence;
+pub use task::{Priority, Task, TaskStatus};
+pub use task_store::{TaskStore, DEFAULT_BUSY_TIMEOUT};
@@ -1,18 +1,46 @@
 use colored::Colorize;
 use dotenv::dotenv;
-use rusqlite::{types::FromSql, Result};
+use serde_json::json;
+use std::collections::HashSet;
 use std::env;
+use std::path::{Path, PathBuf};
 
 mod args;
-mod db_handler;
-mod task;
+mod config;
+mod output;
+mod selection;
+mod tui;
 
-use crate::args::{Cli, Commands};
-use crate::db_handler::DatabaseHandler;
-use crate::task::Task;
-use crate::task::TaskStatus;
+use crate::args::{
+    Cli, Commands, ConfigCommands, HistoryCommands, ProfileCommands, ProjectCommands,
+};
+use crate::config::{Config, Profile, DEFAULT_PROFILE, KEYS};
+use crate::output::{Format, TaskRecord};
+use chrono::NaiveDateTime;
+use list_rs::query::{Expr, Field, Op, Value};
+use list_rs::search::{quote_words, SearchMatch};
+use list_rs::sort::SortOrder;
+use list_rs::task::{parse_tag, split_text_and_tags, DisplayStyle};
+use list_rs::{
+    Error, HistoryBranch, HistoryEntry, HistoryNames, HistoryStep, Result, Task, TaskStatus,
+    TaskStore,
+};
+
+/// Prints `matching`, the tasks selected by a command. `tasks` holds every
+/// task so an empty list can be told apart from no matches.
+fn print_tasks(
+    tasks: &[Task],
+    matching: &[Task],
+    should_show_archived: bool,
+    format: Format,
+    style: &DisplayStyle,
+) -> Result<()> {
+    if format != Format::Text {
+        let matching: Vec<&Task> = matching.iter().collect();
+        output::write_tasks(std::io::stdout(), format, &matching, tasks)?;
+        return Ok(());
+    }
 
-fn print_tasks<F: Fn(&Task) -> bool>(tasks: &Vec<Task>, filter: F, should_show_archived: bool) {
     println!();
     let undone_tasks: Vec<_> = tasks
         .iter()
@@ -27,156 +55,1265 @@ fn print_tasks<F: Fn(&Task) -> bool>(tasks: &Vec<Task>, filter: F, should_show_a
     } else if undone_tasks.is_empty() && !should_show_archived {
         println!("Great, no pending tasks 🎉");
     } else {
-        for task in tasks.iter().filter(|&x| filter(x)) {
-            println!("{}", task);
+        print_tree(tasks, matching, style);
+    }
+    println!();
+
+    Ok(())
+}
+
+/// Prints tasks with their subtasks indented below them. Subtasks whose parent
+/// isn't printed are shown at the top level.
+fn print_tree(tasks: &[Task], matching: &[Task], style: &DisplayStyle) {
+    fn print_subtree(
+        tasks: &[Task],
+        matching: &[Task],
+        style: &DisplayStyle,
+        task: &Task,
+        depth: usize,
+    ) {
+        let children: Vec<&Task> = tasks
+            .iter()
+            .filter(|child| child.parent_id == Some(task.id))
+            .filter(|child| child.status != TaskStatus::Archived)
+            .collect();
+        let rollup = match children.len() {
+            0 => String::new(),
+            total => {
+                let done = children
+                    .iter()
+                    .filter(|child| child.status == TaskStatus::Done)
+                    .count();
+                format!(" {}", format!("[{}/{} done]", done, total).dimmed())
+            }
+        };
+        println!(
+            "{}{}{}{}",
+            "   ".repeat(depth),
+            task.display(style),
+            describe_dependencies(tasks, task),
+            rollup
+        );
+
+        for child in matching
+            .iter()
+            .filter(|child| child.parent_id == Some(task.id))
+        {
+            print_subtree(tasks, matching, style, child, depth + 1);
         }
     }
+
+    let ids: HashSet<i32> = matching.iter().map(|task| task.id).collect();
+    for task in matching.iter().filter(|task| {
+        task.parent_id
+            .is_none_or(|parent_id| !ids.contains(&parent_id))
+    }) {
+        print_subtree(tasks, matching, style, task, 0);
+    }
+}
+
+/// Names the tasks `task` depends on by their labels, e.g. ` after 2, 5`.
+fn describe_dependencies(tasks: &[Task], task: &Task) -> String {
+    if task.depends_on.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = tasks
+        .iter()
+        .filter(|other| task.depends_on.contains(&other.id))
+        .map(Task::label)
+        .collect();
+    format!(" {}", format!("after {}", labels.join(", ")).dimmed())
+}
+
+/// Prints search results with their highlighted snippets. Snippets of fuzzy
+/// matches are the whole text, so they are highlighted within the task.
+fn print_matches(
+    tasks: &[Task],
+    matches: &[SearchMatch],
+    fuzzy: bool,
+    format: Format,
+    style: &DisplayStyle,
+) -> Result<()> {
+    if format != Format::Text {
+        output::write_matches(std::io::stdout(), format, matches, tasks)?;
+        return Ok(());
+    }
+    if tasks.is_empty() || matches.is_empty() {
+        return print_tasks(tasks, &[], true, format, style);
+    }
+
+    println!();
+    for found in matches {
+        let dependencies = describe_dependencies(tasks, &found.task);
+        if fuzzy {
+            println!(
+                "{}{}",
+                found.task.highlighted(style, &found.highlights),
+                dependencies
+            );
+            continue;
+        }
+        println!("{}{}", found.task.display(style), dependencies);
+        let snippet: String = found
+            .parts()
+            .into_iter()
+            .map(|(part, is_match)| match is_match {
+                true => part.yellow().bold().to_string(),
+                false => part.dimmed().to_string(),
+            })
+            .collect();
+        println!("   {}", snippet);
+    }
     println!();
+
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    dotenv().ok();
-    let database_path = match env::var("DB_PATH") {
-        Ok(value) => value,
-        Err(_) => String::from("tasks.db"),
+/// Prints the steps of the history, each with the time it was made and a line
+/// per change.
+fn print_history(steps: &[HistoryStep], names: &HistoryNames, format: Format) -> Result<()> {
+    if format != Format::Text {
+        output::write_history(std::io::stdout(), format, steps, names)?;
+        return Ok(());
+    }
+
+    println!();
+    if steps.is_empty() {
+        println!("No changes yet");
+    }
+    for step in steps {
+        print_step(step, names, "");
+    }
+    println!();
+
+    Ok(())
+}
+
+/// Prints the history like `print_history`, with every branch below the step
+/// it continues from.
+fn print_history_tree(
+    steps: &[HistoryStep],
+    branches: &[HistoryBranch],
+    names: &HistoryNames,
+    format: Format,
+) -> Result<()> {
+    if format != Format::Text {
+        output::write_history_tree(std::io::stdout(), format, steps, branches, names)?;
+        return Ok(());
+    }
+
+    println!();
+    if steps.is_empty() && branches.is_empty() {
+        println!("No changes yet");
+    }
+    print_branches(None, branches, names, "");
+    for step in steps {
+        print_step(step, names, "");
+        print_branches(Some(step.id), branches, names, "");
+    }
+    println!();
+
+    Ok(())
+}
+
+/// Prints the branches continuing from the step `fork`, and the branches of
+/// their steps, indented below it.
+fn print_branches(
+    fork: Option<i64>,
+    branches: &[HistoryBranch],
+    names: &HistoryNames,
+    indent: &str,
+) {
+    let indent = format!("{}      ", indent);
+    for branch in branches.iter().filter(|branch| branch.fork == fork) {
+        let created_at = branch
+            .created_at
+            .map(|created_at| format!(", left {}", created_at.format("%Y-%m-%d %H:%M")))
+            .unwrap_or_default();
+        println!(
+            "{}{}",
+            indent,
+            format!(
+                "└ branch {}{}, switch with `redo --branch {}`",
+                branch.id, created_at, branch.id
+            )
+            .bold()
+        );
+        for step in &branch.steps {
+            print_step(step, names, &indent);
+            print_branches(Some(step.id), branches, names, &indent);
+        }
+    }
+}
+
+/// Prints a step of the history with the time it was made and a line per
+/// change.
+fn print_step(step: &HistoryStep, names: &HistoryNames, indent: &str) {
+    let created_at = step
+        .created_at
+        .map(|created_at| created_at.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+    for (i, entry) in step.entries.iter().enumerate() {
+        let heading = match i {
+            0 => format!("{:>4}  {:16}", step.id, created_at),
+            _ => " ".repeat(22),
+        };
+        let description = match step.undone {
+            true => format!("{} (undone)", entry.describe(names)).dimmed(),
+            false => entry.describe(names).normal(),
+        };
+        println!("{}{}  {}", indent, heading.bold(), description);
+    }
+}
+
+/// Forgets the steps of the history beyond the `keep` most recent ones or
+/// older than `max_age` days, and returns the number of steps removed.
+fn prune_history(store: &TaskStore, keep: Option<usize>, max_age: Option<u32>) -> Result<usize> {
+    let mut removed = 0;
+    if let Some(days) = max_age {
+        let cutoff = chrono::Local::now().naive_local() - chrono::Duration::days(days.into());
+        removed += store.forget_history_before(cutoff)?;
+    }
+    if let Some(keep) = keep {
+        removed += store.limit_history(keep)?;
+    }
+    Ok(removed)
+}
+
+/// Applies the history preferences when list-rs starts, and rebuilds the
+/// database once the forgotten steps leave more than half of it unused. Only
+/// locks the database when there is something to forget.
+fn compact_history(store: &TaskStore, config: &Config) -> Result<()> {
+    if prune_history(store, config.history_limit, config.history_max_age)? > 0
+        && store.unused_space()? * 2 > store.database_size()?
+    {
+        store.vacuum()?;
+    }
+    Ok(())
+}
+
+/// A number of bytes for humans, e.g. `12.5 KiB`.
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} bytes", bytes),
+        1024..=1_048_575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// Reports the changes made by `undo` or `redo`, a line per change.
+fn print_changes(
+    changes: &[HistoryEntry],
+    names: &HistoryNames,
+    action: &str,
+    nothing: &str,
+    format: Format,
+) {
+    let lines: Vec<String> = changes
+        .iter()
+        .map(|change| change.describe(names))
+        .collect();
+    let message = match lines.is_empty() {
+        true => nothing.to_string(),
+        false => lines.join("\n"),
     };
+    let changes: Vec<_> = changes
+        .iter()
+        .map(|change| output::change_record(change, names))
+        .collect();
+    output::print_result(
+        format,
+        &message,
+        json!({ "action": action, "changes": changes }),
+    );
+}
 
-    let db_handler = DatabaseHandler::new(&database_path);
+/// Returns the tasks matching `query`, in the order given with --sort.
+fn query_tasks(store: &TaskStore, query: &Expr, sort: &Option<SortOrder>) -> Result<Vec<Task>> {
+    let mut tasks = store.query(query)?;
+    sort.clone().unwrap_or_default().sort(&mut tasks);
+    Ok(tasks)
+}
 
-    let mut tasks = db_handler.read_tasks();
-    tasks.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+/// Parses a query given as several command line arguments.
+fn parse_query(words: &[String], now: NaiveDateTime) -> Result<Option<Expr>> {
+    if words.is_empty() {
+        return Ok(None);
+    }
+    Expr::parse(&words.join(" "), now).map(Some)
+}
 
+/// Limits a listing to the active project, unless the query picks projects
+/// itself.
+fn in_active_project(store: &TaskStore, query: Expr) -> Result<Expr> {
+    if query.mentions(Field::Project) {
+        return Ok(query);
+    }
+    match store.active_project()? {
+        Some(project) => {
+            Ok(Expr::condition(Field::Project, Op::Is, Value::Text(project.name)).and(query))
+        }
+        None => Ok(query),
+    }
+}
+
+/// Validates a tag given on the command line.
+fn parse_valid_tag(tag: &str) -> Result<String> {
+    parse_tag(tag).ok_or_else(|| {
+        Error::InvalidInput(format!(
+            "Invalid tag '{}', tags can't contain commas or spaces",
+            tag
+        ))
+    })
+}
+
+/// Validates tags given on the command line.
+fn parse_tags(tags: &[String]) -> Result<Vec<String>> {
+    tags.iter().map(|tag| parse_valid_tag(tag)).collect()
+}
+
+/// Names the tasks a bulk command changed by their labels, e.g. "Task 3" or
+/// "Tasks 3, 5, 7".
+fn describe_tasks(labels: &[String]) -> String {
+    match labels.len() {
+        1 => format!("Task {}", labels[0]),
+        _ => format!("Tasks {}", labels.join(", ")),
+    }
+}
+
+/// The labels of the tasks with the given ids, read before a change takes
+/// their numbers away.
+fn labels(store: &TaskStore, ids: &[i32]) -> Result<Vec<String>> {
+    ids.iter()
+        .map(|&id| Ok(store.read_task(id)?.label()))
+        .collect()
+}
+
+/// The uuids of the given tasks, which machine readable results refer to
+/// them by since numbers change when tasks are done or removed.
+fn uuids<'a>(tasks: &'a [Task], ids: &[i32]) -> Vec<&'a str> {
+    ids.iter()
+        .filter_map(|&id| output::uuid_of(tasks, id))
+        .collect()
+}
+
+/// Adds the subtasks of the given tasks to `ids` when cascading, or makes
+/// sure they are selected already. Subtasks for which `is_done` holds don't
+/// need to be selected.
+fn with_subtasks<F: Fn(&Task) -> bool>(
+    store: &TaskStore,
+    mut ids: Vec<i32>,
+    cascade: bool,
+    is_done: F,
+    action: &str,
+) -> Result<Vec<i32>> {
+    let mut subtasks = Vec::new();
+    for &id in &ids {
+        for task in store.read_subtree(id)? {
+            if is_done(&task) || ids.contains(&task.id) {
+                continue;
+            }
+            if !cascade {
+                return Err(Error::InvalidInput(format!(
+                    "Task {} has subtasks, select them too or use --cascade to {} them",
+                    store.read_task(id)?.label(),
+                    action
+                )));
+            }
+            subtasks.push(task.id);
+        }
+    }
+
+    ids.extend(subtasks);
+    ids.sort();
+    ids.dedup();
+    Ok(ids)
+}
+
+/// Sets the status of the given tasks as a single undoable step and reports
+/// the outcome. Recurring tasks set to Done add their next occurrence.
+/// `tasks` holds every task before the change.
+fn set_status(
+    store: &TaskStore,
+    tasks: &[Task],
+    ids: Vec<i32>,
+    status: TaskStatus,
+    action: &str,
+    format: Format,
+) -> Result<()> {
+    if ids.is_empty() {
+        output::print_result(
+            format,
+            "No tasks matched",
+            json!({ "action": action, "uuids": [], "tasks": [] }),
+        );
+        return Ok(());
+    }
+
+    let labels = labels(store, &ids)?;
+    let uuids = uuids(tasks, &ids);
+    let mut warnings = Vec::new();
+    if status == TaskStatus::Done {
+        for (&id, label) in ids.iter().zip(&labels) {
+            let blockers: Vec<String> = store
+                .read_blockers(id)?
+                .iter()
+                .filter(|blocker| !ids.contains(&blocker.id))
+                .map(Task::label)
+                .collect();
+            if !blockers.is_empty() {
+                warnings.push(format!(
+                    "Task {} depends on {}, which isn't done yet",
+                    label,
+                    describe_tasks(&blockers).to_lowercase()
+                ));
+            }
+        }
+    }
+
+    let (updated, created) = store.batch(|store| {
+        let mut updated = Vec::new();
+        let mut created = Vec::new();
+        for &id in &ids {
+            if status == TaskStatus::Done {
+                if let Some(next_id) = store.complete_task(id)? {
+                    created.push(store.read_task(next_id)?);
+                }
+            } else {
+                let mut task = store.read_task(id)?;
+                task.status = status;
+                store.update_task(id, &task)?;
+            }
+            updated.push(TaskRecord::new(&store.read_task(id)?, tasks));
+        }
+        Ok((updated, created))
+    })?;
+
+    let mut message = format!("{} set to {}", describe_tasks(&labels), status);
+    let mut result = json!({ "action": action, "uuids": uuids, "tasks": updated });
+    if status == TaskStatus::Done {
+        let created_labels: Vec<String> = created.iter().map(Task::label).collect();
+        if !created_labels.is_empty() {
+            message += &format!(
+                "\nNext occurrence added as {}",
+                describe_tasks(&created_labels).to_lowercase()
+            );
+        }
+        let created: Vec<TaskRecord> = created
+            .iter()
+            .map(|task| TaskRecord::new(task, tasks))
+            .collect();
+        result["created"] = json!(created);
+        result["warnings"] = json!(warnings);
+        if format != Format::Json {
+            for warning in &warnings {
+                output::print_warning(warning);
+            }
+        }
+    }
+
+    output::print_result(format, &message, result);
+    Ok(())
+}
+
+/// The exit code for each class of error, so scripts can tell them apart.
+/// Usage errors reported by clap also exit with 2.
+fn exit_code(error: &Error) -> i32 {
+    match error {
+        Error::InvalidInput(_) | Error::InvalidQuery { .. } => 2,
+        Error::NotFound(_) => 3,
+        Error::Storage(_) => 4,
+        Error::CorruptedHistory(_) => 5,
+        Error::UnsupportedSchema { .. } => 6,
+        Error::Io(_) => 7,
+    }
+}
+
+fn main() {
+    dotenv().ok();
     let cli = Cli::parse_arguments();
 
+    if let Err(e) = run(&cli) {
+        output::print_error(cli.format, &e.to_string());
+        std::process::exit(exit_code(&e));
+    }
+}
+
+/// Picks the database: `--db`, the profile given with `--profile`, the one
+/// made active with `profile use`, `DB_PATH` or else the default profile.
+fn database_path(cli: &Cli, config: &Config, config_path: &Path) -> Result<PathBuf> {
+    if let Some(db) = &cli.db {
+        return Ok(db.clone());
+    }
+    if let Some(profile) = &cli.profile {
+        return config.profile_path(profile, config_path);
+    }
+    let db_path = env::var("DB_PATH").ok().map(PathBuf::from);
+    match (&config.profile, db_path) {
+        (Some(profile), db_path) => {
+            let path = config.profile_path(profile, config_path)?;
+            // DB_PATH may come from a forgotten .env file
+            if let Some(db_path) = db_path.filter(|db_path| *db_path != path) {
+                output::print_warning(&format!(
+                    "Using the active profile '{}', DB_PATH={} is ignored",
+                    profile,
+                    db_path.display()
+                ));
+            }
+            Ok(path)
+        }
+        (None, Some(db_path)) => Ok(db_path),
+        (None, None) => config.profile_path(DEFAULT_PROFILE, config_path),
+    }
+}
+
+/// The command run when none is given, picked with `default_command` in the
+/// config file.
+fn default_command(config: &Config) -> Option<Commands> {
+    Cli::parse_command(config.default_command.as_deref()?)
+}
+
+/// Loads the config for the commands that rewrite it, skipping bad entries
+/// with a warning rather than keeping them from repairing the file.
+fn load_leniently(config_path: &Path) -> Result<Config> {
+    let (config, problems) = Config::load_lenient(config_path)?;
+    for problem in problems {
+        output::print_warning(&format!(
+            "Skipping an entry of {}: {}",
+            config_path.display(),
+            problem
+        ));
+    }
+    Ok(config)
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    let config_path = cli.config.clone().unwrap_or_else(config::config_path);
     match &cli.command {
-        Some(Commands::Add { text }) => {
-            match db_handler.create_task(Task::new(1, text, TaskStatus::Undone, None, None)) {
-                Ok(_) => {
-                    println!("Task Added");
-                }
-                Err(e) => {
-                    println!("Error creating task {}", e)
+        Some(Commands::Profile { command }) => {
+            return run_profile(
+                load_leniently(&config_path)?,
+                &config_path,
+                command,
+                cli.format,
+            )
+        }
+        Some(Commands::Config { command }) => {
+            return run_config(
+                load_leniently(&config_path)?,
+                &config_path,
+                command,
+                cli.format,
+            )
+        }
+        _ => {}
+    }
+    let config = Config::load(&config_path)?;
+
+    if let Some(color) = config.color {
+        colored::control::set_override(color);
+    }
+    let style = config.display_style();
+    let sort = cli.sort.clone().or_else(|| config.sort_order());
+    let default_command = default_command(&config);
+    let command = cli.command.as_ref().or(default_command.as_ref());
+
+    let database_path = database_path(cli, &config, &config_path)?;
+    if let Some(dir) = database_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let store = TaskStore::open_with_timeout(&database_path, config.busy_timeout())?;
+    // `history prune` reports what it removes itself
+    if !matches!(
+        command,
+        Some(Commands::History {
+            command: Some(HistoryCommands::Prune { .. })
+        })
+    ) {
+        // The history is pruned again next time, the command shouldn't fail
+        // because another process holds the database
+        if let Err(e) = compact_history(&store, &config) {
+            output::print_warning(&format!("Couldn't prune the history: {}", e));
+        }
+    }
+
+    let mut tasks = store.read_tasks()?;
+    sort.clone().unwrap_or_default().sort(&mut tasks);
+
+    let format = cli.format;
+
+    match command {
+        Some(Commands::Add {
+            text,
+            due,
+            priority,
+            repeat,
+            parent,
+            project,
+        }) => {
+            let (text, mut tags) = split_text_and_tags(text);
+            if text.is_empty() {
+                return Err(Error::InvalidInput(String::from(
+                    "Task text can't be empty",
+                )));
+            }
+
+            for tag in &config.default_tags {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
                 }
             }
+
+            let project_id = match (project, store.active_project()?) {
+                (Some(project), _) => Some(store.read_project(project)?.id),
+                (None, Some(active)) => Some(active.id),
+                (None, None) => match &config.default_project {
+                    Some(project) => Some(
+                        store
+                            .read_project(project)
+                            .map_err(|e| {
+                                Error::InvalidInput(format!(
+                                    "{}, set as default_project in {}",
+                                    e,
+                                    config_path.display()
+                                ))
+                            })?
+                            .id,
+                    ),
+                    None => None,
+                },
+            };
+            let parent_id = match parent {
+                Some(parent) => Some(store.find_task(parent)?.id),
+                None => None,
+            };
+            let task = Task {
+                priority: *priority,
+                recurrence: repeat.clone(),
+                parent_id,
+                project_id,
+                ..Task::new(1, &text, TaskStatus::Undone, tags, *due)
+            };
+            let id = store.create_task(task)?;
+            let task = store.read_task(id)?;
+            output::print_result(
+                format,
+                "Task Added",
+                json!({ "action": "add", "id": task.index, "task": TaskRecord::new(&task, &tasks) }),
+            );
         }
-        Some(Commands::List {}) => {
-            print_tasks(&tasks, |task| task.status != TaskStatus::Archived, false);
+        Some(Commands::List {
+            query,
+            tags,
+            overdue,
+            due_before,
+            due_after,
+            project,
+        }) => {
+            let now = chrono::Local::now().naive_local();
+            let mut conditions: Vec<Expr> = parse_tags(tags)?
+                .into_iter()
+                .map(|tag| Expr::condition(Field::Tag, Op::Is, Value::Text(tag)))
+                .collect();
+            if *overdue {
+                conditions.push(
+                    Expr::condition(Field::Status, Op::Is, Value::Status(TaskStatus::Undone))
+                        .and(Expr::condition(Field::Due, Op::Less, Value::Date(now))),
+                );
+            }
+            if let Some(before) = due_before {
+                conditions.push(Expr::condition(
+                    Field::Due,
+                    Op::LessOrEqual,
+                    Value::Date(*before),
+                ));
+            }
+            if let Some(after) = due_after {
+                conditions.push(Expr::condition(
+                    Field::Due,
+                    Op::Greater,
+                    Value::Date(*after),
+                ));
+            }
+            if let Some(project) = project {
+                let project = store.read_project(project)?;
+                conditions.push(Expr::condition(
+                    Field::Project,
+                    Op::Is,
+                    Value::Text(project.name),
+                ));
+            }
+            conditions.extend(parse_query(query, now)?);
+
+            let not_archived =
+                Expr::condition(Field::Status, Op::Is, Value::Status(TaskStatus::Archived)).not();
+            let query = conditions.into_iter().reduce(Expr::and);
+            let show_archived = query
+                .as_ref()
+                .is_some_and(|query| query.mentions(Field::Status));
+            let query = match query {
+                Some(query) if show_archived => query,
+                Some(query) => not_archived.and(query),
+                None => not_archived,
+            };
+            let query = in_active_project(&store, query)?;
+
+            let matching = query_tasks(&store, &query, &sort)?;
+            print_tasks(&tasks, &matching, show_archived, format, &style)?;
         }
         Some(Commands::All {}) => {
-            print_tasks(&tasks, |_| true, true);
+            print_tasks(&tasks, &tasks, true, format, &style)?;
         }
         Some(Commands::Archived {}) => {
-            print_tasks(&tasks, |task| task.status == TaskStatus::Archived, true);
-        }
-        Some(Commands::Remove { id }) => match db_handler.delete_task(*id) {
-            Ok(_) => {
-                println!("Task {} removed", id);
-            }
-            Err(e) => {
-                println!("Error removing task {}", e)
-            }
-        },
-        Some(Commands::Search { content }) => {
-            print_tasks(
-                &tasks,
-                |task| task.text.to_lowercase().contains(&content.to_lowercase()),
-                true,
-            );
-        }
-        Some(Commands::Update { id, text }) => {
-            let task = db_handler.read_task(*id);
-
-            match task {
-                Some(mut task) => {
-                    task.text = (*text).clone();
-                    match db_handler.update_task(*id, &task) {
-                        Ok(_) => {
-                            println!("Task {} updated", id)
-                        }
-                        Err(e) => {
-                            println!("Error updating task {}", e)
-                        }
+            let query = Expr::condition(Field::Status, Op::Is, Value::Status(TaskStatus::Archived));
+            let matching = query_tasks(&store, &query, &sort)?;
+            print_tasks(&tasks, &matching, true, format, &style)?;
+        }
+        Some(Commands::Blocked {}) | Some(Commands::Ready {}) => {
+            let blocked = matches!(command, Some(Commands::Blocked {}));
+            let query =
+                Expr::condition(Field::Status, Op::Is, Value::Status(TaskStatus::Undone)).and(
+                    Expr::condition(Field::Blocked, Op::Is, Value::Bool(blocked)),
+                );
+            let query = in_active_project(&store, query)?;
+            let matching = query_tasks(&store, &query, &sort)?;
+            print_tasks(&tasks, &matching, false, format, &style)?;
+        }
+        Some(Commands::Remove { selection, cascade }) => {
+            let ids = selection.resolve(&store, chrono::Local::now().naive_local())?;
+            let ids = with_subtasks(&store, ids, *cascade, |_| false, "remove")?;
+            let labels = labels(&store, &ids)?;
+            let uuids = uuids(&tasks, &ids);
+            store.batch(|store| {
+                let mut removed = HashSet::new();
+                for &id in &ids {
+                    // Selected subtasks go with their parent
+                    if !removed.contains(&id) {
+                        removed.extend(store.delete_subtree(id)?);
                     }
                 }
-                None => {
-                    println!("Task with id {} does not exist", *id);
-                }
+                Ok(())
+            })?;
+
+            let message = if ids.is_empty() {
+                String::from("No tasks matched")
+            } else {
+                format!("{} removed", describe_tasks(&labels))
+            };
+            output::print_result(
+                format,
+                &message,
+                json!({ "action": "remove", "uuids": uuids }),
+            );
+        }
+        Some(Commands::Search {
+            terms,
+            raw,
+            query,
+            fuzzy,
+        }) => {
+            let now = chrono::Local::now().naive_local();
+            let query = query
+                .as_deref()
+                .map(|query| Expr::parse(query, now))
+                .transpose()?;
+            let terms = terms.join(" ");
+            let mut matches = match (fuzzy, raw) {
+                (true, _) => store.fuzzy_search(&terms, query.as_ref())?,
+                (false, true) => store.search(&terms, query.as_ref())?,
+                (false, false) => store.search(&quote_words(&terms), query.as_ref())?,
+            };
+            if let Some(sort) = &sort {
+                matches.sort_by(|a, b| sort.compare(&a.task, &b.task));
             }
+            print_matches(&tasks, &matches, *fuzzy, format, &style)?;
         }
-        Some(Commands::Done { id }) => {
-            let task = db_handler.read_task(*id);
+        Some(Commands::Update {
+            task: reference,
+            text,
+            due,
+            no_due,
+            priority,
+            no_priority,
+            repeat,
+            no_repeat,
+            parent,
+            no_parent,
+            project,
+            no_project,
+        }) => {
+            let mut task = store.find_task(reference)?;
+            let (id, label) = (task.id, task.label());
+            if text.is_none()
+                && due.is_none()
+                && !no_due
+                && priority.is_none()
+                && !no_priority
+                && repeat.is_none()
+                && !no_repeat
+                && parent.is_none()
+                && !no_parent
+                && project.is_none()
+                && !no_project
+            {
+                return Err(Error::InvalidInput(String::from(
+                    "Nothing to update, give a new text, --due, --no-due, --priority, --no-priority, --repeat, --no-repeat, --parent, --no-parent, --project or --no-project",
+                )));
+            }
 
-            match task {
-                Some(mut task) => {
-                    task.status = TaskStatus::Done;
-                    match db_handler.update_task(*id, &task) {
-                        Ok(_) => {
-                            println!("Task {} set to Done", id)
-                        }
-                        Err(e) => {
-                            println!("Error modifying task {}", e)
-                        }
-                    }
-                }
-                None => {
-                    println!("Task with id {} does not exist", *id);
-                }
+            if let Some(text) = text {
+                task.text = text.clone();
+            }
+            if let Some(due) = due {
+                task.due_date = Some(*due);
+            }
+            if *no_due {
+                task.due_date = None;
+            }
+            if let Some(priority) = priority {
+                task.priority = Some(*priority);
+            }
+            if *no_priority {
+                task.priority = None;
+            }
+            if let Some(repeat) = repeat {
+                task.recurrence = Some(repeat.clone());
+            }
+            if *no_repeat {
+                task.recurrence = None;
+            }
+            if let Some(parent) = parent {
+                task.parent_id = Some(store.find_task(parent)?.id);
             }
+            if *no_parent {
+                task.parent_id = None;
+            }
+            if let Some(project) = project {
+                task.project_id = Some(store.read_project(project)?.id);
+            }
+            if *no_project {
+                task.project_id = None;
+            }
+            store.update_task(id, &task)?;
+            let task = store.read_task(id)?;
+            output::print_result(
+                format,
+                &format!("Task {} updated", label),
+                json!({ "action": "update", "id": task.index, "task": TaskRecord::new(&task, &tasks) }),
+            );
         }
-        Some(Commands::Archive { id }) => {
-            let task = db_handler.read_task(*id);
-
-            match task {
-                Some(mut task) => {
-                    task.status = TaskStatus::Archived;
-                    match db_handler.update_task(*id, &task) {
-                        Ok(_) => {
-                            println!("Task {} set to Archived", id)
-                        }
-                        Err(e) => {
-                            println!("Error modifying task {}", e)
-                        }
+        Some(Commands::Done { selection }) => {
+            let ids = selection.resolve(&store, chrono::Local::now().naive_local())?;
+            set_status(&store, &tasks, ids, TaskStatus::Done, "done", format)?;
+        }
+        Some(Commands::Archive { selection, cascade }) => {
+            let ids = selection.resolve(&store, chrono::Local::now().naive_local())?;
+            let ids = with_subtasks(
+                &store,
+                ids,
+                *cascade,
+                |task| task.status == TaskStatus::Archived,
+                "archive",
+            )?;
+            set_status(&store, &tasks, ids, TaskStatus::Archived, "archive", format)?;
+        }
+        Some(Commands::Undone { selection }) => {
+            let ids = selection.resolve(&store, chrono::Local::now().naive_local())?;
+            set_status(&store, &tasks, ids, TaskStatus::Undone, "undone", format)?;
+        }
+        Some(Commands::Depends {
+            task: reference,
+            dependencies,
+            remove,
+            ..
+        }) => {
+            let task = store.find_task(reference)?;
+            let dependencies = dependencies
+                .iter()
+                .map(|dependency| store.find_task(dependency))
+                .collect::<Result<Vec<_>>>()?;
+            store.batch(|store| {
+                for dependency in &dependencies {
+                    match remove {
+                        true => store.remove_dependency(task.id, dependency.id)?,
+                        false => store.add_dependency(task.id, dependency.id)?,
                     }
                 }
-                None => {
-                    println!("Task with id {} does not exist", *id);
+                Ok(())
+            })?;
+
+            let (id, label) = (task.id, task.label());
+            let task = store.read_task(id)?;
+            let labels: Vec<String> = dependencies.iter().map(Task::label).collect();
+            let dependencies = describe_tasks(&labels).to_lowercase();
+            let message = match remove {
+                true => format!("Task {} no longer depends on {}", label, dependencies),
+                false => format!("Task {} depends on {}", label, dependencies),
+            };
+            output::print_result(
+                format,
+                &message,
+                json!({ "action": "depends", "id": task.index, "task": TaskRecord::new(&task, &tasks) }),
+            );
+        }
+        Some(Commands::Tag {
+            task: reference,
+            tags,
+        }) => {
+            let tags = parse_tags(tags)?;
+            let mut task = store.find_task(reference)?;
+            for tag in tags {
+                if !task.tags.contains(&tag) {
+                    task.tags.push(tag);
                 }
             }
+            store.update_task(task.id, &task)?;
+            output::print_result(
+                format,
+                &format!("Task {} tagged", task.label()),
+                json!({ "action": "tag", "id": task.index, "task": TaskRecord::new(&task, &tasks) }),
+            );
         }
-        Some(Commands::Undone { id }) => {
-            let task = db_handler.read_task(*id);
+        Some(Commands::Untag {
+            task: reference,
+            tags,
+        }) => {
+            let tags = parse_tags(tags)?;
+            let mut task = store.find_task(reference)?;
+            task.tags.retain(|tag| !tags.contains(tag));
+            store.update_task(task.id, &task)?;
+            output::print_result(
+                format,
+                &format!("Task {} untagged", task.label()),
+                json!({ "action": "untag", "id": task.index, "task": TaskRecord::new(&task, &tasks) }),
+            );
+        }
+        Some(Commands::Tags {}) => {
+            let tags = store.read_tags()?;
+            if format != Format::Text {
+                output::write_tags(std::io::stdout(), format, &tags)?;
+                return Ok(());
+            }
 
-            match task {
-                Some(mut task) => {
-                    task.status = TaskStatus::Undone;
-                    match db_handler.update_task(*id, &task) {
-                        Ok(_) => {
-                            println!("Task {} set to Undone", id)
-                        }
-                        Err(e) => {
-                            println!("Error modifying task {}", e)
-                        }
-                    }
-                }
-                None => {
-                    println!("Task with id {} does not exist", *id);
-                }
+            println!();
+            if tags.is_empty() {
+                println!(
+                    "No tags yet. Add one with {}",
+                    "list-rs tag".bold().color("Blue")
+                );
+            }
+            for (tag, count) in tags {
+                println!("{} ({})", format!("+{}", tag).color("Magenta"), count);
             }
+            println!();
         }
-        Some(Commands::Undo {}) => match db_handler.undo() {
-            Ok(_) => {}
-            Err(e) => {
-                println!("Error undoing task {}", e)
+        Some(Commands::RenameTag { old, new }) => {
+            let old = parse_valid_tag(old)?;
+            let new = parse_valid_tag(new)?;
+            let count = store.rename_tag(&old, &new)?;
+            if count == 0 {
+                return Err(Error::InvalidInput(format!(
+                    "No task is tagged with +{}",
+                    old
+                )));
             }
-        },
-        Some(Commands::Redo {}) => match db_handler.redo() {
-            Ok(_) => {}
-            Err(e) => {
-                println!("Error redoing task {}", e)
+
+            output::print_result(
+                format,
+                &format!("Tag +{} renamed to +{} on {} task(s)", old, new, count),
+                json!({ "action": "rename-tag", "old": old, "new": new, "count": count }),
+            );
+        }
+        Some(Commands::Project { command }) => run_project(&store, command, format)?,
+        // Handled before opening the database
+        Some(Commands::Profile { .. }) | Some(Commands::Config { .. }) => {}
+        Some(Commands::History { command: None }) => {
+            print_history(&store.read_history()?, &store.read_history_names()?, format)?
+        }
+        Some(Commands::History {
+            command: Some(HistoryCommands::Tree {}),
+        }) => print_history_tree(
+            &store.read_history()?,
+            &store.read_branches()?,
+            &store.read_history_names()?,
+            format,
+        )?,
+        Some(Commands::History {
+            command:
+                Some(HistoryCommands::Prune {
+                    keep,
+                    max_age,
+                    vacuum,
+                }),
+        }) => {
+            let steps = prune_history(
+                &store,
+                keep.or(config.history_limit),
+                max_age.or(config.history_max_age),
+            )?;
+            let mut message = format!("Removed {} step(s) from the history", steps);
+            let reclaimed = match vacuum {
+                true => Some(store.vacuum()?),
+                false => None,
+            };
+            if let Some(bytes) = reclaimed {
+                message.push_str(&format!(", reclaimed {}", format_size(bytes)));
             }
-        },
+            output::print_result(
+                format,
+                &message,
+                json!({ "action": "prune", "steps": steps, "reclaimed_bytes": reclaimed }),
+            );
+        }
+        Some(Commands::Undo { steps, to }) => {
+            let changes = match (steps, to) {
+                (_, Some(id)) => store.undo_to(*id)?,
+                (Some(steps), None) => store.undo_steps(*steps as usize)?,
+                (None, None) => store.undo()?,
+            };
+            let names = store.read_history_names()?;
+            print_changes(&changes, &names, "undo", "Nothing to undo", format);
+        }
+        Some(Commands::Redo { steps, branch }) => {
+            let changes = match (steps, branch) {
+                (_, Some(branch)) => store.redo_branch(*branch, steps.unwrap_or(1) as usize)?,
+                (Some(steps), None) => store.redo_steps(*steps as usize)?,
+                (None, None) => store.redo()?,
+            };
+            let names = store.read_history_names()?;
+            print_changes(&changes, &names, "redo", "Nothing to redo", format);
+        }
+        Some(Commands::Tui {}) => tui::run(&store, style)?,
         None => {
-            print_tasks(&tasks, |task| task.status != TaskStatus::Archived, false);
+            let not_archived =
+                Expr::condition(Field::Status, Op::Is, Value::Status(TaskStatus::Archived)).not();
+            let query = in_active_project(&store, not_archived)?;
+            let matching = query_tasks(&store, &query, &sort)?;
+            print_tasks(&tasks, &matching, false, format, &style)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the `project` subcommands.
+fn run_project(store: &TaskStore, command: &ProjectCommands, format: Format) -> Result<()> {
+    match command {
+        ProjectCommands::Add { name } => {
+            let id = store.create_project(name)?;
+            let project = store.read_project(name)?;
+            output::print_result(
+                format,
+                &format!("Project '{}' added", project.name),
+                json!({ "action": "project-add", "id": id, "name": project.name }),
+            );
+        }
+        ProjectCommands::List {} => {
+            let projects = store.read_projects()?;
+            let active = store.active_project()?.map(|project| project.id);
+            if format != Format::Text {
+                output::write_projects(std::io::stdout(), format, &projects, active)?;
+                return Ok(());
+            }
+
+            println!();
+            if projects.is_empty() {
+                println!(
+                    "No projects yet. Add one with {}",
+                    "list-rs project add".bold().color("Blue")
+                );
+            }
+            for project in &projects {
+                let name = match Some(project.id) == active {
+                    true => format!("{} (active)", project.name).bold(),
+                    false => project.name.normal(),
+                };
+                println!(
+                    "{}: {} undone, {} done, {} archived",
+                    name, project.undone, project.done, project.archived
+                );
+            }
+            println!();
+        }
+        ProjectCommands::Rename { old, new } => {
+            store.rename_project(old, new)?;
+            let project = store.read_project(new)?;
+            output::print_result(
+                format,
+                &format!("Project '{}' renamed to '{}'", old.trim(), project.name),
+                json!({ "action": "project-rename", "id": project.id, "name": project.name }),
+            );
+        }
+        ProjectCommands::Remove { name } => {
+            let project = store.read_project(name)?;
+            store.delete_project(name)?;
+            output::print_result(
+                format,
+                &format!("Project '{}' removed", project.name),
+                json!({ "action": "project-remove", "id": project.id, "name": project.name }),
+            );
+        }
+        ProjectCommands::Use { name } => {
+            store.set_active_project(name.as_deref())?;
+            let (message, project) = match store.active_project()? {
+                Some(project) => (
+                    format!("Now using project '{}'", project.name),
+                    json!({ "id": project.id, "name": project.name }),
+                ),
+                None => (String::from("Showing tasks of every project"), json!(null)),
+            };
+            output::print_result(
+                format,
+                &message,
+                json!({ "action": "project-use", "project": project }),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the `profile` subcommands, which only change the config file.
+fn run_profile(
+    mut config: Config,
+    config_path: &Path,
+    command: &ProfileCommands,
+    format: Format,
+) -> Result<()> {
+    match command {
+        ProfileCommands::List {} => {
+            let profiles = config.profiles(config_path);
+            let active = config.active_profile();
+            if format != Format::Text {
+                output::write_profiles(std::io::stdout(), format, &profiles, active)?;
+                return Ok(());
+            }
+
+            println!();
+            for (name, db) in &profiles {
+                let name = match name == active {
+                    true => format!("{} (active)", name).bold(),
+                    false => name.normal(),
+                };
+                println!("{}: {}", name, db.display());
+            }
+            println!();
+        }
+        ProfileCommands::Use { name } => {
+            let db = config.profile_path(name, config_path)?;
+            config.profile = match name.as_str() {
+                DEFAULT_PROFILE => None,
+                _ => Some(name.clone()),
+            };
+            config.save(config_path)?;
+            output::print_result(
+                format,
+                &format!("Now using profile '{}' at {}", name, db.display()),
+                json!({ "action": "profile-use", "name": name, "db": db }),
+            );
+        }
+        ProfileCommands::Add { name, db } => {
+            if name.trim().is_empty() {
+                return Err(Error::InvalidInput(String::from(
+                    "Profile names can't be empty",
+                )));
+            }
+            if config.profile_path(name, config_path).is_ok() {
+                return Err(Error::InvalidInput(format!(
+                    "Profile '{}' already exists",
+                    name
+                )));
+            }
+            let db = std::path::absolute(db)?;
+            config.profiles.insert(
+                name.clone(),
+                Profile {
+                    db: db.display().to_string(),
+                },
+            );
+            config.save(config_path)?;
+            output::print_result(
+                format,
+                &format!("Profile '{}' added", name),
+                json!({ "action": "profile-add", "name": name, "db": db }),
+            );
+        }
+        ProfileCommands::Remove { name } => {
+            if config.profiles.remove(name).is_none() {
+                return Err(Error::InvalidInput(format!(
+                    "No profile named '{}' in {}",
+                    name,
+                    config_path.display()
+                )));
+            }
+            if config.profile.as_ref() == Some(name) {
+                config.profile = None;
+            }
+            config.save(config_path)?;
+            output::print_result(
+                format,
+                &format!("Profile '{}' removed, its database was kept", name),
+                json!({ "action": "profile-remove", "name": name }),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the `config` subcommands, which only change the config file.
+fn run_config(
+    mut config: Config,
+    config_path: &Path,
+    command: &ConfigCommands,
+    format: Format,
+) -> Result<()> {
+    match command {
+        ConfigCommands::List {} => {
+            let preferences = KEYS
+                .iter()
+                .map(|&key| Ok((key, config.get(key)?)))
+                .collect::<Result<Vec<_>>>()?;
+            if format != Format::Text {
+                output::write_preferences(std::io::stdout(), format, &preferences)?;
+                return Ok(());
+            }
+
+            println!();
+            for (key, value) in &preferences {
+                match value {
+                    Some(value) => println!("{} = {}", key.bold(), value),
+                    None => println!("{} {}", key.bold(), "(default)".dimmed()),
+                }
+            }
+            println!();
+        }
+        ConfigCommands::Get { key } => {
+            let value = config.get(key)?;
+            if format != Format::Text {
+                output::write_preferences(std::io::stdout(), format, &[(key, value)])?;
+                return Ok(());
+            }
+            println!("{}", value.unwrap_or_default());
+        }
+        ConfigCommands::Set { key, value } => {
+            config.set(key, value)?;
+            config.save(config_path)?;
+            let value = config.get(key)?;
+            output::print_result(
+                format,
+                &format!("{} set to {}", key, value.clone().unwrap_or_default()),
+                json!({ "action": "config-set", "key": key, "value": value }),
+            );
+        }
+        ConfigCommands::Unset { key } => {
+            config.unset(key)?;
+            config.save(config_path)?;
+            output::print_result(
+                format,
+                &format!("{} reset to its default", key),
+                json!({ "action": "config-unset", "key": key }),
+            );
         }
     }
 
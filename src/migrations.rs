@@ -0,0 +1,673 @@
+use crate::error::{Error, Result};
+use crate::history;
+use crate::task::Task;
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+use uuid::Uuid;
+
+/// A single schema upgrade, taking the database from the version matching its
+/// position in [`MIGRATIONS`] to the next one.
+///
+/// Migrations must only use their own SQL and never the handler's queries, so
+/// that they keep working as the schema evolves after them.
+type Migration = fn(&Connection) -> rusqlite::Result<()>;
+
+/// Every schema upgrade in order. The schema version stored in
+/// `PRAGMA user_version` is the number of migrations applied so far; databases
+/// created by list-rs 0.1.1 have version 0.
+const MIGRATIONS: &[Migration] = &[
+    create_initial_tables,
+    create_typed_history,
+    create_tag_tables,
+    normalize_due_dates,
+    add_priorities,
+    create_search_index,
+    add_recurrence,
+    add_parents,
+    create_dependencies,
+    create_projects,
+    add_task_uuids,
+    create_history_branches,
+    share_snapshots,
+    store_history_times_in_utc,
+];
+
+/// The schema version this build of list-rs reads and writes.
+pub const SCHEMA_VERSION: i32 = MIGRATIONS.len() as i32;
+
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<i32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Upgrades the database step by step to [`SCHEMA_VERSION`]. Each step runs in
+/// its own transaction together with the version bump, so an interrupted
+/// upgrade resumes from the last completed step.
+pub fn migrate(conn: &Connection) -> Result<()> {
+    let version = schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchema {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+        // Another process may have upgraded the database while this one
+        // waited for the lock
+        if schema_version(&tx)? > index as i32 {
+            continue;
+        }
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", index as i32 + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Version 1: the schema of list-rs 0.1.1.
+fn create_initial_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS Tasks (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            text        TEXT NOT NULL,
+            status      TEXT NOT NULL,
+            tag         TEXT,
+            due_date    TEXT,
+            created_at  TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS UndoHistory (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            command     TEXT NOT NULL,
+            created_at  TEXT,
+            task_id          INTEGER,
+            task_text        TEXT NOT NULL,
+            task_status      TEXT NOT NULL,
+            task_tag         TEXT,
+            task_due_date    TEXT,
+            task_created_at  TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS RedoHistory (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            redo_command        TEXT NOT NULL,
+            undo_command        TEXT NOT NULL,
+            created_at  TEXT,
+            task_id          INTEGER,
+            task_text        TEXT NOT NULL,
+            task_status      TEXT NOT NULL,
+            task_tag         TEXT,
+            task_due_date    TEXT,
+            task_created_at  TEXT
+        );",
+    )
+}
+
+/// Version 2: replaces the stored SQL commands of the undo/redo history with a
+/// typed operation log referencing task snapshots. Rows whose command cannot be
+/// understood are dropped, they could never be replayed anyway.
+fn create_typed_history(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS TaskSnapshots (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            task_id     INTEGER NOT NULL,
+            text        TEXT NOT NULL,
+            status      TEXT NOT NULL,
+            tag         TEXT,
+            due_date    TEXT,
+            created_at  TEXT
+        );",
+    )?;
+
+    let is_legacy = conn
+        .prepare("SELECT 1 FROM pragma_table_info('UndoHistory') WHERE name = 'command'")?
+        .exists([])?;
+    if is_legacy {
+        conn.execute_batch(
+            "ALTER TABLE UndoHistory RENAME TO LegacyUndoHistory;
+            ALTER TABLE RedoHistory RENAME TO LegacyRedoHistory;",
+        )?;
+    }
+
+    for table in ["UndoHistory", "RedoHistory"] {
+        conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    id               INTEGER PRIMARY KEY AUTOINCREMENT,
+                    operation        TEXT NOT NULL,
+                    created_at       TEXT,
+                    task_id          INTEGER NOT NULL,
+                    before_snapshot  INTEGER REFERENCES TaskSnapshots(id),
+                    after_snapshot   INTEGER REFERENCES TaskSnapshots(id)
+                )",
+                table
+            ),
+            (), // empty list of parameters.
+        )?;
+    }
+
+    if !is_legacy {
+        return Ok(());
+    }
+
+    // In both old tables the command in `undo_command` reverts the change
+    // described by the stored task, so the same conversion applies.
+    for (legacy_table, command_column, table) in [
+        ("LegacyUndoHistory", "command", "UndoHistory"),
+        ("LegacyRedoHistory", "undo_command", "RedoHistory"),
+    ] {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}, created_at, task_id, task_text, task_status, task_tag, task_due_date, task_created_at FROM {} ORDER BY id",
+            command_column, legacy_table
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                Task::new_with_created_at(
+                    row.get(2)?,
+                    &row.get::<_, String>(3)?,
+                    row.get(4)?,
+                    row.get::<_, Option<String>>(5)?.into_iter().collect(),
+                    // Due dates were never set before they were typed
+                    None,
+                    row.get(7)?,
+                ),
+            ))
+        })?;
+
+        for row in rows {
+            let (command, created_at, task) = row?;
+            let task_id = task.id;
+            if let Some((operation, before, after)) = history::from_legacy_command(&command, task) {
+                let before_snapshot = match before {
+                    Some(task) => Some(insert_v2_snapshot(conn, &task)?),
+                    None => None,
+                };
+                let after_snapshot = match after {
+                    Some(task) => Some(insert_v2_snapshot(conn, &task)?),
+                    None => None,
+                };
+
+                conn.execute(
+                    &format!(
+                        "INSERT INTO {} (operation, created_at, task_id, before_snapshot, after_snapshot) VALUES (?1, ?2, ?3, ?4, ?5)",
+                        table
+                    ),
+                    params![
+                        operation.to_string(),
+                        created_at,
+                        task_id,
+                        before_snapshot,
+                        after_snapshot
+                    ],
+                )?;
+            }
+        }
+    }
+
+    conn.execute_batch(
+        "DROP TABLE LegacyUndoHistory;
+        DROP TABLE LegacyRedoHistory;",
+    )
+}
+
+fn insert_v2_snapshot(conn: &Connection, task: &Task) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO TaskSnapshots (task_id, text, status, tag, due_date, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            task.id,
+            task.text,
+            task.status.to_string(),
+            task.tags.first(),
+            task.due_date,
+            task.created_at
+        ],
+    )?;
+
+    Ok(conn.last_insert_rowid())
+}
+
+/// Version 3: moves tags from the single `tag` column to a join table so a task
+/// can have several of them, and groups history entries into batches that are
+/// undone and redone as one step.
+fn create_tag_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE Tags (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            name        TEXT NOT NULL UNIQUE
+        );
+
+        CREATE TABLE TaskTags (
+            task_id     INTEGER NOT NULL,
+            tag_id      INTEGER NOT NULL,
+            PRIMARY KEY (task_id, tag_id)
+        );
+
+        INSERT OR IGNORE INTO Tags (name)
+            SELECT tag FROM Tasks WHERE tag IS NOT NULL AND tag <> '' ORDER BY id;
+        INSERT INTO TaskTags (task_id, tag_id)
+            SELECT Tasks.id, Tags.id FROM Tasks JOIN Tags ON Tags.name = Tasks.tag;
+        ALTER TABLE Tasks DROP COLUMN tag;
+
+        ALTER TABLE TaskSnapshots RENAME COLUMN tag TO tags;
+
+        ALTER TABLE UndoHistory ADD COLUMN batch INTEGER;
+        ALTER TABLE RedoHistory ADD COLUMN batch INTEGER;
+        UPDATE UndoHistory SET batch = id;
+        UPDATE RedoHistory SET batch = id + (SELECT COALESCE(MAX(id), 0) FROM UndoHistory);",
+    )
+}
+
+/// Version 4: due dates are read as date-times, so any other text stored in
+/// them is converted or dropped. Bare dates become due at the end of the day.
+fn normalize_due_dates(conn: &Connection) -> rusqlite::Result<()> {
+    for table in ["Tasks", "TaskSnapshots"] {
+        conn.execute(
+            &format!(
+                "UPDATE {} SET due_date = CASE
+                    WHEN date(due_date) = due_date THEN due_date || ' 23:59:59'
+                    ELSE datetime(due_date)
+                END
+                WHERE due_date IS NOT NULL",
+                table
+            ),
+            (), // empty list of parameters.
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Version 5: tasks and their snapshots get an optional priority.
+fn add_priorities(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE Tasks ADD COLUMN priority TEXT;
+        ALTER TABLE TaskSnapshots ADD COLUMN priority TEXT;",
+    )
+}
+
+/// Version 6: a full-text index of the task texts, kept up to date by
+/// triggers and filled with the existing tasks.
+fn create_search_index(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE TaskSearch USING fts5(
+            text,
+            content = 'Tasks',
+            content_rowid = 'id',
+            prefix = '2 3'
+        );
+
+        CREATE TRIGGER TaskSearchInsert AFTER INSERT ON Tasks BEGIN
+            INSERT INTO TaskSearch (rowid, text) VALUES (new.id, new.text);
+        END;
+
+        CREATE TRIGGER TaskSearchDelete AFTER DELETE ON Tasks BEGIN
+            INSERT INTO TaskSearch (TaskSearch, rowid, text) VALUES ('delete', old.id, old.text);
+        END;
+
+        CREATE TRIGGER TaskSearchUpdate AFTER UPDATE OF id, text ON Tasks BEGIN
+            INSERT INTO TaskSearch (TaskSearch, rowid, text) VALUES ('delete', old.id, old.text);
+            INSERT INTO TaskSearch (rowid, text) VALUES (new.id, new.text);
+        END;
+
+        INSERT INTO TaskSearch (TaskSearch) VALUES ('rebuild');",
+    )
+}
+
+/// Version 7: tasks and their snapshots get an optional recurrence rule.
+fn add_recurrence(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE Tasks ADD COLUMN recurrence TEXT;
+        ALTER TABLE TaskSnapshots ADD COLUMN recurrence TEXT;",
+    )
+}
+
+/// Version 8: tasks and their snapshots get an optional parent task.
+fn add_parents(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE Tasks ADD COLUMN parent_id INTEGER;
+        ALTER TABLE TaskSnapshots ADD COLUMN parent_id INTEGER;
+        CREATE INDEX TasksByParent ON Tasks (parent_id);",
+    )
+}
+
+/// Version 9: tasks can depend on other tasks. Snapshots keep the ids of the
+/// dependencies comma separated, like tags.
+fn create_dependencies(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE TaskDependencies (
+            task_id     INTEGER NOT NULL,
+            depends_on  INTEGER NOT NULL,
+            PRIMARY KEY (task_id, depends_on)
+        );
+        CREATE INDEX TaskDependenciesByDependency ON TaskDependencies (depends_on);
+
+        ALTER TABLE TaskSnapshots ADD COLUMN depends_on TEXT;",
+    )
+}
+
+/// Version 10: tasks can belong to a named project, and settings such as the
+/// active project are kept in the database.
+fn create_projects(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE Projects (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            name        TEXT NOT NULL UNIQUE
+        );
+
+        CREATE TABLE Settings (
+            name        TEXT PRIMARY KEY,
+            value       TEXT NOT NULL
+        );
+
+        ALTER TABLE Tasks ADD COLUMN project_id INTEGER;
+        ALTER TABLE TaskSnapshots ADD COLUMN project_id INTEGER;
+        CREATE INDEX TasksByProject ON Tasks (project_id);",
+    )
+}
+
+/// Version 11: tasks get a permanent uuid, shared by their snapshots, and
+/// pending tasks a short display number, given in order of their ids.
+fn add_task_uuids(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE Tasks ADD COLUMN uuid TEXT;
+        ALTER TABLE Tasks ADD COLUMN display_index INTEGER;
+        ALTER TABLE TaskSnapshots ADD COLUMN uuid TEXT;
+        ALTER TABLE TaskSnapshots ADD COLUMN display_index INTEGER;",
+    )?;
+
+    let task_ids: Vec<i32> = conn
+        .prepare("SELECT id FROM Tasks UNION SELECT task_id FROM TaskSnapshots")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for task_id in task_ids {
+        let uuid = Uuid::new_v4().to_string();
+        conn.execute(
+            "UPDATE Tasks SET uuid = ?1 WHERE id = ?2",
+            params![uuid, task_id],
+        )?;
+        conn.execute(
+            "UPDATE TaskSnapshots SET uuid = ?1 WHERE task_id = ?2",
+            params![uuid, task_id],
+        )?;
+    }
+
+    conn.execute_batch(
+        "UPDATE Tasks SET display_index = (
+            SELECT COUNT(*) FROM Tasks AS Earlier WHERE Earlier.status = 'Undone' AND Earlier.id <= Tasks.id
+        ) WHERE status = 'Undone';
+        CREATE UNIQUE INDEX TasksByUuid ON Tasks (uuid);
+        CREATE UNIQUE INDEX TasksByDisplayIndex ON Tasks (display_index) WHERE display_index IS NOT NULL;",
+    )
+}
+
+/// Version 12: undone steps that a new change left behind are kept as
+/// branches of the history instead of staying in the redo history.
+fn create_history_branches(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE Branches (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            fork        INTEGER,
+            created_at  TEXT
+        );
+
+        CREATE TABLE BranchHistory (
+            id               INTEGER PRIMARY KEY AUTOINCREMENT,
+            branch           INTEGER NOT NULL REFERENCES Branches(id),
+            batch            INTEGER NOT NULL,
+            operation        TEXT NOT NULL,
+            created_at       TEXT,
+            task_id          INTEGER NOT NULL,
+            before_snapshot  INTEGER REFERENCES TaskSnapshots(id),
+            after_snapshot   INTEGER REFERENCES TaskSnapshots(id)
+        );",
+    )
+}
+
+/// Version 13: identical snapshots of a task are stored once and shared by
+/// the history entries using them, which look them up by task.
+fn share_snapshots(conn: &Connection) -> rusqlite::Result<()> {
+    let same: Vec<String> = [
+        "task_id",
+        "text",
+        "status",
+        "tags",
+        "due_date",
+        "priority",
+        "created_at",
+        "recurrence",
+        "parent_id",
+        "depends_on",
+        "project_id",
+        "uuid",
+        "display_index",
+    ]
+    .iter()
+    .map(|column| format!("Same.{} IS TaskSnapshots.{}", column, column))
+    .collect();
+    conn.execute_batch(&format!(
+        "CREATE INDEX TaskSnapshotsByTask ON TaskSnapshots (task_id);
+
+        CREATE TEMP TABLE SnapshotCopies AS
+            SELECT * FROM (
+                SELECT id, (SELECT MIN(Same.id) FROM TaskSnapshots AS Same WHERE {}) AS original
+                FROM TaskSnapshots
+            ) WHERE original < id;",
+        same.join(" AND ")
+    ))?;
+
+    for table in ["UndoHistory", "RedoHistory", "BranchHistory"] {
+        for column in ["before_snapshot", "after_snapshot"] {
+            conn.execute(
+                &format!(
+                    "UPDATE {table} SET {column} = (SELECT original FROM SnapshotCopies WHERE id = {table}.{column}) WHERE {column} IN (SELECT id FROM SnapshotCopies)",
+                    table = table,
+                    column = column
+                ),
+                (),
+            )?;
+        }
+    }
+
+    conn.execute_batch(
+        "DELETE FROM TaskSnapshots WHERE id IN (SELECT id FROM SnapshotCopies);
+        DROP TABLE SnapshotCopies;",
+    )
+}
+
+/// Version 14: history times are stored in UTC with a fixed width, so that
+/// they compare as text and retention by age finds old steps by index.
+fn store_history_times_in_utc(conn: &Connection) -> rusqlite::Result<()> {
+    for table in ["UndoHistory", "RedoHistory", "BranchHistory", "Branches"] {
+        let times: Vec<(i64, String)> = conn
+            .prepare(&format!(
+                "SELECT id, created_at FROM {} WHERE created_at IS NOT NULL",
+                table
+            ))?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        for (id, time) in times {
+            if let Some(time) = history::normalize_timestamp(&time) {
+                conn.execute(
+                    &format!("UPDATE {} SET created_at = ?1 WHERE id = ?2", table),
+                    params![time, id],
+                )?;
+            }
+        }
+    }
+
+    conn.execute_batch(
+        "CREATE INDEX UndoHistoryByTime ON UndoHistory (created_at);
+        CREATE INDEX BranchesByTime ON Branches (created_at);",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{migrate, schema_version, SCHEMA_VERSION};
+    use crate::error::Error;
+    use rusqlite::Connection;
+
+    /// A database as left behind by list-rs 0.1.1: one task created and then
+    /// marked as done, and a second task that was created, removed and then
+    /// restored by an undo.
+    fn setup_v0_1_1_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Tasks (id INTEGER PRIMARY KEY AUTOINCREMENT, text TEXT NOT NULL, status TEXT NOT NULL, tag TEXT, due_date TEXT, created_at TEXT);
+            CREATE TABLE UndoHistory (id INTEGER PRIMARY KEY AUTOINCREMENT, command TEXT NOT NULL, created_at TEXT, task_id INTEGER, task_text TEXT NOT NULL, task_status TEXT NOT NULL, task_tag TEXT, task_due_date TEXT, task_created_at TEXT);
+            CREATE TABLE RedoHistory (id INTEGER PRIMARY KEY AUTOINCREMENT, redo_command TEXT NOT NULL, undo_command TEXT NOT NULL, created_at TEXT, task_id INTEGER, task_text TEXT NOT NULL, task_status TEXT NOT NULL, task_tag TEXT, task_due_date TEXT, task_created_at TEXT);
+            INSERT INTO Tasks VALUES (1, 'Watch Oppenheimer', 'Done', 'movies', NULL, '2023-08-01 10:00:00');
+            INSERT INTO Tasks VALUES (2, 'Meditate', 'Undone', NULL, '2023-09-01', '2023-08-01 12:00:00');
+            INSERT INTO UndoHistory VALUES (1, 'DELETE FROM Tasks WHERE id = (SELECT MAX(id) FROM Tasks)', '2023-08-01 10:00:00', 1, 'Watch Oppenheimer', 'Undone', NULL, NULL, '2023-08-01 10:00:00');
+            INSERT INTO UndoHistory VALUES (2, 'UPDATE Tasks SET text = ''Watch Oppenheimer'', status = ''Undone'', tag = NULL, due_date = NULL, created_at = ''2023-08-01 10:00:00'' WHERE id = 1', '2023-08-01 11:00:00', 1, 'Watch Oppenheimer', 'Done', NULL, NULL, '2023-08-01 10:00:00');
+            INSERT INTO UndoHistory VALUES (3, 'DELETE FROM Tasks WHERE id = (SELECT MAX(id) FROM Tasks)', '2023-08-01 12:00:00', 2, 'Meditate', 'Undone', NULL, NULL, '2023-08-01 12:00:00');
+            INSERT INTO RedoHistory VALUES (1, 'DELETE FROM Tasks WHERE id = 2', 'INSERT INTO Tasks (id, text, status, tag, due_date, created_at) VALUES (2, ''Meditate'', ''Undone'', NULL, NULL, ''2023-08-01 12:00:00'')', '2023-08-01 12:00:00', 2, 'Meditate', 'Undone', NULL, NULL, '2023-08-01 12:00:00');",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn new_database_should_be_created_at_latest_version() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        migrate(&conn).unwrap();
+
+        assert_eq!(SCHEMA_VERSION, schema_version(&conn).unwrap());
+        assert_eq!(0, count(&conn, "Tasks"));
+        assert_eq!(0, count(&conn, "UndoHistory"));
+    }
+
+    #[test]
+    fn v0_1_1_database_should_be_upgraded() {
+        let conn = setup_v0_1_1_database();
+
+        migrate(&conn).unwrap();
+
+        assert_eq!(SCHEMA_VERSION, schema_version(&conn).unwrap());
+        assert_eq!(2, count(&conn, "Tasks"));
+        assert_eq!(3, count(&conn, "UndoHistory"));
+        assert_eq!(1, count(&conn, "RedoHistory"));
+
+        let operations: Vec<String> = conn
+            .prepare("SELECT operation FROM UndoHistory ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|operation| operation.unwrap())
+            .collect();
+        assert_eq!(vec!["Create", "Update", "Create"], operations);
+
+        let found: i64 = conn
+            .query_row(
+                "SELECT rowid FROM TaskSearch WHERE TaskSearch MATCH 'oppenheimer'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(1, found);
+
+        let before_status: String = conn
+            .query_row(
+                "SELECT s.status FROM UndoHistory h JOIN TaskSnapshots s ON s.id = h.before_snapshot WHERE h.operation = 'Update'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("Undone", before_status);
+
+        let tag: String = conn
+            .query_row(
+                "SELECT Tags.name FROM TaskTags JOIN Tags ON Tags.id = TaskTags.tag_id WHERE TaskTags.task_id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("movies", tag);
+
+        let due_date: String = conn
+            .query_row("SELECT due_date FROM Tasks WHERE id = 2", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!("2023-09-01 23:59:59", due_date);
+
+        let (uuid, display_index): (String, Option<i32>) = conn
+            .query_row(
+                "SELECT uuid, display_index FROM Tasks WHERE id = 2",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(Some(1), display_index);
+        let snapshot_uuids: Vec<String> = conn
+            .prepare("SELECT uuid FROM TaskSnapshots WHERE task_id = 2")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|uuid| uuid.unwrap())
+            .collect();
+        assert!(!snapshot_uuids.is_empty());
+        assert!(snapshot_uuids
+            .iter()
+            .all(|snapshot_uuid| *snapshot_uuid == uuid));
+
+        // Removing task 2 starts from the snapshot its creation left
+        let (created, removed): (i64, i64) = conn
+            .query_row(
+                "SELECT (SELECT after_snapshot FROM UndoHistory WHERE task_id = 2), (SELECT before_snapshot FROM RedoHistory WHERE task_id = 2)",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(created, removed);
+        assert_eq!(1, snapshot_uuids.len());
+
+        let times: Vec<String> = conn
+            .prepare(
+                "SELECT created_at FROM UndoHistory UNION ALL SELECT created_at FROM RedoHistory",
+            )
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|time| time.unwrap())
+            .collect();
+        assert!(times.iter().all(|time| time.ends_with(".000000 +00:00")));
+    }
+
+    #[test]
+    fn migrating_twice_should_be_a_no_op() {
+        let conn = setup_v0_1_1_database();
+
+        migrate(&conn).unwrap();
+        migrate(&conn).unwrap();
+
+        assert_eq!(SCHEMA_VERSION, schema_version(&conn).unwrap());
+        assert_eq!(3, count(&conn, "UndoHistory"));
+    }
+
+    #[test]
+    fn newer_database_should_be_refused() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+
+        match migrate(&conn) {
+            Err(Error::UnsupportedSchema { found, supported }) => {
+                assert_eq!(SCHEMA_VERSION + 1, found);
+                assert_eq!(SCHEMA_VERSION, supported);
+            }
+            _ => panic!("newer database should not be opened"),
+        }
+    }
+}
@@ -0,0 +1,713 @@
+use chrono::{Local, NaiveDateTime, TimeZone};
+use clap::ValueEnum;
+use colored::Colorize;
+use list_rs::search::SearchMatch;
+use list_rs::{HistoryBranch, HistoryEntry, HistoryNames, HistoryStep, Project, Task};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// How listings and command results are printed.
+#[derive(Debug, PartialEq, Clone, Copy, Default, ValueEnum)]
+pub enum Format {
+    /// Colored text for humans
+    #[default]
+    Text,
+    /// A JSON array of tasks, results of other commands as a JSON object
+    Json,
+    /// Comma separated values with a header row
+    Csv,
+    /// Tab separated values with a header row
+    Tsv,
+}
+
+/// The version of the documented output schema. Records carry it so scripts
+/// can tell when field names or formats changed, and it has to be raised
+/// with every such change.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The machine readable form of a task. Tasks are referred to by their number
+/// while pending and by their uuid, database ids are never shown.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TaskRecord {
+    pub id: Option<i32>,
+    pub text: String,
+    pub status: String,
+    pub tags: Vec<String>,
+    pub due_date: Option<String>,
+    pub created_at: String,
+    pub priority: Option<String>,
+    pub recurrence: Option<String>,
+    /// Uuid of the parent task.
+    pub parent: Option<String>,
+    /// Uuids of the tasks it waits for.
+    pub depends_on: Vec<String>,
+    pub project_id: Option<i32>,
+    pub uuid: String,
+    pub schema_version: u32,
+}
+
+impl TaskRecord {
+    /// Builds the record of `task`. `tasks` has to hold its parent and the
+    /// tasks it depends on, to look up their uuids.
+    pub fn new(task: &Task, tasks: &[Task]) -> Self {
+        TaskRecord {
+            id: task.index,
+            text: task.text.clone(),
+            status: task.status.to_string(),
+            tags: task.tags.clone(),
+            due_date: task.due_date.map(to_iso_8601),
+            created_at: to_iso_8601(task.created_at),
+            priority: task.priority.map(|priority| priority.to_string()),
+            recurrence: task
+                .recurrence
+                .as_ref()
+                .map(|recurrence| recurrence.to_string()),
+            parent: task
+                .parent_id
+                .and_then(|id| uuid_of(tasks, id))
+                .map(String::from),
+            depends_on: task
+                .depends_on
+                .iter()
+                .filter_map(|&id| uuid_of(tasks, id))
+                .map(String::from)
+                .collect(),
+            project_id: task.project_id,
+            uuid: task.uuid.clone(),
+            schema_version: SCHEMA_VERSION,
+        }
+    }
+}
+
+/// The uuid of the task with the given database id among `tasks`.
+pub fn uuid_of(tasks: &[Task], id: i32) -> Option<&str> {
+    tasks
+        .iter()
+        .find(|task| task.id == id)
+        .map(|task| task.uuid.as_str())
+}
+
+/// The machine readable form of a search result.
+#[derive(Debug, Serialize)]
+struct MatchRecord {
+    #[serde(flatten)]
+    task: TaskRecord,
+    snippet: String,
+    score: f64,
+}
+
+/// CSV and TSV columns of a task.
+const TASK_HEADER: &[&str] = &[
+    "id",
+    "text",
+    "status",
+    "tags",
+    "due_date",
+    "created_at",
+    "priority",
+    "recurrence",
+    "parent",
+    "depends_on",
+    "project_id",
+    "uuid",
+    "schema_version",
+];
+
+impl TaskRecord {
+    fn to_row(&self) -> Vec<String> {
+        vec![
+            self.id.map(|id| id.to_string()).unwrap_or_default(),
+            self.text.clone(),
+            self.status.clone(),
+            self.tags.join(" "),
+            self.due_date.clone().unwrap_or_default(),
+            self.created_at.clone(),
+            self.priority.clone().unwrap_or_default(),
+            self.recurrence.clone().unwrap_or_default(),
+            self.parent.clone().unwrap_or_default(),
+            self.depends_on.join(" "),
+            self.project_id.map(|id| id.to_string()).unwrap_or_default(),
+            self.uuid.clone(),
+            self.schema_version.to_string(),
+        ]
+    }
+}
+
+/// Formats a local date-time as ISO 8601 with the local UTC offset.
+pub fn to_iso_8601(datetime: NaiveDateTime) -> String {
+    match Local.from_local_datetime(&datetime).earliest() {
+        Some(datetime) => datetime.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        // The time doesn't exist locally (skipped by a DST change)
+        None => datetime.format("%Y-%m-%dT%H:%M:%S").to_string(),
+    }
+}
+
+/// Writes tasks in a machine readable format, `all` holds every task to look
+/// up parents and dependencies. Text output is handled by the caller since it
+/// has its own messages.
+pub fn write_tasks<W: Write>(
+    writer: W,
+    format: Format,
+    tasks: &[&Task],
+    all: &[Task],
+) -> io::Result<()> {
+    let records: Vec<TaskRecord> = tasks
+        .iter()
+        .map(|&task| TaskRecord::new(task, all))
+        .collect();
+
+    match format {
+        Format::Json => write_json(writer, &records),
+        Format::Csv | Format::Tsv => write_delimited(
+            writer,
+            format,
+            TASK_HEADER,
+            records.iter().map(TaskRecord::to_row),
+        ),
+        Format::Text => Ok(()),
+    }
+}
+
+/// Writes search results as tasks with their `snippet`, matches wrapped in
+/// `[` and `]`, and relevance `score`.
+pub fn write_matches<W: Write>(
+    writer: W,
+    format: Format,
+    matches: &[SearchMatch],
+    all: &[Task],
+) -> io::Result<()> {
+    let snippet = |found: &SearchMatch| found.marked("[", "]");
+
+    match format {
+        Format::Json => {
+            let records: Vec<MatchRecord> = matches
+                .iter()
+                .map(|found| MatchRecord {
+                    task: TaskRecord::new(&found.task, all),
+                    snippet: snippet(found),
+                    score: found.score,
+                })
+                .collect();
+            write_json(writer, &records)
+        }
+        Format::Csv | Format::Tsv => {
+            let header = [TASK_HEADER, &["snippet", "score"]].concat();
+            write_delimited(
+                writer,
+                format,
+                &header,
+                matches.iter().map(|found| {
+                    let mut row = TaskRecord::new(&found.task, all).to_row();
+                    row.push(snippet(found));
+                    row.push(found.score.to_string());
+                    row
+                }),
+            )
+        }
+        Format::Text => Ok(()),
+    }
+}
+
+/// Writes tag names with the number of tasks carrying them.
+pub fn write_tags<W: Write>(writer: W, format: Format, tags: &[(String, usize)]) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            let tags: Vec<Value> = tags
+                .iter()
+                .map(|(tag, count)| json!({ "tag": tag, "count": count }))
+                .collect();
+            write_json(writer, &tags)
+        }
+        Format::Csv | Format::Tsv => write_delimited(
+            writer,
+            format,
+            &["tag", "count"],
+            tags.iter()
+                .map(|(tag, count)| vec![tag.clone(), count.to_string()]),
+        ),
+        Format::Text => Ok(()),
+    }
+}
+
+/// Writes projects with the number of their tasks by status. `active` is the
+/// id of the active project.
+pub fn write_projects<W: Write>(
+    writer: W,
+    format: Format,
+    projects: &[Project],
+    active: Option<i32>,
+) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            let projects: Vec<Value> = projects
+                .iter()
+                .map(|project| {
+                    json!({
+                        "id": project.id,
+                        "name": project.name,
+                        "active": Some(project.id) == active,
+                        "undone": project.undone,
+                        "done": project.done,
+                        "archived": project.archived,
+                    })
+                })
+                .collect();
+            write_json(writer, &projects)
+        }
+        Format::Csv | Format::Tsv => write_delimited(
+            writer,
+            format,
+            &["id", "name", "active", "undone", "done", "archived"],
+            projects.iter().map(|project| {
+                vec![
+                    project.id.to_string(),
+                    project.name.clone(),
+                    (Some(project.id) == active).to_string(),
+                    project.undone.to_string(),
+                    project.done.to_string(),
+                    project.archived.to_string(),
+                ]
+            }),
+        ),
+        Format::Text => Ok(()),
+    }
+}
+
+/// Writes profiles with the paths of their databases. `active` is the name of
+/// the active profile.
+pub fn write_profiles<W: Write>(
+    writer: W,
+    format: Format,
+    profiles: &[(String, PathBuf)],
+    active: &str,
+) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            let profiles: Vec<Value> = profiles
+                .iter()
+                .map(|(name, db)| json!({ "name": name, "db": db, "active": name == active }))
+                .collect();
+            write_json(writer, &profiles)
+        }
+        Format::Csv | Format::Tsv => write_delimited(
+            writer,
+            format,
+            &["name", "db", "active"],
+            profiles.iter().map(|(name, db)| {
+                vec![
+                    name.clone(),
+                    db.display().to_string(),
+                    (name == active).to_string(),
+                ]
+            }),
+        ),
+        Format::Text => Ok(()),
+    }
+}
+
+/// Writes config preferences with their values, `None` for unset ones.
+pub fn write_preferences<W: Write>(
+    writer: W,
+    format: Format,
+    preferences: &[(&str, Option<String>)],
+) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            let preferences: Vec<Value> = preferences
+                .iter()
+                .map(|(key, value)| json!({ "key": key, "value": value }))
+                .collect();
+            write_json(writer, &preferences)
+        }
+        Format::Csv | Format::Tsv => write_delimited(
+            writer,
+            format,
+            &["key", "value"],
+            preferences
+                .iter()
+                .map(|(key, value)| vec![key.to_string(), value.clone().unwrap_or_default()]),
+        ),
+        Format::Text => Ok(()),
+    }
+}
+
+/// The machine readable form of a change recorded in the history. The task is
+/// referred to like in [`TaskRecord`], by the number it had and its uuid.
+pub fn change_record(entry: &HistoryEntry, names: &HistoryNames) -> Value {
+    let task = entry.task();
+    json!({
+        "operation": entry.operation.to_string(),
+        "task_id": task.and_then(|task| task.index),
+        "task_uuid": task.map(|task| &task.uuid),
+        "description": entry.describe(names),
+        "schema_version": SCHEMA_VERSION,
+    })
+}
+
+/// The machine readable form of a step of the history.
+fn step_record(step: &HistoryStep, names: &HistoryNames) -> Value {
+    json!({
+        "id": step.id,
+        "created_at": step.created_at.map(to_iso_8601),
+        "undone": step.undone,
+        "changes": step
+            .entries
+            .iter()
+            .map(|entry| change_record(entry, names))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// The CSV and TSV rows of a step of the history, one per change.
+fn step_rows<'a>(
+    step: &'a HistoryStep,
+    names: &'a HistoryNames,
+) -> impl Iterator<Item = Vec<String>> + 'a {
+    step.entries.iter().map(move |entry| {
+        let task = entry.task();
+        vec![
+            step.id.to_string(),
+            step.created_at.map(to_iso_8601).unwrap_or_default(),
+            step.undone.to_string(),
+            entry.operation.to_string(),
+            task.and_then(|task| task.index)
+                .map(|index| index.to_string())
+                .unwrap_or_default(),
+            task.map(|task| task.uuid.clone()).unwrap_or_default(),
+            entry.describe(names),
+            SCHEMA_VERSION.to_string(),
+        ]
+    })
+}
+
+const STEP_HEADER: [&str; 8] = [
+    "step",
+    "created_at",
+    "undone",
+    "operation",
+    "task_id",
+    "task_uuid",
+    "description",
+    "schema_version",
+];
+
+/// Writes the steps of the history with their changes, described with
+/// `names`. CSV and TSV have a row per change.
+pub fn write_history<W: Write>(
+    writer: W,
+    format: Format,
+    steps: &[HistoryStep],
+    names: &HistoryNames,
+) -> io::Result<()> {
+    match format {
+        Format::Json => write_json(
+            writer,
+            &steps
+                .iter()
+                .map(|step| step_record(step, names))
+                .collect::<Vec<_>>(),
+        ),
+        Format::Csv | Format::Tsv => write_delimited(
+            writer,
+            format,
+            &STEP_HEADER,
+            steps.iter().flat_map(|step| step_rows(step, names)),
+        ),
+        Format::Text => Ok(()),
+    }
+}
+
+/// Writes the history together with its branches. CSV and TSV have a row per
+/// change, with the branch and the step it continues from left empty for the
+/// changes of the history itself.
+pub fn write_history_tree<W: Write>(
+    writer: W,
+    format: Format,
+    steps: &[HistoryStep],
+    branches: &[HistoryBranch],
+    names: &HistoryNames,
+) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            let branches: Vec<Value> = branches
+                .iter()
+                .map(|branch| {
+                    json!({
+                        "id": branch.id,
+                        "fork": branch.fork,
+                        "created_at": branch.created_at.map(to_iso_8601),
+                        "steps": branch
+                            .steps
+                            .iter()
+                            .map(|step| step_record(step, names))
+                            .collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            write_json(
+                writer,
+                &json!({
+                    "steps": steps
+                        .iter()
+                        .map(|step| step_record(step, names))
+                        .collect::<Vec<_>>(),
+                    "branches": branches,
+                }),
+            )
+        }
+        Format::Csv | Format::Tsv => {
+            let header: Vec<&str> = ["branch", "fork"].into_iter().chain(STEP_HEADER).collect();
+            let history = steps.iter().flat_map(|step| {
+                step_rows(step, names).map(|row| [vec![String::new(), String::new()], row].concat())
+            });
+            let branches = branches.iter().flat_map(|branch| {
+                let fork = branch.fork.map(|fork| fork.to_string()).unwrap_or_default();
+                branch
+                    .steps
+                    .iter()
+                    .flat_map(|step| step_rows(step, names))
+                    .map(move |row| [vec![branch.id.to_string(), fork.clone()], row].concat())
+            });
+            write_delimited(writer, format, &header, history.chain(branches))
+        }
+        Format::Text => Ok(()),
+    }
+}
+
+fn write_json<W: Write, T: Serialize>(mut writer: W, value: &T) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writeln!(writer)
+}
+
+fn write_delimited<W, I>(writer: W, format: Format, header: &[&str], rows: I) -> io::Result<()>
+where
+    W: Write,
+    I: Iterator<Item = Vec<String>>,
+{
+    let delimiter = if format == Format::Tsv { b'\t' } else { b',' };
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+
+    writer.write_record(header)?;
+    for row in rows {
+        writer.write_record(&row)?;
+    }
+    writer.flush()
+}
+
+/// Prints the outcome of a command: `message` for text, CSV and TSV, or
+/// `result` with `"ok": true` added for JSON. Empty messages aren't printed.
+pub fn print_result(format: Format, message: &str, mut result: Value) {
+    if format == Format::Json {
+        result["ok"] = json!(true);
+        println!("{}", result);
+    } else if !message.is_empty() {
+        println!("{}", message);
+    }
+}
+
+/// Prints a warning about a command that still succeeded to stderr.
+pub fn print_warning(message: &str) {
+    eprintln!("{} {}", "Warning:".yellow().bold(), message);
+}
+
+/// Prints a failed command to stderr: `message` for text, CSV and TSV, or a
+/// JSON object with `"ok": false` and the message as `error`.
+pub fn print_error(format: Format, message: &str) {
+    if format == Format::Json {
+        eprintln!("{}", json!({ "ok": false, "error": message }));
+    } else {
+        eprintln!("Error: {}", message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_history, write_matches, write_tags, write_tasks, Format};
+    use chrono::NaiveDate;
+    use list_rs::search::SearchMatch;
+    use list_rs::{
+        HistoryEntry, HistoryNames, HistoryStep, Operation, Priority, Recurrence, Task, TaskStatus,
+    };
+
+    fn tasks() -> Vec<Task> {
+        let created_at = NaiveDate::from_ymd_opt(2023, 8, 1)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        vec![
+            Task {
+                priority: Some(Priority::High),
+                recurrence: Some(Recurrence::Daily),
+                uuid: String::from("0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a"),
+                index: Some(1),
+                ..Task::new_with_created_at(
+                    1,
+                    "Watch \"Oppenheimer\", then sleep",
+                    TaskStatus::Undone,
+                    vec![String::from("fun"), String::from("movies")],
+                    NaiveDate::from_ymd_opt(2023, 8, 25)
+                        .unwrap()
+                        .and_hms_opt(23, 59, 59),
+                    created_at,
+                )
+            },
+            Task {
+                parent_id: Some(1),
+                depends_on: vec![1],
+                uuid: String::from("7d3e9f10-2c4b-4a6e-8f1d-5b0a9c8e7f6d"),
+                ..Task::new_with_created_at(
+                    2,
+                    "Meditate",
+                    TaskStatus::Done,
+                    vec![],
+                    None,
+                    created_at,
+                )
+            },
+        ]
+    }
+
+    fn render(format: Format) -> String {
+        let all = tasks();
+        let tasks: Vec<&Task> = all.iter().collect();
+        let mut output = Vec::new();
+        write_tasks(&mut output, format, &tasks, &all).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn json_should_follow_the_schema() {
+        let value: serde_json::Value = serde_json::from_str(&render(Format::Json)).unwrap();
+        let first = &value[0];
+
+        assert_eq!(1, first["id"]);
+        assert_eq!("Watch \"Oppenheimer\", then sleep", first["text"]);
+        assert_eq!("Undone", first["status"]);
+        assert_eq!(serde_json::json!(["fun", "movies"]), first["tags"]);
+        assert!(first["due_date"]
+            .as_str()
+            .unwrap()
+            .starts_with("2023-08-25T23:59:59"));
+        assert!(first["created_at"]
+            .as_str()
+            .unwrap()
+            .starts_with("2023-08-01T10:00:00"));
+        assert_eq!("High", first["priority"]);
+        assert_eq!("daily", first["recurrence"]);
+        assert_eq!(serde_json::Value::Null, value[1]["due_date"]);
+        assert_eq!(serde_json::Value::Null, value[1]["priority"]);
+        assert_eq!(serde_json::Value::Null, value[1]["recurrence"]);
+        assert_eq!(serde_json::Value::Null, value[1]["id"]);
+        assert_eq!(serde_json::Value::Null, first["parent"]);
+        assert_eq!("0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a", value[1]["parent"]);
+        assert_eq!(serde_json::json!([]), first["depends_on"]);
+        assert_eq!(
+            serde_json::json!(["0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a"]),
+            value[1]["depends_on"]
+        );
+        assert_eq!("0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a", first["uuid"]);
+        assert_eq!("7d3e9f10-2c4b-4a6e-8f1d-5b0a9c8e7f6d", value[1]["uuid"]);
+        assert!(first.get("parent_id").is_none());
+        assert!(first.get("index").is_none());
+        assert_eq!(2, first["schema_version"]);
+        assert_eq!(2, value.as_array().unwrap().len());
+    }
+
+    #[test]
+    fn csv_should_quote_fields() {
+        let output = render(Format::Csv);
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(
+            "id,text,status,tags,due_date,created_at,priority,recurrence,parent,depends_on,project_id,uuid,schema_version",
+            lines[0]
+        );
+        assert!(lines[1].starts_with(
+            "1,\"Watch \"\"Oppenheimer\"\", then sleep\",Undone,fun movies,2023-08-25T23:59:59"
+        ));
+        assert!(lines[1].ends_with(",High,daily,,,,0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a,2"));
+        assert!(lines[2].starts_with(",Meditate,Done,,,2023-08-01T10:00:00"));
+        assert!(lines[2].ends_with(",0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a,0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a,,7d3e9f10-2c4b-4a6e-8f1d-5b0a9c8e7f6d,2"));
+        assert_eq!(3, lines.len());
+    }
+
+    #[test]
+    fn tsv_should_use_tabs() {
+        let output = render(Format::Tsv);
+
+        assert!(output.starts_with(
+            "id\ttext\tstatus\ttags\tdue_date\tcreated_at\tpriority\trecurrence\tparent\tdepends_on\tproject_id\tuuid\tschema_version\n"
+        ));
+        assert!(output.contains("\n\tMeditate\tDone\t\t\t2023-08-01T10:00:00"));
+    }
+
+    #[test]
+    fn tags_should_be_written() {
+        let tags = vec![(String::from("work"), 2)];
+        let mut output = Vec::new();
+
+        write_tags(&mut output, Format::Csv, &tags).unwrap();
+
+        assert_eq!("tag,count\nwork,2\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn matches_should_have_marked_snippets() {
+        let all = tasks();
+        let found = SearchMatch {
+            task: all[1].clone(),
+            snippet: String::from("Meditate"),
+            highlights: vec![0..4, 6..8],
+            score: 1.5,
+        };
+        let mut output = Vec::new();
+
+        write_matches(&mut output, Format::Csv, &[found], &all).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            "id,text,status,tags,due_date,created_at,priority,recurrence,parent,depends_on,project_id,uuid,schema_version,snippet,score",
+            lines[0]
+        );
+        assert!(lines[1].ends_with(",[Medi]ta[te],1.5"));
+    }
+
+    #[test]
+    fn history_should_name_tasks_by_number_and_uuid() {
+        let task = tasks().remove(0);
+        let done = Task {
+            status: TaskStatus::Done,
+            index: None,
+            ..task.clone()
+        };
+        let step = HistoryStep {
+            id: 7,
+            created_at: None,
+            undone: false,
+            entries: vec![HistoryEntry {
+                id: 12,
+                operation: Operation::Update,
+                task_id: 42,
+                before: Some(task),
+                after: Some(done),
+            }],
+        };
+        let mut output = Vec::new();
+
+        write_history(&mut output, Format::Csv, &[step], &HistoryNames::default()).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            "step,created_at,undone,operation,task_id,task_uuid,description,schema_version",
+            lines[0]
+        );
+        assert!(lines[1].starts_with("7,,false,Update,1,0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a,"));
+        assert!(!lines[1].contains("42"));
+    }
+}
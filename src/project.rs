@@ -0,0 +1,45 @@
+//! Named lists grouping tasks, see [`TaskStore::create_project`](crate::TaskStore::create_project).
+
+use crate::error::{Error, Result};
+
+/// A named list of tasks, with the number of its tasks in each status.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Project {
+    pub id: i32,
+    pub name: String,
+    pub undone: usize,
+    pub done: usize,
+    pub archived: usize,
+}
+
+impl Project {
+    /// The number of tasks in the project.
+    pub fn total(&self) -> usize {
+        self.undone + self.done + self.archived
+    }
+}
+
+/// Checks a project name given by the user and returns it without
+/// surrounding whitespace.
+pub(crate) fn parse_project_name(name: &str) -> Result<&str> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(',') {
+        return Err(Error::InvalidInput(format!(
+            "Invalid project name '{}', names can't be empty or contain commas",
+            name
+        )));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_project_name;
+
+    #[test]
+    fn names_should_be_trimmed_and_validated() {
+        assert_eq!("Home office", parse_project_name(" Home office ").unwrap());
+        assert!(parse_project_name("  ").is_err());
+        assert!(parse_project_name("a,b").is_err());
+    }
+}
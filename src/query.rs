@@ -0,0 +1,654 @@
+//! A small filter language for tasks, e.g.
+//! `status:undone and (tag:work or due<friday) and text~"report"`.
+//!
+//! Conditions are `field`, an operator and a value. Conditions are combined
+//! with `and`, `or`, `not` and parentheses, `and` binding tighter than `or`.
+//! Conditions next to each other are joined with `and`, and a bare word or
+//! quoted string matches the task text.
+//!
+//! | Field | Operators | Values |
+//! | --- | --- | --- |
+//! | `id` | `:` `<` `<=` `>` `>=` | The number of a pending task |
+//! | `uuid` | `:` | The uuid of a task or its first characters |
+//! | `status` | `:` | `done`, `undone` or `archived` |
+//! | `priority` | `:` `<` `<=` `>` `>=` | `high`, `medium`, `low` or `none`, `high` being the greatest |
+//! | `tag` | `:` `~` | A tag name or `none` |
+//! | `text` | `:` `~` | Any text, both match tasks containing it |
+//! | `due`, `created` | `:` `<` `<=` `>` `>=` | A date as accepted by `--due`, or `none` for `due` |
+//! | `blocked` | `:` | `yes` if a task it depends on isn't done yet, or `no` |
+//! | `project` | `:` | A project name or `none` |
+//!
+//! `:` compares dates by day, `~` matches part of a tag or text. Text matches
+//! ignore the case of ASCII letters.
+
+use crate::due::parse_due;
+use crate::error::{Error, Result};
+use crate::task::{Priority, TaskStatus};
+use chrono::NaiveDateTime;
+use rusqlite::types::Value as SqlValue;
+
+/// A parsed filter expression.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Condition(Condition),
+}
+
+/// A single `field op value` test.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Condition {
+    pub field: Field,
+    pub op: Op,
+    pub value: Value,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Field {
+    /// The number of a pending task, see [`Task::label`](crate::Task::label).
+    Id,
+    Uuid,
+    Status,
+    Priority,
+    Tag,
+    Text,
+    Due,
+    Created,
+    Blocked,
+    Project,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    /// `:`
+    Is,
+    /// `~`
+    Contains,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// The value of a condition, already checked against its field.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    /// The field isn't set, written `none`.
+    None,
+    Id(i32),
+    Status(TaskStatus),
+    Priority(Priority),
+    Text(String),
+    Date(NaiveDateTime),
+    Bool(bool),
+}
+
+impl Expr {
+    /// Parses a filter expression. Dates are relative to `now`.
+    pub fn parse(query: &str, now: NaiveDateTime) -> Result<Expr> {
+        let tokens = tokenize(query)?;
+        let mut parser = Parser {
+            query,
+            tokens,
+            index: 0,
+            now,
+        };
+
+        let expr = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) if token.kind == TokenKind::RightParen => {
+                Err(parser.error_at(token.start, "unmatched ')'"))
+            }
+            Some(token) => Err(parser.error_at(token.start, "expected 'and', 'or' or the end")),
+        }
+    }
+
+    pub fn condition(field: Field, op: Op, value: Value) -> Expr {
+        Expr::Condition(Condition { field, op, value })
+    }
+
+    pub fn and(self, other: Expr) -> Expr {
+        Expr::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Expr) -> Expr {
+        Expr::Or(Box::new(self), Box::new(other))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Expr {
+        Expr::Not(Box::new(self))
+    }
+
+    /// Whether any condition of the expression tests `field`.
+    pub fn mentions(&self, field: Field) -> bool {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => a.mentions(field) || b.mentions(field),
+            Expr::Not(expr) => expr.mentions(field),
+            Expr::Condition(condition) => condition.field == field,
+        }
+    }
+
+    /// Compiles the expression to an SQL condition on the `Tasks` table, with
+    /// its parameters.
+    pub(crate) fn to_sql(&self) -> (String, Vec<SqlValue>) {
+        let mut params = Vec::new();
+        let sql = self.write_sql(&mut params);
+        (sql, params)
+    }
+
+    fn write_sql(&self, params: &mut Vec<SqlValue>) -> String {
+        match self {
+            Expr::And(a, b) => format!("({} AND {})", a.write_sql(params), b.write_sql(params)),
+            Expr::Or(a, b) => format!("({} OR {})", a.write_sql(params), b.write_sql(params)),
+            // Conditions on missing values are NULL, which `not` must turn into true
+            Expr::Not(expr) => format!("NOT COALESCE({}, 0)", expr.write_sql(params)),
+            Expr::Condition(condition) => condition.write_sql(params),
+        }
+    }
+}
+
+impl Condition {
+    fn write_sql(&self, params: &mut Vec<SqlValue>) -> String {
+        const HAS_TAG: &str = "EXISTS (SELECT 1 FROM TaskTags JOIN Tags ON Tags.id = TaskTags.tag_id WHERE TaskTags.task_id = Tasks.id AND Tags.name";
+
+        let operator = match self.op {
+            Op::Less => "<",
+            Op::LessOrEqual => "<=",
+            Op::Greater => ">",
+            Op::GreaterOrEqual => ">=",
+            Op::Is | Op::Contains => "=",
+        };
+
+        match (self.field, &self.value) {
+            (Field::Due, Value::None) => String::from("due_date IS NULL"),
+            (Field::Priority, Value::None) => String::from("priority IS NULL"),
+            (Field::Tag, Value::None) => String::from(
+                "NOT EXISTS (SELECT 1 FROM TaskTags WHERE TaskTags.task_id = Tasks.id)",
+            ),
+            (Field::Project, Value::None) => String::from(
+                "NOT EXISTS (SELECT 1 FROM Projects WHERE Projects.id = Tasks.project_id)",
+            ),
+            (Field::Id, Value::Id(id)) => {
+                params.push(SqlValue::Integer(*id as i64));
+                format!("display_index {} ?", operator)
+            }
+            (Field::Uuid, Value::Text(uuid)) => {
+                params.push(SqlValue::Text(format!("{}%", escape_like(uuid))));
+                String::from("uuid LIKE ? ESCAPE '\\'")
+            }
+            (Field::Status, Value::Status(status)) => {
+                params.push(SqlValue::Text(status.to_string()));
+                String::from("status = ?")
+            }
+            (Field::Priority, Value::Priority(priority)) if self.op == Op::Is => {
+                params.push(SqlValue::Text(priority.to_string()));
+                String::from("priority = ?")
+            }
+            (Field::Priority, Value::Priority(priority)) => {
+                params.push(SqlValue::Integer(priority_rank(*priority)));
+                format!(
+                    "CASE priority WHEN 'High' THEN 3 WHEN 'Medium' THEN 2 WHEN 'Low' THEN 1 END {} ?",
+                    operator
+                )
+            }
+            (Field::Tag, Value::Text(tag)) if self.op == Op::Contains => {
+                params.push(SqlValue::Text(like_pattern(tag)));
+                format!("{} LIKE ? ESCAPE '\\')", HAS_TAG)
+            }
+            (Field::Tag, Value::Text(tag)) => {
+                params.push(SqlValue::Text(tag.clone()));
+                format!("{} = ?)", HAS_TAG)
+            }
+            (Field::Text, Value::Text(text)) => {
+                params.push(SqlValue::Text(like_pattern(text)));
+                String::from("text LIKE ? ESCAPE '\\'")
+            }
+            (Field::Due | Field::Created, Value::Date(date)) => {
+                let column = if self.field == Field::Due {
+                    "due_date"
+                } else {
+                    "created_at"
+                };
+                // The same format rusqlite uses to store date-times
+                params.push(SqlValue::Text(date.format("%F %T%.f").to_string()));
+                if self.op == Op::Is {
+                    format!("date({}) = date(?)", column)
+                } else {
+                    format!("{} {} ?", column, operator)
+                }
+            }
+            (Field::Project, Value::Text(name)) => {
+                params.push(SqlValue::Text(name.clone()));
+                String::from("project_id = (SELECT id FROM Projects WHERE name = ?)")
+            }
+            (Field::Blocked, Value::Bool(blocked)) => format!(
+                "{}EXISTS (SELECT 1 FROM TaskDependencies JOIN Tasks AS Blocker ON Blocker.id = TaskDependencies.depends_on WHERE TaskDependencies.task_id = Tasks.id AND Blocker.status = 'Undone')",
+                if *blocked { "" } else { "NOT " }
+            ),
+            // The parser only builds the combinations above
+            _ => String::from("0"),
+        }
+    }
+}
+
+fn priority_rank(priority: Priority) -> i64 {
+    match priority {
+        Priority::High => 3,
+        Priority::Medium => 2,
+        Priority::Low => 1,
+    }
+}
+
+/// A `LIKE` pattern matching text containing `value`.
+fn like_pattern(value: &str) -> String {
+    format!("%{}%", escape_like(value))
+}
+
+/// Escapes the wildcards of `LIKE` in `value`.
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum TokenKind {
+    LeftParen,
+    RightParen,
+    Op(Op),
+    Word(String),
+    Quoted(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Token {
+    kind: TokenKind,
+    /// Position of the first character, counted in characters
+    start: usize,
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.kind, TokenKind::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let start = index;
+        let kind = match chars[index] {
+            c if c.is_whitespace() => {
+                index += 1;
+                continue;
+            }
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            ':' => TokenKind::Op(Op::Is),
+            '~' => TokenKind::Op(Op::Contains),
+            '<' | '>' => {
+                let or_equal = chars.get(index + 1) == Some(&'=');
+                let op = match (chars[index], or_equal) {
+                    ('<', false) => Op::Less,
+                    ('<', true) => Op::LessOrEqual,
+                    (_, false) => Op::Greater,
+                    (_, true) => Op::GreaterOrEqual,
+                };
+                if or_equal {
+                    index += 1;
+                }
+                TokenKind::Op(op)
+            }
+            '"' => {
+                let mut value = String::new();
+                index += 1;
+                loop {
+                    match chars.get(index) {
+                        None => return Err(query_error(query, start, "unterminated string")),
+                        Some('"') => break,
+                        Some('\\') if chars.get(index + 1).is_some() => {
+                            value.push(chars[index + 1]);
+                            index += 2;
+                        }
+                        Some(&c) => {
+                            value.push(c);
+                            index += 1;
+                        }
+                    }
+                }
+                TokenKind::Quoted(value)
+            }
+            _ => {
+                let end = chars[index..]
+                    .iter()
+                    .position(|&c| c.is_whitespace() || "():~<>\"".contains(c))
+                    .map_or(chars.len(), |offset| index + offset);
+                let word: String = chars[index..end].iter().collect();
+                index = end - 1;
+                TokenKind::Word(word)
+            }
+        };
+
+        index += 1;
+        tokens.push(Token { kind, start });
+    }
+
+    Ok(tokens)
+}
+
+fn query_error(query: &str, position: usize, message: &str) -> Error {
+    Error::InvalidQuery {
+        query: query.to_string(),
+        position,
+        message: message.to_string(),
+    }
+}
+
+struct Parser<'a> {
+    query: &'a str,
+    tokens: Vec<Token>,
+    index: usize,
+    now: NaiveDateTime,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        token
+    }
+
+    fn error_at(&self, position: usize, message: &str) -> Error {
+        query_error(self.query, position, message)
+    }
+
+    /// Position just after the last character, for errors at the end.
+    fn end(&self) -> usize {
+        self.query.chars().count()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.peek().is_some_and(|token| token.is_keyword("or")) {
+            self.next();
+            expr = expr.or(self.parse_and()?);
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            match self.peek() {
+                Some(token) if token.is_keyword("and") => {
+                    self.next();
+                }
+                // Conditions next to each other are joined with `and`
+                Some(token) if !token.is_keyword("or") && token.kind != TokenKind::RightParen => {}
+                _ => return Ok(expr),
+            }
+            expr = expr.and(self.parse_unary()?);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return Err(self.error_at(self.end(), "expected a condition")),
+        };
+
+        if token.is_keyword("not") {
+            return Ok(self.parse_unary()?.not());
+        }
+        if token.is_keyword("and") || token.is_keyword("or") {
+            return Err(self.error_at(token.start, "expected a condition before this"));
+        }
+
+        match token.kind {
+            TokenKind::LeftParen => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token {
+                        kind: TokenKind::RightParen,
+                        ..
+                    }) => Ok(expr),
+                    Some(token) => Err(self.error_at(token.start, "expected ')'")),
+                    None => Err(self.error_at(self.end(), "expected ')'")),
+                }
+            }
+            TokenKind::RightParen => Err(self.error_at(token.start, "expected a condition")),
+            TokenKind::Op(_) => Err(self.error_at(token.start, "expected a field name")),
+            TokenKind::Quoted(text) => Ok(Expr::condition(
+                Field::Text,
+                Op::Contains,
+                Value::Text(text),
+            )),
+            TokenKind::Word(word) => match self.peek().map(|token| token.kind.clone()) {
+                Some(TokenKind::Op(op)) => {
+                    self.next();
+                    self.parse_condition(&word, token.start, op)
+                }
+                _ => Ok(Expr::condition(
+                    Field::Text,
+                    Op::Contains,
+                    Value::Text(word),
+                )),
+            },
+        }
+    }
+
+    fn parse_condition(&mut self, name: &str, name_start: usize, op: Op) -> Result<Expr> {
+        let field = match name.to_lowercase().as_str() {
+            "id" => Field::Id,
+            "uuid" => Field::Uuid,
+            "status" => Field::Status,
+            "priority" => Field::Priority,
+            "tag" => Field::Tag,
+            "text" => Field::Text,
+            "due" => Field::Due,
+            "created" => Field::Created,
+            "blocked" => Field::Blocked,
+            "project" => Field::Project,
+            _ => {
+                return Err(self.error_at(
+                    name_start,
+                    &format!(
+                    "unknown field '{}', expected id, uuid, status, priority, tag, text, due, created, blocked or project",
+                    name
+                ),
+                ))
+            }
+        };
+
+        let op_start = self.tokens[self.index - 1].start;
+        let allowed = match field {
+            Field::Uuid | Field::Status | Field::Blocked | Field::Project => matches!(op, Op::Is),
+            Field::Tag | Field::Text => matches!(op, Op::Is | Op::Contains),
+            Field::Id | Field::Priority | Field::Due | Field::Created => op != Op::Contains,
+        };
+        if !allowed {
+            return Err(self.error_at(
+                op_start,
+                &format!("this operator can't be used with '{}'", name),
+            ));
+        }
+
+        let (raw, start) = match self.next() {
+            Some(Token {
+                kind: TokenKind::Word(value) | TokenKind::Quoted(value),
+                start,
+            }) => (value, start),
+            Some(token) => return Err(self.error_at(token.start, "expected a value")),
+            None => return Err(self.error_at(self.end(), "expected a value")),
+        };
+        let invalid = |message: String| self.error_at(start, &message);
+
+        let is_none = raw.eq_ignore_ascii_case("none");
+        let value = match field {
+            Field::Due | Field::Priority | Field::Tag | Field::Project if is_none => {
+                if op != Op::Is {
+                    return Err(invalid(String::from("'none' can only be used with ':'")));
+                }
+                Value::None
+            }
+            Field::Id => Value::Id(
+                raw.parse()
+                    .map_err(|_| invalid(format!("'{}' is not a task number", raw)))?,
+            ),
+            Field::Uuid => Value::Text(raw.to_lowercase()),
+            Field::Status => Value::Status(match raw.to_lowercase().as_str() {
+                "done" => TaskStatus::Done,
+                "undone" => TaskStatus::Undone,
+                "archived" => TaskStatus::Archived,
+                _ => {
+                    return Err(invalid(format!(
+                        "invalid status '{}', expected done, undone or archived",
+                        raw
+                    )))
+                }
+            }),
+            Field::Priority => {
+                Value::Priority(raw.parse().map_err(|e: Error| invalid(e.to_string()))?)
+            }
+            Field::Tag => Value::Text(raw.strip_prefix('+').unwrap_or(&raw).to_string()),
+            Field::Text | Field::Project => Value::Text(raw),
+            Field::Due | Field::Created => Value::Date(parse_due(&raw, self.now).map_err(invalid)?),
+            Field::Blocked => Value::Bool(match raw.to_lowercase().as_str() {
+                "yes" | "true" => true,
+                "no" | "false" => false,
+                _ => return Err(invalid(format!("'{}' is not yes or no", raw))),
+            }),
+        };
+
+        Ok(Expr::condition(field, op, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expr, Field, Op, Value};
+    use crate::error::Error;
+    use crate::task::{Priority, TaskStatus};
+    use chrono::NaiveDateTime;
+
+    /// A Wednesday afternoon
+    fn now() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2023-08-16 14:30:00", "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn parse(query: &str) -> Expr {
+        Expr::parse(query, now()).unwrap()
+    }
+
+    /// Returns the position and message of a parse error.
+    fn parse_error(query: &str) -> (usize, String) {
+        match Expr::parse(query, now()) {
+            Err(Error::InvalidQuery {
+                position, message, ..
+            }) => (position, message),
+            other => panic!("expected a query error for {}, got {:?}", query, other),
+        }
+    }
+
+    fn text(value: &str) -> Expr {
+        Expr::condition(Field::Text, Op::Contains, Value::Text(value.to_string()))
+    }
+
+    #[test]
+    fn conditions_should_be_parsed() {
+        assert_eq!(
+            Expr::condition(Field::Status, Op::Is, Value::Status(TaskStatus::Undone)),
+            parse("status:undone")
+        );
+        assert_eq!(
+            Expr::condition(
+                Field::Priority,
+                Op::GreaterOrEqual,
+                Value::Priority(Priority::Medium)
+            ),
+            parse("priority>=m")
+        );
+        assert_eq!(
+            Expr::condition(Field::Tag, Op::Is, Value::Text(String::from("work"))),
+            parse("tag:+work")
+        );
+        assert_eq!(
+            Expr::condition(Field::Due, Op::Is, Value::None),
+            parse("due:none")
+        );
+        assert_eq!(
+            Expr::condition(
+                Field::Due,
+                Op::Less,
+                Value::Date(
+                    NaiveDateTime::parse_from_str("2023-08-18 23:59:59", "%Y-%m-%d %H:%M:%S")
+                        .unwrap()
+                )
+            ),
+            parse("due<friday")
+        );
+        assert_eq!(text("Bob's report"), parse("text~\"Bob's report\""));
+        assert_eq!(
+            Expr::condition(Field::Blocked, Op::Is, Value::Bool(false)),
+            parse("blocked:no")
+        );
+    }
+
+    #[test]
+    fn and_should_bind_tighter_than_or() {
+        let status = Expr::condition(Field::Status, Op::Is, Value::Status(TaskStatus::Done));
+
+        assert_eq!(
+            status.clone().or(text("a").and(text("b"))),
+            parse("status:done or a and b")
+        );
+        assert_eq!(
+            status.clone().or(text("a")).and(text("b")),
+            parse("(status:done or a) b")
+        );
+        assert_eq!(status.not().and(text("a")), parse("NOT status:done a"));
+    }
+
+    #[test]
+    fn errors_should_point_at_the_bad_token() {
+        assert_eq!(0, parse_error("colour:red").0);
+        assert_eq!(7, parse_error("status:pending").0);
+        assert_eq!(6, parse_error("status~done").0);
+        assert_eq!(9, parse_error("(tag:work").0);
+        assert_eq!(8, parse_error("tag:work)").0);
+        assert_eq!(4, parse_error("due<").0);
+        assert_eq!(11, parse_error("tag:work or").0);
+        assert_eq!(0, parse_error("and a").0);
+        assert_eq!(5, parse_error("text~\"report").0);
+        assert_eq!(4, parse_error("due<someday").0);
+        assert!(parse_error("id:x").1.contains("not a task number"));
+    }
+
+    #[test]
+    fn sql_should_be_parameterized() {
+        let (sql, params) = parse("tag:work or not text~\"50%\"").to_sql();
+
+        assert_eq!(
+            "(EXISTS (SELECT 1 FROM TaskTags JOIN Tags ON Tags.id = TaskTags.tag_id WHERE TaskTags.task_id = Tasks.id AND Tags.name = ?) OR NOT COALESCE(text LIKE ? ESCAPE '\\', 0))",
+            sql
+        );
+        assert_eq!(
+            vec![
+                rusqlite::types::Value::Text(String::from("work")),
+                rusqlite::types::Value::Text(String::from("%50\\%%")),
+            ],
+            params
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use crate::due::{end_of_day_time, parse_weekday};
+use crate::error::Error;
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime, Weekday};
+use rusqlite::types::FromSql;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// How a recurring task repeats. Completing it creates the next occurrence,
+/// due on the first matching day after both the current due date and the day
+/// it was completed on.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Recurrence {
+    /// Every day, written `daily`.
+    Daily,
+    /// On the given weekdays, written `weekly:mon,thu`. Without weekdays, on
+    /// the weekday of the due date.
+    Weekly(Vec<Weekday>),
+    /// On the given day of every month, written `monthly:15`, or on the last
+    /// day of shorter months. Without a day, on the day of the due date.
+    Monthly(Option<u32>),
+    /// The given number of days after the task was completed, written
+    /// `after:3d`.
+    AfterDone(u32),
+}
+
+impl Display for Recurrence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recurrence::Daily => write!(f, "daily"),
+            Recurrence::Weekly(weekdays) if weekdays.is_empty() => write!(f, "weekly"),
+            Recurrence::Weekly(weekdays) => {
+                let weekdays: Vec<String> = weekdays
+                    .iter()
+                    .map(|weekday| weekday.to_string().to_lowercase())
+                    .collect();
+                write!(f, "weekly:{}", weekdays.join(","))
+            }
+            Recurrence::Monthly(None) => write!(f, "monthly"),
+            Recurrence::Monthly(Some(day)) => write!(f, "monthly:{}", day),
+            Recurrence::AfterDone(days) => write!(f, "after:{}d", days),
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim().to_lowercase();
+        let invalid = || {
+            Error::InvalidInput(format!(
+                "invalid recurrence '{}', expected daily, weekly:mon,thu, monthly:15 or after:3d",
+                s
+            ))
+        };
+
+        let (kind, argument) = match input.split_once(':') {
+            Some((kind, argument)) => (kind, Some(argument)),
+            None => (input.as_str(), None),
+        };
+
+        let recurrence = match (kind, argument) {
+            ("daily", None) => Recurrence::Daily,
+            ("weekly", None) => Recurrence::Weekly(vec![]),
+            ("weekly", Some(weekdays)) => {
+                let mut weekdays = weekdays
+                    .split(',')
+                    .map(|weekday| parse_weekday(weekday.trim()).ok_or_else(invalid))
+                    .collect::<Result<Vec<_>, _>>()?;
+                weekdays.sort_by_key(|weekday| weekday.num_days_from_monday());
+                weekdays.dedup();
+                Recurrence::Weekly(weekdays)
+            }
+            ("monthly", None) => Recurrence::Monthly(None),
+            ("monthly", Some(day)) => match day.parse() {
+                Ok(day @ 1..=31) => Recurrence::Monthly(Some(day)),
+                _ => return Err(invalid()),
+            },
+            ("after", Some(days)) => match days.strip_suffix('d').unwrap_or(days).parse() {
+                Ok(days @ 1..) => Recurrence::AfterDone(days),
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        };
+
+        Ok(recurrence)
+    }
+}
+
+impl FromSql for Recurrence {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e: Error| rusqlite::types::FromSqlError::Other(Box::new(e)))
+    }
+}
+
+impl Recurrence {
+    /// The due date of the occurrence following one due on `due` and
+    /// completed at `done_at`. It keeps the time of `due`, or is due at the end
+    /// of the day.
+    pub fn next_due(&self, due: Option<NaiveDateTime>, done_at: NaiveDateTime) -> NaiveDateTime {
+        let time = due.map_or_else(end_of_day_time, |due| due.time());
+        let anchor = due.map_or(done_at.date(), |due| due.date());
+        // The next occurrence comes after the current one, and after today
+        // for tasks completed late
+        let after = anchor.max(done_at.date());
+
+        let date = match self {
+            Recurrence::Daily => after + Duration::days(1),
+            Recurrence::Weekly(weekdays) => {
+                let weekdays = match weekdays.is_empty() {
+                    true => vec![anchor.weekday()],
+                    false => weekdays.clone(),
+                };
+                (1..=7)
+                    .map(|days| after + Duration::days(days))
+                    .find(|date| weekdays.contains(&date.weekday()))
+                    .unwrap_or(after + Duration::days(7))
+            }
+            Recurrence::Monthly(day) => {
+                let day = day.unwrap_or(anchor.day());
+                let this_month = day_of_month(after, day);
+                match this_month > after {
+                    true => this_month,
+                    false => day_of_month(after.with_day(1).unwrap() + Months::new(1), day),
+                }
+            }
+            Recurrence::AfterDone(days) => done_at.date() + Duration::days(*days as i64),
+        };
+
+        date.and_time(time)
+    }
+
+    /// Fixes the weekday or day of the month taken from the due date, so the
+    /// rule doesn't drift when a month is shorter.
+    pub(crate) fn anchored(&self, due: Option<NaiveDateTime>) -> Recurrence {
+        match (self, due) {
+            (Recurrence::Weekly(weekdays), Some(due)) if weekdays.is_empty() => {
+                Recurrence::Weekly(vec![due.weekday()])
+            }
+            (Recurrence::Monthly(None), Some(due)) => Recurrence::Monthly(Some(due.day())),
+            _ => self.clone(),
+        }
+    }
+}
+
+/// The given day in the month of `date`, or the last day of a shorter month.
+fn day_of_month(date: NaiveDate, day: u32) -> NaiveDate {
+    (0..4)
+        .find_map(|shorter| date.with_day(day - shorter))
+        .unwrap_or(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Recurrence;
+    use chrono::{NaiveDate, NaiveDateTime, Weekday};
+
+    fn at(day: &str, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{} {}", day, time), "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn next(rule: &str, due: Option<&str>, done: &str) -> NaiveDate {
+        let recurrence: Recurrence = rule.parse().unwrap();
+        recurrence
+            .next_due(due.map(|due| at(due, "23:59:59")), at(done, "12:00:00"))
+            .date()
+    }
+
+    fn date(day: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn rules_should_be_parsed_and_written() {
+        assert_eq!(
+            Recurrence::Weekly(vec![Weekday::Mon, Weekday::Thu]),
+            "Weekly:thu,monday,mon".parse().unwrap()
+        );
+        for rule in [
+            "daily",
+            "weekly",
+            "weekly:mon,thu",
+            "monthly",
+            "monthly:15",
+            "after:3d",
+        ] {
+            assert_eq!(rule, rule.parse::<Recurrence>().unwrap().to_string());
+        }
+        for rule in [
+            "hourly",
+            "weekly:someday",
+            "monthly:32",
+            "after:0d",
+            "daily:2",
+        ] {
+            assert!(rule.parse::<Recurrence>().is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn next_due_should_roll_forward() {
+        // 2023-08-16 is a Wednesday
+        assert_eq!(
+            date("2023-08-17"),
+            next("daily", Some("2023-08-16"), "2023-08-16")
+        );
+        assert_eq!(date("2023-08-17"), next("daily", None, "2023-08-16"));
+        assert_eq!(
+            date("2023-08-17"),
+            next("weekly:mon,thu", Some("2023-08-16"), "2023-08-16")
+        );
+        assert_eq!(
+            date("2023-08-21"),
+            next("weekly:mon,thu", Some("2023-08-17"), "2023-08-16")
+        );
+        assert_eq!(
+            date("2023-08-23"),
+            next("weekly", Some("2023-08-16"), "2023-08-16")
+        );
+        assert_eq!(
+            date("2023-09-15"),
+            next("monthly:15", Some("2023-08-15"), "2023-08-16")
+        );
+        assert_eq!(
+            date("2023-09-30"),
+            next("monthly", Some("2023-08-31"), "2023-08-31")
+        );
+        assert_eq!(
+            date("2023-08-19"),
+            next("after:3d", Some("2023-08-10"), "2023-08-16")
+        );
+    }
+
+    #[test]
+    fn late_completion_should_skip_missed_occurrences() {
+        assert_eq!(
+            date("2023-08-17"),
+            next("daily", Some("2023-08-10"), "2023-08-16")
+        );
+        assert_eq!(
+            date("2023-08-17"),
+            next("weekly:mon,thu", Some("2023-08-07"), "2023-08-16")
+        );
+    }
+}
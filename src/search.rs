@@ -0,0 +1,130 @@
+//! Full-text search results, see [`TaskStore::search`](crate::TaskStore::search).
+
+use crate::task::Task;
+use std::ops::Range;
+
+/// Marks the start of a match in snippets returned by SQLite.
+pub(crate) const MATCH_START: char = '\u{1}';
+/// Marks the end of a match in snippets returned by SQLite.
+pub(crate) const MATCH_END: char = '\u{2}';
+
+/// Turns plain words into a full-text query for tasks containing all of them,
+/// see [`TaskStore::search`](crate::TaskStore::search). Punctuation like in
+/// `e-mail` or `c++` is taken literally, a trailing `*` still matches a
+/// prefix.
+pub fn quote_words(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(stem) if !stem.is_empty() => (stem, "*"),
+                _ => (word, ""),
+            };
+            format!("\"{}\"{}", word.replace('"', "\"\""), prefix)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A task found by a full-text search.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SearchMatch {
+    pub task: Task,
+    /// The part of the task text around the matches, shortened with `…`.
+    pub snippet: String,
+    /// Byte ranges of the matched words in `snippet`.
+    pub highlights: Vec<Range<usize>>,
+    /// How well the task matches, higher is better.
+    pub score: f64,
+}
+
+impl SearchMatch {
+    /// Builds a match from a snippet with its matches between [`MATCH_START`]
+    /// and [`MATCH_END`].
+    pub(crate) fn new(task: Task, marked_snippet: &str, score: f64) -> Self {
+        let mut snippet = String::with_capacity(marked_snippet.len());
+        let mut highlights = Vec::new();
+        let mut start = None;
+        for c in marked_snippet.chars() {
+            match c {
+                MATCH_START => start = Some(snippet.len()),
+                MATCH_END => {
+                    if let Some(start) = start.take() {
+                        highlights.push(start..snippet.len());
+                    }
+                }
+                _ => snippet.push(c),
+            }
+        }
+
+        SearchMatch {
+            task,
+            snippet,
+            highlights,
+            score,
+        }
+    }
+
+    /// Splits the snippet into consecutive parts, each flagged with whether it
+    /// is a match.
+    pub fn parts(&self) -> Vec<(&str, bool)> {
+        let mut parts = Vec::new();
+        let mut position = 0;
+        for highlight in &self.highlights {
+            if position < highlight.start {
+                parts.push((&self.snippet[position..highlight.start], false));
+            }
+            parts.push((&self.snippet[highlight.clone()], true));
+            position = highlight.end;
+        }
+        if position < self.snippet.len() {
+            parts.push((&self.snippet[position..], false));
+        }
+        parts
+    }
+
+    /// The snippet with every match wrapped in `start` and `end`.
+    pub fn marked(&self, start: &str, end: &str) -> String {
+        self.parts()
+            .into_iter()
+            .map(|(part, is_match)| match is_match {
+                true => format!("{}{}{}", start, part, end),
+                false => part.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote_words, SearchMatch};
+    use crate::task::Task;
+
+    #[test]
+    fn words_should_be_quoted() {
+        assert_eq!(r#""Bob's" "c++""#, quote_words("Bob's  c++"));
+        assert_eq!(r#""say" """hi""" "rep"*"#, quote_words(r#"say "hi" rep*"#));
+        assert_eq!(r#""*""#, quote_words("*"));
+    }
+
+    #[test]
+    fn markers_should_become_highlights() {
+        let found = SearchMatch::new(
+            Task::default(),
+            "…the \u{1}weekly\u{2} \u{1}report\u{2}",
+            1.0,
+        );
+
+        assert_eq!("…the weekly report", found.snippet);
+        assert_eq!(vec![7..13, 14..20], found.highlights);
+        assert_eq!(
+            vec![
+                ("…the ", false),
+                ("weekly", true),
+                (" ", false),
+                ("report", true)
+            ],
+            found.parts()
+        );
+        assert_eq!("…the [weekly] [report]", found.marked("[", "]"));
+    }
+}
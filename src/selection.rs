@@ -0,0 +1,291 @@
+use chrono::{Duration, NaiveDateTime};
+use clap::Args;
+use list_rs::query::{Expr, Field, Op, Value};
+use list_rs::task::parse_uuid_prefix;
+use list_rs::{Result, TaskStatus, TaskStore};
+
+/// The tasks a bulk command applies to: task numbers, ranges of them and
+/// uuids, optionally narrowed by filters. Filters alone select from every task.
+#[derive(Args, Debug, Clone, Default)]
+pub struct Selection {
+    /// Numbers of pending tasks or ranges of them, e.g. 3 5 7-12, or uuids of
+    /// any task or their first characters
+    #[arg(value_parser = parse_id_range, required_unless_present_any = ["status", "older_than", "query"])]
+    pub ids: Vec<IdRange>,
+    /// Only tasks with this status: done, undone or archived
+    #[arg(long, value_parser = parse_status_arg)]
+    pub status: Option<TaskStatus>,
+    /// Only tasks created longer ago than this, e.g. 30d, 2w or 12h
+    #[arg(long, value_parser = parse_age)]
+    pub older_than: Option<Duration>,
+    /// Only tasks matching this query, e.g. "tag:work and due<friday"
+    #[arg(long = "where")]
+    pub query: Option<String>,
+}
+
+/// A single task number (`3`), an inclusive range of them (`7-12`), or a task
+/// uuid or its first characters (`3f2a9c`).
+#[derive(Debug, PartialEq, Clone)]
+pub enum IdRange {
+    Numbers { start: i32, end: i32 },
+    Uuid(String),
+}
+
+impl IdRange {
+    /// The query selecting the tasks in the range. Single tasks must exist,
+    /// ranges only select the tasks that exist within them.
+    fn to_query(&self, store: &TaskStore) -> Result<Expr> {
+        match self {
+            IdRange::Numbers { start, end } if start == end => {
+                store.find_task(&start.to_string())?;
+                Ok(Expr::condition(Field::Id, Op::Is, Value::Id(*start)))
+            }
+            IdRange::Numbers { start, end } => {
+                Ok(
+                    Expr::condition(Field::Id, Op::GreaterOrEqual, Value::Id(*start))
+                        .and(Expr::condition(Field::Id, Op::LessOrEqual, Value::Id(*end))),
+                )
+            }
+            IdRange::Uuid(uuid) => {
+                let task = store.find_task(uuid)?;
+                Ok(Expr::condition(Field::Uuid, Op::Is, Value::Text(task.uuid)))
+            }
+        }
+    }
+}
+
+impl Selection {
+    /// Returns the ids of the selected tasks, in ascending order.
+    pub fn resolve(&self, store: &TaskStore, now: NaiveDateTime) -> Result<Vec<i32>> {
+        let ids = self
+            .ids
+            .iter()
+            .map(|range| range.to_query(store))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .reduce(Expr::or);
+        let status = self
+            .status
+            .map(|status| Expr::condition(Field::Status, Op::Is, Value::Status(status)));
+        let older_than = self
+            .older_than
+            .map(|age| Expr::condition(Field::Created, Op::Less, Value::Date(now - age)));
+        let query = self
+            .query
+            .as_deref()
+            .map(|query| Expr::parse(query, now))
+            .transpose()?;
+
+        let mut ids: Vec<i32> = match [ids, status, older_than, query]
+            .into_iter()
+            .flatten()
+            .reduce(Expr::and)
+        {
+            Some(expr) => store.query(&expr)?,
+            None => store.read_tasks()?,
+        }
+        .iter()
+        .map(|task| task.id)
+        .collect();
+        ids.sort();
+
+        Ok(ids)
+    }
+}
+
+fn parse_id_range(value: &str) -> std::result::Result<IdRange, String> {
+    let parse_number = |number: &str| number.trim().parse::<i32>().ok();
+
+    let (start, end) = match value.split_once('-') {
+        Some((start, end)) => (parse_number(start), parse_number(end)),
+        None => (parse_number(value), parse_number(value)),
+    };
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) => (start, end),
+        _ => {
+            return parse_uuid_prefix(value.trim())
+                .map(IdRange::Uuid)
+                .ok_or_else(|| {
+                    format!(
+                        "'{}' is not a task number, a range like 7-12 or a uuid",
+                        value
+                    )
+                })
+        }
+    };
+
+    if start > end {
+        return Err(format!("range '{}' ends before it starts", value));
+    }
+
+    Ok(IdRange::Numbers { start, end })
+}
+
+fn parse_status_arg(value: &str) -> std::result::Result<TaskStatus, String> {
+    match value.to_lowercase().as_str() {
+        "done" => Ok(TaskStatus::Done),
+        "undone" => Ok(TaskStatus::Undone),
+        "archived" => Ok(TaskStatus::Archived),
+        _ => Err(format!(
+            "invalid status '{}', expected done, undone or archived",
+            value
+        )),
+    }
+}
+
+/// Parses an age such as `30d`, `2w` or `12h`.
+fn parse_age(value: &str) -> std::result::Result<Duration, String> {
+    let invalid = || format!("invalid age '{}', expected e.g. 30d, 2w or 12h", value);
+
+    let value = value.trim();
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let amount: i64 = value[..unit_start].parse().map_err(|_| invalid())?;
+
+    match &value[unit_start..] {
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        "w" => Ok(Duration::weeks(amount)),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_age, parse_id_range, IdRange, Selection};
+    use chrono::{Duration, NaiveDate, NaiveDateTime};
+    use list_rs::{Error, Task, TaskStatus, TaskStore};
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 9, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    /// Tasks 1, 3 and 5 are pending with the numbers 1, 2 and 3.
+    fn store() -> TaskStore {
+        let statuses = [
+            TaskStatus::Undone,
+            TaskStatus::Done,
+            TaskStatus::Undone,
+            TaskStatus::Archived,
+            TaskStatus::Undone,
+        ];
+        let store = TaskStore::open_in_memory().unwrap();
+        statuses
+            .iter()
+            .enumerate()
+            .map(|(index, &status)| {
+                let id = index as i32 + 1;
+                let created_at = now() - Duration::days(10 * id as i64);
+                Task {
+                    // Starts with a letter, so its prefix is never a number
+                    uuid: format!("a{}000000-0000-4000-8000-000000000000", id),
+                    ..Task::new_with_created_at(id, "Task", status, vec![], None, created_at)
+                }
+            })
+            .for_each(|task| {
+                store.create_task(task).unwrap();
+            });
+        store
+    }
+
+    #[test]
+    fn ids_and_ranges_should_be_parsed() {
+        assert_eq!(
+            IdRange::Numbers { start: 3, end: 3 },
+            parse_id_range("3").unwrap()
+        );
+        assert_eq!(
+            IdRange::Numbers { start: 7, end: 12 },
+            parse_id_range("7-12").unwrap()
+        );
+        assert_eq!(
+            IdRange::Uuid(String::from("3f2a9c1e-4b")),
+            parse_id_range("3F2A9C1E-4b").unwrap()
+        );
+        assert!(parse_id_range("12-7").is_err());
+        assert!(parse_id_range("a-b").is_err());
+        assert!(parse_id_range("-").is_err());
+    }
+
+    #[test]
+    fn ages_should_be_parsed() {
+        assert_eq!(Duration::days(30), parse_age("30d").unwrap());
+        assert_eq!(Duration::weeks(2), parse_age("2w").unwrap());
+        assert_eq!(Duration::hours(12), parse_age("12h").unwrap());
+        for value in ["30", "d", "30y", "-3d"] {
+            assert!(parse_age(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn ranges_should_skip_missing_tasks() {
+        let selection = Selection {
+            ids: vec![parse_id_range("3-9").unwrap(), parse_id_range("1").unwrap()],
+            ..Selection::default()
+        };
+
+        assert_eq!(vec![1, 5], selection.resolve(&store(), now()).unwrap());
+    }
+
+    #[test]
+    fn uuids_should_select_tasks_without_numbers() {
+        let store = store();
+        let archived = store.read_task(4).unwrap();
+        let selection = Selection {
+            ids: vec![
+                parse_id_range(&archived.uuid[..6]).unwrap(),
+                parse_id_range("2").unwrap(),
+            ],
+            ..Selection::default()
+        };
+
+        assert_eq!(vec![3, 4], selection.resolve(&store, now()).unwrap());
+    }
+
+    #[test]
+    fn missing_single_id_should_be_not_found() {
+        let selection = Selection {
+            ids: vec![parse_id_range("9").unwrap()],
+            ..Selection::default()
+        };
+
+        assert!(matches!(
+            selection.resolve(&store(), now()),
+            Err(Error::NotFound(9))
+        ));
+    }
+
+    #[test]
+    fn filters_should_narrow_the_selection() {
+        let selection = Selection {
+            ids: vec![],
+            status: Some(TaskStatus::Undone),
+            older_than: Some(Duration::days(25)),
+            query: None,
+        };
+        assert_eq!(vec![3, 5], selection.resolve(&store(), now()).unwrap());
+
+        let selection = Selection {
+            ids: vec![parse_id_range("1-2").unwrap()],
+            status: Some(TaskStatus::Undone),
+            older_than: None,
+            query: None,
+        };
+        assert_eq!(vec![1, 3], selection.resolve(&store(), now()).unwrap());
+    }
+
+    #[test]
+    fn queries_should_narrow_the_selection() {
+        let selection = Selection {
+            ids: vec![parse_id_range("1-3").unwrap()],
+            query: Some(String::from("not id:2")),
+            ..Selection::default()
+        };
+
+        assert_eq!(vec![1, 5], selection.resolve(&store(), now()).unwrap());
+    }
+}
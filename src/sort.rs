@@ -0,0 +1,201 @@
+use crate::error::Error;
+use crate::task::Task;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// A task field listings can be sorted by.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortField {
+    Priority,
+    Due,
+    Created,
+    Id,
+    Text,
+}
+
+/// One key of a sort order, e.g. `priority` or `-due` for descending.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+/// A comma separated list of sort keys such as `priority,-due`. Later keys
+/// break ties of the earlier ones.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SortOrder(pub Vec<SortKey>);
+
+impl FromStr for SortKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, descending) = match s.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (s, false),
+        };
+
+        let field = match name.to_lowercase().as_str() {
+            "priority" => SortField::Priority,
+            "due" => SortField::Due,
+            "created" => SortField::Created,
+            "id" => SortField::Id,
+            "text" => SortField::Text,
+            _ => {
+                return Err(Error::InvalidInput(format!(
+                    "invalid sort key '{}', expected priority, due, created, id or text",
+                    name
+                )))
+            }
+        };
+
+        Ok(SortKey { field, descending })
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = s
+            .split(',')
+            .map(SortKey::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SortOrder(keys))
+    }
+}
+
+impl Default for SortOrder {
+    /// Oldest tasks first.
+    fn default() -> Self {
+        SortOrder(vec![SortKey {
+            field: SortField::Created,
+            descending: false,
+        }])
+    }
+}
+
+impl SortKey {
+    fn compare(&self, a: &Task, b: &Task) -> Ordering {
+        let ordering = match self.field {
+            SortField::Priority => {
+                return compare_optional(a.priority, b.priority, self.descending)
+            }
+            SortField::Due => return compare_optional(a.due_date, b.due_date, self.descending),
+            SortField::Created => a.created_at.cmp(&b.created_at),
+            // By number, done and archived tasks have none and go last in a
+            // stable order
+            SortField::Id => {
+                return compare_optional(a.index, b.index, self.descending)
+                    .then_with(|| a.uuid.cmp(&b.uuid))
+            }
+            SortField::Text => a.text.to_lowercase().cmp(&b.text.to_lowercase()),
+        };
+
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// Compares optional values, keeping tasks without one last in both
+/// directions.
+fn compare_optional<T: Ord>(a: Option<T>, b: Option<T>, descending: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) if descending => b.cmp(&a),
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+impl SortOrder {
+    /// Sorts `tasks` by every key in turn, then by creation date and id so the
+    /// order is always the same. Priorities sort from high to low.
+    pub fn sort(&self, tasks: &mut [Task]) {
+        tasks.sort_by(|a, b| self.compare(a, b));
+    }
+
+    /// Compares two tasks the way [`SortOrder::sort`] orders them.
+    pub fn compare(&self, a: &Task, b: &Task) -> Ordering {
+        self.0
+            .iter()
+            .map(|key| key.compare(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SortOrder;
+    use crate::task::{Priority, Task, TaskStatus};
+    use chrono::NaiveDate;
+
+    fn tasks() -> Vec<Task> {
+        let due = |day| {
+            NaiveDate::from_ymd_opt(2023, 8, day)
+                .unwrap()
+                .and_hms_opt(23, 59, 59)
+        };
+        let task = |id, text, priority, due_date| Task {
+            priority,
+            uuid: format!("{}-uuid", id),
+            index: Some(id),
+            ..Task::new(id, text, TaskStatus::Undone, vec![], due_date)
+        };
+        vec![
+            task(1, "b", Some(Priority::Low), due(3)),
+            task(2, "C", None, due(1)),
+            task(3, "a", Some(Priority::High), None),
+            task(4, "d", Some(Priority::High), due(2)),
+        ]
+    }
+
+    fn sorted_ids(order: &str) -> Vec<i32> {
+        let mut tasks = tasks();
+        order.parse::<SortOrder>().unwrap().sort(&mut tasks);
+        tasks.iter().map(|task| task.id).collect()
+    }
+
+    #[test]
+    fn single_keys_should_sort() {
+        assert_eq!(vec![3, 4, 1, 2], sorted_ids("priority"));
+        assert_eq!(vec![2, 4, 1, 3], sorted_ids("due"));
+        assert_eq!(vec![1, 4, 2, 3], sorted_ids("-due"));
+        assert_eq!(vec![3, 1, 2, 4], sorted_ids("text"));
+        assert_eq!(vec![4, 3, 2, 1], sorted_ids("-id"));
+    }
+
+    #[test]
+    fn later_keys_should_break_ties() {
+        assert_eq!(vec![4, 3, 1, 2], sorted_ids("priority,due"));
+        assert_eq!(vec![3, 4, 1, 2], sorted_ids("priority,text"));
+    }
+
+    #[test]
+    fn tasks_without_a_number_should_come_last() {
+        let mut tasks = tasks();
+        // Tasks 1 and 3 have no number, like done tasks
+        for task in &mut tasks {
+            task.index = [None, Some(2), None, Some(1)][task.id as usize - 1];
+        }
+        let sorted = |order: &str| {
+            let mut tasks = tasks.clone();
+            order.parse::<SortOrder>().unwrap().sort(&mut tasks);
+            tasks.iter().map(|task| task.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec![4, 2, 1, 3], sorted("id"));
+        assert_eq!(vec![2, 4, 1, 3], sorted("-id"));
+    }
+
+    #[test]
+    fn invalid_keys_should_be_rejected() {
+        assert!("priority,size".parse::<SortOrder>().is_err());
+        assert!("".parse::<SortOrder>().is_err());
+    }
+}
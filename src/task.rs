@@ -1,7 +1,12 @@
-use crate::FromSql;
+use crate::due::{describe_due, DueState, DEFAULT_DATE_FORMAT};
+use crate::error::Error;
+use crate::recurrence::Recurrence;
 use chrono::NaiveDateTime;
 use colored::*;
+use rusqlite::types::FromSql;
+use std::ops::Range;
 use std::{fmt::Display, str::FromStr};
+use uuid::Uuid;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TaskStatus {
@@ -21,52 +26,204 @@ impl Display for TaskStatus {
 }
 
 impl FromStr for TaskStatus {
-    type Err = ();
+    type Err = Error;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s {
             "Archived" => Ok(TaskStatus::Archived),
             "Done" => Ok(TaskStatus::Done),
             "Undone" => Ok(TaskStatus::Undone),
-            _ => panic!("Invalid task status"),
+            _ => Err(Error::InvalidInput(format!("invalid task status '{}'", s))),
         }
     }
 }
 
 impl FromSql for TaskStatus {
     fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
-        match value.as_str()? {
-            "Done" => Ok(TaskStatus::Done),
-            "Undone" => Ok(TaskStatus::Undone),
-            "Archived" => Ok(TaskStatus::Archived),
-            _ => Err(rusqlite::types::FromSqlError::InvalidType),
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| rusqlite::types::FromSqlError::Other(Box::new(e)))
+    }
+}
+
+/// How important a task is. Ordered from the most to the least important.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Priority::High => write!(f, "High"),
+            Priority::Medium => write!(f, "Medium"),
+            Priority::Low => write!(f, "Low"),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = Error;
+
+    /// Accepts the full names and their first letter, in any case.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "high" | "h" => Ok(Priority::High),
+            "medium" | "m" => Ok(Priority::Medium),
+            "low" | "l" => Ok(Priority::Low),
+            _ => Err(Error::InvalidInput(format!(
+                "invalid priority '{}', expected high, medium or low",
+                s
+            ))),
         }
     }
 }
 
+impl FromSql for Priority {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| rusqlite::types::FromSqlError::Other(Box::new(e)))
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Task {
     pub id: i32,
+    /// Identifies the task for good, across undo and redo and between
+    /// databases.
+    pub uuid: String,
+    /// The short number the task is shown with while it's pending, see
+    /// [`Task::label`].
+    pub index: Option<i32>,
     pub text: String,
     pub status: TaskStatus,
-    pub tag: Option<String>,
-    pub due_date: Option<String>,
+    pub tags: Vec<String>,
+    pub due_date: Option<NaiveDateTime>,
+    pub priority: Option<Priority>,
+    pub recurrence: Option<Recurrence>,
+    /// The task this one is a subtask of.
+    pub parent_id: Option<i32>,
+    /// The tasks that have to be done before this one, sorted by id.
+    pub depends_on: Vec<i32>,
+    /// The project the task belongs to.
+    pub project_id: Option<i32>,
     pub created_at: NaiveDateTime,
 }
 
+/// Displays the task in the default style, see [`Task::display`].
 impl Display for Task {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let checkbox = match self.status {
-            TaskStatus::Done => "✅",
-            TaskStatus::Archived => "📦",
-            _ => "⌛",
-        };
-        let id = format!("{}", self.id).bold();
-        let text = match self.status {
-            TaskStatus::Done => self.text.strikethrough().to_string(),
-            _ => self.text.clone(),
-        };
-        write!(f, "{}) {} {}", id, checkbox, text.color("Blue"))
+        self.write(f, &DisplayStyle::default(), &[])
+    }
+}
+
+/// A task displayed in a given style with parts of its text highlighted, see
+/// [`Task::display`] and [`Task::highlighted`].
+pub struct Styled<'a> {
+    task: &'a Task,
+    style: &'a DisplayStyle,
+    ranges: &'a [Range<usize>],
+}
+
+impl Display for Styled<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.task.write(f, self.style, self.ranges)
+    }
+}
+
+impl Task {
+    /// Displays the task with the markers and date format of `style`.
+    pub fn display<'a>(&'a self, style: &'a DisplayStyle) -> Styled<'a> {
+        self.highlighted(style, &[])
+    }
+
+    /// Displays the task like [`Task::display`] with the given byte ranges of
+    /// its text highlighted, e.g. the characters matched by a search.
+    pub fn highlighted<'a>(
+        &'a self,
+        style: &'a DisplayStyle,
+        ranges: &'a [Range<usize>],
+    ) -> Styled<'a> {
+        Styled {
+            task: self,
+            style,
+            ranges,
+        }
+    }
+
+    fn write(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        style: &DisplayStyle,
+        highlights: &[Range<usize>],
+    ) -> std::fmt::Result {
+        let checkbox = style.markers.marker(self.status);
+        let id = self.label().bold();
+        write!(f, "{}) {} ", id, checkbox)?;
+        if let Some(priority) = self.priority {
+            let marker = format!("({})", &priority.to_string()[..1]);
+            let marker = match (self.status, priority) {
+                (TaskStatus::Undone, Priority::High) => marker.red().bold(),
+                (TaskStatus::Undone, Priority::Medium) => marker.yellow(),
+                (TaskStatus::Undone, Priority::Low) => marker.cyan(),
+                _ => marker.dimmed(),
+            };
+            write!(f, "{} ", marker)?;
+        }
+        let mut position = 0;
+        let mut parts = Vec::new();
+        for range in highlights {
+            parts.push((&self.text[position..range.start], false));
+            parts.push((&self.text[range.clone()], true));
+            position = range.end;
+        }
+        parts.push((&self.text[position..], false));
+        for (part, is_highlighted) in parts {
+            let part = match is_highlighted {
+                true => part.yellow().bold().underline(),
+                false => part.color("Blue"),
+            };
+            match self.status {
+                TaskStatus::Done => write!(f, "{}", part.strikethrough())?,
+                _ => write!(f, "{}", part)?,
+            }
+        }
+        for tag in &self.tags {
+            write!(f, " {}", format!("+{}", tag).color("Magenta"))?;
+        }
+        if let Some(due_date) = self.due_date {
+            let (description, state) = describe_due(
+                due_date,
+                chrono::Local::now().naive_local(),
+                &style.date_format,
+            );
+            let description = format!("({})", description);
+            let description = match (self.status, state) {
+                (TaskStatus::Undone, DueState::Overdue) => description.red().bold(),
+                (TaskStatus::Undone, DueState::Today) => description.yellow(),
+                (TaskStatus::Undone, DueState::Upcoming) => description.green(),
+                _ => description.dimmed(),
+            };
+            write!(f, " {}", description)?;
+        }
+        if let Some(recurrence) = &self.recurrence {
+            write!(f, " {}", format!("↻ {}", recurrence).dimmed())?;
+        }
+        Ok(())
+    }
+
+    /// How the task is shown and referred to on the command line: its number
+    /// while it's pending, or else the first 8 characters of its uuid.
+    pub fn label(&self) -> String {
+        match self.index {
+            Some(index) => index.to_string(),
+            None => self.uuid.chars().take(8).collect(),
+        }
     }
 }
 
@@ -74,10 +231,17 @@ impl Default for Task {
     fn default() -> Self {
         Self {
             id: 1,
+            uuid: Default::default(),
+            index: Default::default(),
             text: Default::default(),
             status: TaskStatus::Undone,
-            tag: Default::default(),
+            tags: Default::default(),
             due_date: Default::default(),
+            priority: Default::default(),
+            recurrence: Default::default(),
+            parent_id: Default::default(),
+            depends_on: Default::default(),
+            project_id: Default::default(),
             created_at: Default::default(),
         }
     }
@@ -88,15 +252,22 @@ impl Task {
         id: i32,
         text: &str,
         status: TaskStatus,
-        tag: Option<String>,
-        due_date: Option<String>,
+        tags: Vec<String>,
+        due_date: Option<NaiveDateTime>,
     ) -> Self {
         Task {
             id,
+            uuid: Uuid::new_v4().to_string(),
+            index: None,
             text: text.to_string(),
             status,
-            tag,
+            tags,
             due_date,
+            priority: None,
+            recurrence: None,
+            parent_id: None,
+            depends_on: Vec::new(),
+            project_id: None,
             created_at: chrono::Local::now().naive_local(),
         }
     }
@@ -104,17 +275,208 @@ impl Task {
         id: i32,
         text: &str,
         status: TaskStatus,
-        tag: Option<String>,
-        due_date: Option<String>,
+        tags: Vec<String>,
+        due_date: Option<NaiveDateTime>,
         created_at: NaiveDateTime,
     ) -> Self {
         Task {
             id,
+            uuid: Uuid::new_v4().to_string(),
+            index: None,
             text: text.to_string(),
             status,
-            tag,
+            tags,
             due_date,
+            priority: None,
+            recurrence: None,
+            parent_id: None,
+            depends_on: Vec::new(),
+            project_id: None,
             created_at,
         }
     }
 }
+
+/// The markers showing the status of a task.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Markers {
+    #[default]
+    Emoji,
+    Ascii,
+}
+
+impl Markers {
+    /// The marker of a task with the given status.
+    pub fn marker(self, status: TaskStatus) -> &'static str {
+        match (self, status) {
+            (Markers::Emoji, TaskStatus::Done) => "✅",
+            (Markers::Emoji, TaskStatus::Archived) => "📦",
+            (Markers::Emoji, TaskStatus::Undone) => "⌛",
+            (Markers::Ascii, TaskStatus::Done) => "[x]",
+            (Markers::Ascii, TaskStatus::Archived) => "[a]",
+            (Markers::Ascii, TaskStatus::Undone) => "[ ]",
+        }
+    }
+}
+
+impl Display for Markers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Markers::Emoji => write!(f, "emoji"),
+            Markers::Ascii => write!(f, "ascii"),
+        }
+    }
+}
+
+impl FromStr for Markers {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "emoji" => Ok(Markers::Emoji),
+            "ascii" => Ok(Markers::Ascii),
+            _ => Err(Error::InvalidInput(format!(
+                "invalid markers '{}', expected emoji or ascii",
+                s
+            ))),
+        }
+    }
+}
+
+/// How [`Task::display`] writes status markers and due dates.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DisplayStyle {
+    pub markers: Markers,
+    /// strftime format of due dates more than a week away.
+    pub date_format: String,
+}
+
+impl Default for DisplayStyle {
+    fn default() -> Self {
+        Self {
+            markers: Markers::default(),
+            date_format: DEFAULT_DATE_FORMAT.to_string(),
+        }
+    }
+}
+
+/// Checks that `value` is a uuid or its first characters, e.g. `3f2a9c1e-4b`,
+/// and returns it in lowercase.
+pub fn parse_uuid_prefix(value: &str) -> Option<String> {
+    const PATTERN: &str = "xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx";
+
+    let is_prefix = !value.is_empty()
+        && value.len() <= PATTERN.len()
+        && value
+            .chars()
+            .zip(PATTERN.chars())
+            .all(|(c, pattern)| match pattern {
+                '-' => c == '-',
+                _ => c.is_ascii_hexdigit(),
+            });
+    is_prefix.then(|| value.to_lowercase())
+}
+
+/// Validates a tag name given on the command line, accepting an optional
+/// leading `+`. Tags are stored comma separated in the history, so they can't
+/// contain commas or whitespace.
+pub fn parse_tag(tag: &str) -> Option<String> {
+    let tag = tag.strip_prefix('+').unwrap_or(tag);
+    if tag.is_empty() || tag.contains(|c: char| c == ',' || c.is_whitespace()) {
+        None
+    } else {
+        Some(tag.to_string())
+    }
+}
+
+/// Splits the words given to `add` into the task text and the `+tag` words.
+pub fn split_text_and_tags(words: &[String]) -> (String, Vec<String>) {
+    let mut text = Vec::new();
+    let mut tags = Vec::new();
+    for word in words {
+        match word.strip_prefix('+').and_then(parse_tag) {
+            Some(tag) if !tags.contains(&tag) => tags.push(tag),
+            Some(_) => {}
+            None => text.push(word.as_str()),
+        }
+    }
+    (text.join(" "), tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_tag, parse_uuid_prefix, split_text_and_tags, DisplayStyle, Markers, Task, TaskStatus,
+    };
+    use crate::error::Error;
+
+    #[test]
+    fn parse_tag_should_validate() {
+        assert_eq!(Some(String::from("work")), parse_tag("+work"));
+        assert_eq!(Some(String::from("work")), parse_tag("work"));
+        assert_eq!(None, parse_tag("+"));
+        assert_eq!(None, parse_tag("a,b"));
+        assert_eq!(None, parse_tag("a b"));
+    }
+
+    #[test]
+    fn uuid_prefixes_should_be_validated() {
+        assert_eq!(
+            Some(String::from("3f2a9c1e-4b")),
+            parse_uuid_prefix("3F2A9C1E-4b")
+        );
+        assert_eq!(
+            Some(String::from("0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a")),
+            parse_uuid_prefix("0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a")
+        );
+        assert_eq!(None, parse_uuid_prefix(""));
+        assert_eq!(None, parse_uuid_prefix("a-b"));
+        assert_eq!(None, parse_uuid_prefix("milk"));
+        assert_eq!(
+            None,
+            parse_uuid_prefix("0b5c7a2e-4f1d-4c3b-9a8e-2d6f1e0c9b7a0")
+        );
+    }
+
+    #[test]
+    fn split_text_and_tags_should_work() {
+        let words: Vec<String> = ["Write", "report", "+work", "+urgent", "+work", "c++"]
+            .iter()
+            .map(|word| word.to_string())
+            .collect();
+
+        let (text, tags) = split_text_and_tags(&words);
+
+        assert_eq!("Write report c++", text);
+        assert_eq!(vec!["work", "urgent"], tags);
+    }
+
+    #[test]
+    fn markers_should_be_parsed() {
+        assert_eq!(Markers::Ascii, "ASCII".parse().unwrap());
+        assert_eq!("[x]", Markers::Ascii.marker(TaskStatus::Done));
+        assert_eq!("⌛", Markers::Emoji.marker(TaskStatus::Undone));
+        assert!("unicode".parse::<Markers>().is_err());
+    }
+
+    #[test]
+    fn tasks_should_be_displayed_in_the_given_style() {
+        let task = Task::new(1, "Meditate", TaskStatus::Undone, vec![], None);
+        let ascii = DisplayStyle {
+            markers: Markers::Ascii,
+            ..DisplayStyle::default()
+        };
+
+        assert!(task.display(&ascii).to_string().contains("[ ]"));
+        assert!(task.to_string().contains("⌛"));
+    }
+
+    #[test]
+    fn invalid_status_should_be_an_error() {
+        assert_eq!(TaskStatus::Done, "Done".parse().unwrap());
+        assert!(matches!(
+            "Pending".parse::<TaskStatus>(),
+            Err(Error::InvalidInput(_))
+        ));
+    }
+}
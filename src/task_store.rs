@@ -0,0 +1,1738 @@
+use crate::error::{Error, Result};
+use crate::fuzzy::fuzzy_match;
+use crate::history::{
+    local_timestamp, parse_timestamp, to_timestamp, HistoryBranch, HistoryEntry, HistoryNames,
+    HistoryStep, Operation,
+};
+use crate::migrations;
+use crate::project::{parse_project_name, Project};
+use crate::query::Expr;
+use crate::search::{SearchMatch, MATCH_END, MATCH_START};
+use crate::task::{parse_uuid_prefix, Task, TaskStatus};
+use chrono::NaiveDateTime;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Every column of a task, with its tags and the ids of its dependencies
+/// joined by commas. The project is left out if it was removed.
+const TASK_COLUMNS: &str = "id, text, status, (SELECT GROUP_CONCAT(Tags.name) FROM TaskTags JOIN Tags ON Tags.id = TaskTags.tag_id WHERE TaskTags.task_id = Tasks.id), due_date, priority, created_at, recurrence, parent_id, (SELECT GROUP_CONCAT(depends_on) FROM TaskDependencies WHERE TaskDependencies.task_id = Tasks.id), (SELECT Projects.id FROM Projects WHERE Projects.id = Tasks.project_id), uuid, display_index";
+
+/// Every project with the number of its tasks in each status, to be completed
+/// with `HAVING` or `ORDER BY`.
+const PROJECT_QUERY: &str = "SELECT Projects.id, Projects.name, COUNT(CASE Tasks.status WHEN 'Undone' THEN 1 END), COUNT(CASE Tasks.status WHEN 'Done' THEN 1 END), COUNT(CASE Tasks.status WHEN 'Archived' THEN 1 END) FROM Projects LEFT JOIN Tasks ON Tasks.project_id = Projects.id GROUP BY Projects.id";
+
+/// The setting holding the id of the active project.
+const ACTIVE_PROJECT: &str = "active_project";
+
+/// How long a store waits for other processes to finish writing before
+/// giving up with `database is locked`.
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An open savepoint, rolled back when it is dropped without being released,
+/// e.g. when an error is returned or a closure panics. Outside of a
+/// transaction it starts one, which releasing commits.
+struct Savepoint<'a> {
+    conn: &'a Connection,
+    /// Whether the savepoint is a transaction of its own.
+    outermost: bool,
+    released: bool,
+}
+
+impl<'a> Savepoint<'a> {
+    fn new(conn: &'a Connection) -> rusqlite::Result<Self> {
+        let outermost = conn.is_autocommit();
+        if outermost {
+            // Take the write lock up front: a transaction that reads first
+            // can't wait for other writers once it wants to write
+            conn.execute_batch("BEGIN IMMEDIATE")?;
+        } else {
+            conn.execute_batch("SAVEPOINT unit_of_work")?;
+        }
+        Ok(Savepoint {
+            conn,
+            outermost,
+            released: false,
+        })
+    }
+
+    fn release(mut self) -> rusqlite::Result<()> {
+        if self.outermost {
+            self.conn.execute_batch("COMMIT")?;
+        } else {
+            self.conn.execute_batch("RELEASE unit_of_work")?;
+        }
+        self.released = true;
+        Ok(())
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if !self.released {
+            // Errors can't be reported from here, the transaction is gone if
+            // the rollback fails
+            let _ = if self.outermost {
+                self.conn.execute_batch("ROLLBACK")
+            } else {
+                self.conn
+                    .execute_batch("ROLLBACK TO unit_of_work; RELEASE unit_of_work")
+            };
+        }
+    }
+}
+
+/// The history batch a store records into while a batch runs. Dropping it
+/// restores the previous one, even when the batch panics.
+struct BatchGuard<'a> {
+    batch: &'a Cell<Option<i64>>,
+    previous: Option<i64>,
+}
+
+impl<'a> BatchGuard<'a> {
+    fn new(batch: &'a Cell<Option<i64>>, current: i64) -> Self {
+        BatchGuard {
+            batch,
+            previous: batch.replace(Some(current)),
+        }
+    }
+}
+
+impl Drop for BatchGuard<'_> {
+    fn drop(&mut self) {
+        self.batch.set(self.previous);
+    }
+}
+
+/// A task list stored in a SQLite database.
+///
+/// Every change made through the store is recorded in its history and can be
+/// reverted with [`TaskStore::undo`] and reapplied with [`TaskStore::redo`].
+pub struct TaskStore {
+    conn: Connection,
+    /// The history batch that changes are currently recorded into, if any.
+    batch: Cell<Option<i64>>,
+}
+
+impl TaskStore {
+    /// Opens the database at `database_path`, creating it if it doesn't exist
+    /// and upgrading it if it was written by an older version.
+    ///
+    /// The database can be used by several stores and processes at once,
+    /// changes wait up to [`DEFAULT_BUSY_TIMEOUT`] for each other.
+    pub fn open<P: AsRef<Path>>(database_path: P) -> Result<Self> {
+        TaskStore::open_with_timeout(database_path, DEFAULT_BUSY_TIMEOUT)
+    }
+
+    /// Opens the database at `database_path` like [`TaskStore::open`], waiting
+    /// up to `busy_timeout` for other writers instead of the default.
+    pub fn open_with_timeout<P: AsRef<Path>>(
+        database_path: P,
+        busy_timeout: Duration,
+    ) -> Result<Self> {
+        let conn = Connection::open(database_path)?;
+        // SQLite retries locked statements until the timeout is over
+        conn.busy_timeout(busy_timeout)?;
+        // Readers don't block writers in WAL mode, and it stays on for every
+        // later connection
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        TaskStore::from_connection(conn)
+    }
+
+    /// Opens a new database that only lives in memory.
+    pub fn open_in_memory() -> Result<Self> {
+        TaskStore::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        migrations::migrate(&conn)?;
+        Ok(TaskStore {
+            conn,
+            batch: Cell::new(None),
+        })
+    }
+
+    fn task_from_row(row: &Row) -> rusqlite::Result<Task> {
+        Ok(Task {
+            id: row.get(0)?,
+            text: row.get(1)?,
+            status: row.get(2)?,
+            tags: TaskStore::tags_from_column(row.get(3)?),
+            due_date: row.get(4)?,
+            priority: row.get(5)?,
+            created_at: row.get(6)?,
+            recurrence: row.get(7)?,
+            parent_id: row.get(8)?,
+            depends_on: TaskStore::ids_from_column(row.get(9)?),
+            project_id: row.get(10)?,
+            uuid: row.get(11)?,
+            index: row.get(12)?,
+        })
+    }
+
+    fn tags_from_column(tags: Option<String>) -> Vec<String> {
+        let mut tags: Vec<String> = tags
+            .iter()
+            .flat_map(|tags| tags.split(','))
+            .filter(|tag| !tag.is_empty())
+            .map(String::from)
+            .collect();
+        tags.sort();
+        tags
+    }
+
+    fn tags_to_column(tags: &[String]) -> Option<String> {
+        if tags.is_empty() {
+            None
+        } else {
+            Some(tags.join(","))
+        }
+    }
+
+    fn ids_from_column(ids: Option<String>) -> Vec<i32> {
+        let mut ids: Vec<i32> = ids
+            .iter()
+            .flat_map(|ids| ids.split(','))
+            .filter_map(|id| id.parse().ok())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn ids_to_column(ids: &[i32]) -> Option<String> {
+        if ids.is_empty() {
+            None
+        } else {
+            let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+            Some(ids.join(","))
+        }
+    }
+
+    /// Runs `f` as a unit of work and a single step of the history:
+    /// everything it changes is committed together and reverted by a single
+    /// `undo`. If `f` fails nothing is changed.
+    ///
+    /// Batches can be nested. A nested batch is part of the outer step, and
+    /// if it fails only its own changes are rolled back, so the outer one can
+    /// handle the error and carry on.
+    pub fn batch<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Self) -> Result<T>,
+    {
+        self.atomically(|| {
+            if self.batch.get().is_some() {
+                return f(self);
+            }
+
+            let _guard = BatchGuard::new(&self.batch, self.next_batch()?);
+            f(self)
+        })
+    }
+
+    /// Runs `f` in a transaction, or in a savepoint of the current one, and
+    /// rolls back everything it changed if it fails.
+    fn atomically<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let savepoint = Savepoint::new(&self.conn)?;
+        let value = f()?;
+        savepoint.release()?;
+        Ok(value)
+    }
+
+    /// Adds a task and returns its id. The id and number of `task` are
+    /// ignored, and it gets a new uuid unless it has a valid one that no other
+    /// task uses.
+    pub fn create_task(&self, task: Task) -> Result<i32> {
+        self.batch(|store| store.insert_new_task(task))
+    }
+
+    fn insert_new_task(&self, mut task: Task) -> Result<i32> {
+        if let Some(parent_id) = task.parent_id {
+            self.read_task(parent_id)?;
+        }
+        self.check_project(task.project_id)?;
+        task.depends_on.sort();
+        task.depends_on.dedup();
+        for &dependency in &task.depends_on {
+            self.read_task(dependency)?;
+        }
+        if Uuid::parse_str(&task.uuid).is_err() || self.is_uuid_taken(&task.uuid)? {
+            task.uuid = Uuid::new_v4().to_string();
+        }
+        task.id = 0;
+        task.index = None;
+        self.assign_index(&mut task)?;
+
+        // Execute create query
+        let _ = self.conn.execute(
+            "INSERT INTO Tasks (text, status, due_date, priority, created_at, recurrence, parent_id, project_id, uuid, display_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                task.text,
+                task.status.to_string(),
+                task.due_date,
+                task.priority.map(|priority| priority.to_string()),
+                task.created_at,
+                task.recurrence.as_ref().map(|recurrence| recurrence.to_string()),
+                task.parent_id,
+                task.project_id,
+                task.uuid,
+                task.index
+            ],
+        )?;
+
+        task.id = self.conn.last_insert_rowid() as i32;
+        self.write_tags(&task)?;
+        self.write_dependencies(&task)?;
+
+        self.push_to_undo_history(Operation::Create, task.id, None, Some(&task))?;
+
+        Ok(task.id)
+    }
+
+    /// Returns every task, in no particular order.
+    pub fn read_tasks(&self) -> Result<Vec<Task>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT {} FROM Tasks", TASK_COLUMNS))?;
+        let tasks = stmt
+            .query_map([], TaskStore::task_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(tasks)
+    }
+
+    /// Returns the tasks matching a filter expression, in no particular order.
+    pub fn query(&self, expr: &Expr) -> Result<Vec<Task>> {
+        let (condition, params) = expr.to_sql();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM Tasks WHERE {}",
+            TASK_COLUMNS, condition
+        ))?;
+        let tasks = stmt
+            .query_map(rusqlite::params_from_iter(params), TaskStore::task_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(tasks)
+    }
+
+    /// Finds the tasks whose text matches a full-text search, best matches
+    /// first. `terms` is an SQLite FTS5 query: words must all appear, `"a b"`
+    /// matches a phrase, `rep*` a prefix, and `OR`, `NOT` and parentheses
+    /// combine them. `filter` optionally narrows the tasks searched.
+    pub fn search(&self, terms: &str, filter: Option<&Expr>) -> Result<Vec<SearchMatch>> {
+        let (condition, filter_params) = match filter {
+            Some(filter) => filter.to_sql(),
+            None => (String::from("1"), vec![]),
+        };
+        let mut params = vec![rusqlite::types::Value::Text(terms.to_string())];
+        params.extend(filter_params);
+
+        // The search runs in a subquery so its columns don't clash with the
+        // ones of Tasks used by the filter.
+        let sql = format!(
+            "SELECT {}, snippet, score FROM Tasks
+            JOIN (
+                SELECT rowid AS match_id,
+                    snippet(TaskSearch, 0, '{}', '{}', '…', 16) AS snippet,
+                    -bm25(TaskSearch) AS score
+                FROM TaskSearch WHERE TaskSearch MATCH ?
+            ) ON match_id = Tasks.id
+            WHERE {}
+            ORDER BY score DESC, id",
+            TASK_COLUMNS, MATCH_START, MATCH_END, condition
+        );
+
+        let run = || -> rusqlite::Result<Vec<SearchMatch>> {
+            let mut stmt = self.conn.prepare(&sql)?;
+            let matches = stmt
+                .query_map(rusqlite::params_from_iter(params.iter()), |row| {
+                    let task = TaskStore::task_from_row(row)?;
+                    let snippet: String = row.get(13)?;
+                    Ok(SearchMatch::new(task, &snippet, row.get(14)?))
+                })?
+                .collect();
+            matches
+        };
+
+        run().map_err(|e| match e {
+            // Syntax errors in the search terms
+            rusqlite::Error::SqliteFailure(_, Some(message))
+                if message.starts_with("fts5:")
+                    || message.starts_with("no such column")
+                    || message == "unterminated string" =>
+            {
+                Error::InvalidInput(format!(
+                    "invalid search '{}': {}, put words with punctuation in double quotes",
+                    terms, message
+                ))
+            }
+            e => Error::Storage(e),
+        })
+    }
+
+    /// Finds the tasks whose text contains every word of `pattern`, allowing
+    /// for typos and left out letters, best matches first. The snippet of each
+    /// match is the whole task text. `filter` optionally narrows the tasks
+    /// searched.
+    pub fn fuzzy_search(&self, pattern: &str, filter: Option<&Expr>) -> Result<Vec<SearchMatch>> {
+        let tasks = match filter {
+            Some(filter) => self.query(filter)?,
+            None => self.read_tasks()?,
+        };
+
+        let mut matches: Vec<SearchMatch> = tasks
+            .into_iter()
+            .filter_map(|task| {
+                let (score, highlights) = fuzzy_match(pattern, &task.text)?;
+                Some(SearchMatch {
+                    snippet: task.text.clone(),
+                    task,
+                    highlights,
+                    score,
+                })
+            })
+            .collect();
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.task.id.cmp(&b.task.id)));
+
+        Ok(matches)
+    }
+
+    /// Returns the task with the given id, or [`Error::NotFound`] if there is
+    /// none.
+    pub fn read_task(&self, id: i32) -> Result<Task> {
+        self.conn
+            .query_row(
+                &format!("SELECT {} FROM Tasks WHERE id = ?1", TASK_COLUMNS),
+                [id],
+                TaskStore::task_from_row,
+            )
+            .optional()?
+            .ok_or(Error::NotFound(id))
+    }
+
+    /// Returns the task a user refers to: a pending task by its number, or
+    /// any task by its uuid or the first characters of it.
+    pub fn find_task(&self, reference: &str) -> Result<Task> {
+        let reference = reference.trim();
+        if let Ok(index) = reference.parse::<i32>() {
+            return self
+                .conn
+                .query_row(
+                    &format!(
+                        "SELECT {} FROM Tasks WHERE display_index = ?1",
+                        TASK_COLUMNS
+                    ),
+                    [index],
+                    TaskStore::task_from_row,
+                )
+                .optional()?
+                .ok_or(Error::NotFound(index));
+        }
+
+        let prefix = parse_uuid_prefix(reference).ok_or_else(|| {
+            Error::InvalidInput(format!("'{}' is not a task number or uuid", reference))
+        })?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM Tasks WHERE uuid LIKE ?1 || '%' LIMIT 2",
+            TASK_COLUMNS
+        ))?;
+        let mut tasks = stmt
+            .query_map([&prefix], TaskStore::task_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        match tasks.len() {
+            0 => Err(Error::InvalidInput(format!(
+                "No task has a uuid starting with '{}'",
+                reference
+            ))),
+            1 => Ok(tasks.remove(0)),
+            _ => Err(Error::InvalidInput(format!(
+                "Several tasks have a uuid starting with '{}', give more of it",
+                reference
+            ))),
+        }
+    }
+
+    fn is_uuid_taken(&self, uuid: &str) -> rusqlite::Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM Tasks WHERE uuid = ?1)",
+            [uuid],
+            |row| row.get(0),
+        )
+    }
+
+    /// Gives a pending task the number it asks for, unless another task
+    /// holds it, or else the lowest free one. Numbers of tasks that are done
+    /// or archived go back to be reused, so they stay small.
+    fn assign_index(&self, task: &mut Task) -> rusqlite::Result<()> {
+        if task.status != TaskStatus::Undone {
+            task.index = None;
+            return Ok(());
+        }
+        if let Some(index) = task.index {
+            let is_taken: bool = self.conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM Tasks WHERE display_index = ?1 AND id != ?2)",
+                [index, task.id],
+                |row| row.get(0),
+            )?;
+            if !is_taken {
+                return Ok(());
+            }
+        }
+
+        task.index = self.conn.query_row(
+            "WITH Taken (number) AS (SELECT display_index FROM Tasks WHERE display_index IS NOT NULL AND id != ?1)
+            SELECT MIN(number) FROM (SELECT 1 AS number UNION ALL SELECT number + 1 FROM Taken)
+            WHERE number NOT IN Taken",
+            [task.id],
+            |row| row.get(0),
+        )?;
+        Ok(())
+    }
+
+    /// Returns every tag in use together with the number of tasks carrying it,
+    /// sorted by name.
+    pub fn read_tags(&self) -> Result<Vec<(String, usize)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT Tags.name, COUNT(*) FROM Tags JOIN TaskTags ON TaskTags.tag_id = Tags.id GROUP BY Tags.id ORDER BY Tags.name",
+        )?;
+        let tags = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(tags)
+    }
+
+    /// Replaces the task with the given id by `new_task`, keeping the id, the
+    /// uuid and, while the task is pending, its number.
+    pub fn update_task(&self, id: i32, new_task: &Task) -> Result<()> {
+        self.batch(|store| store.replace_task(id, new_task))
+    }
+
+    fn replace_task(&self, id: i32, new_task: &Task) -> Result<()> {
+        // Save the current state of the task
+        let previous_task = self.read_task(id)?;
+        let mut new_task = new_task.clone();
+        new_task.id = id;
+        new_task.uuid = previous_task.uuid.clone();
+        new_task.index = previous_task.index;
+        self.assign_index(&mut new_task)?;
+        if new_task.parent_id != previous_task.parent_id {
+            self.check_parent(id, new_task.parent_id)?;
+        }
+        if new_task.project_id != previous_task.project_id {
+            self.check_project(new_task.project_id)?;
+        }
+        new_task.depends_on.sort();
+        new_task.depends_on.dedup();
+        for &dependency in &new_task.depends_on {
+            if !previous_task.depends_on.contains(&dependency) {
+                self.check_dependency(id, dependency)?;
+            }
+        }
+
+        // Execute update query
+        self.write_task(&new_task)?;
+
+        self.push_to_undo_history(Operation::Update, id, Some(&previous_task), Some(&new_task))?;
+
+        Ok(())
+    }
+
+    /// Marks the task with the given id as done. A recurring task hands its
+    /// rule on to a new occurrence due on the next matching day, in the same
+    /// undo step. Returns the id of the new occurrence, if any.
+    pub fn complete_task(&self, id: i32) -> Result<Option<i32>> {
+        self.batch(|store| {
+            let mut task = store.read_task(id)?;
+            let recurrence = match task.status {
+                TaskStatus::Done => None,
+                _ => task.recurrence.take(),
+            };
+            task.status = TaskStatus::Done;
+            store.update_task(id, &task)?;
+
+            let recurrence = match recurrence {
+                Some(recurrence) => recurrence,
+                None => return Ok(None),
+            };
+            let due_date = recurrence.next_due(task.due_date, chrono::Local::now().naive_local());
+            let next = Task {
+                priority: task.priority,
+                recurrence: Some(recurrence.anchored(task.due_date)),
+                parent_id: task.parent_id,
+                project_id: task.project_id,
+                ..Task::new(0, &task.text, TaskStatus::Undone, task.tags, Some(due_date))
+            };
+            store.create_task(next).map(Some)
+        })
+    }
+
+    /// Returns the subtasks of a task, their subtasks and so on, parents
+    /// before their children.
+    pub fn read_subtree(&self, id: i32) -> Result<Vec<Task>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "WITH RECURSIVE Subtree (task_id, depth) AS (
+                SELECT id, 1 FROM Tasks WHERE parent_id = ?1
+                UNION
+                SELECT Tasks.id, depth + 1 FROM Tasks JOIN Subtree ON Tasks.parent_id = Subtree.task_id
+            )
+            SELECT {} FROM Tasks JOIN Subtree ON Subtree.task_id = Tasks.id
+            ORDER BY depth, id",
+            TASK_COLUMNS
+        ))?;
+        let tasks = stmt
+            .query_map([id], TaskStore::task_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(tasks)
+    }
+
+    /// Checks that `parent_id` exists and isn't the task itself or one of its
+    /// subtasks.
+    fn check_parent(&self, id: i32, parent_id: Option<i32>) -> Result<()> {
+        let parent_id = match parent_id {
+            Some(parent_id) => parent_id,
+            None => return Ok(()),
+        };
+        self.read_task(parent_id)?;
+
+        if parent_id == id {
+            return Err(Error::InvalidInput(format!(
+                "Task {} can't be a subtask of itself",
+                id
+            )));
+        }
+        if self
+            .read_subtree(id)?
+            .iter()
+            .any(|task| task.id == parent_id)
+        {
+            return Err(Error::InvalidInput(format!(
+                "Task {} can't be a subtask of task {}, which is one of its own subtasks",
+                id, parent_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Removes the task with the given id, and drops it from the dependencies
+    /// of other tasks in the same undo step. Tasks with subtasks are removed
+    /// with [`TaskStore::delete_subtree`].
+    pub fn delete_task(&self, id: i32) -> Result<()> {
+        self.batch(|store| {
+            let task = store.read_task(id)?;
+            if !store.read_subtree(id)?.is_empty() {
+                return Err(Error::InvalidInput(format!(
+                    "Task {} has subtasks, remove them too or remove it with its subtasks",
+                    id
+                )));
+            }
+            store.detach_dependents(id)?;
+            store.push_to_undo_history(Operation::Delete, id, Some(&task), None)?;
+
+            // Execute delete query
+            store.remove_task(id)?;
+
+            Ok(())
+        })
+    }
+
+    /// Removes a task together with all of its subtasks as a single undoable
+    /// step. Returns the ids of the removed tasks, the given one first.
+    pub fn delete_subtree(&self, id: i32) -> Result<Vec<i32>> {
+        self.batch(|store| {
+            store.read_task(id)?;
+            let mut ids = vec![id];
+            ids.extend(store.read_subtree(id)?.iter().map(|task| task.id));
+
+            // Children go before their parents
+            for &id in ids.iter().rev() {
+                store.detach_dependents(id)?;
+                let task = store.read_task(id)?;
+                store.push_to_undo_history(Operation::Delete, id, Some(&task), None)?;
+                store.remove_task(id)?;
+            }
+
+            Ok(ids)
+        })
+    }
+
+    /// Makes the task `id` depend on the task `depends_on`, which has to be
+    /// done first. Dependencies can't form a cycle.
+    pub fn add_dependency(&self, id: i32, depends_on: i32) -> Result<()> {
+        self.batch(|store| {
+            let mut task = store.read_task(id)?;
+            if task.depends_on.contains(&depends_on) {
+                return Ok(());
+            }
+            task.depends_on.push(depends_on);
+            store.update_task(id, &task)
+        })
+    }
+
+    /// Removes the dependency of the task `id` on the task `depends_on`.
+    pub fn remove_dependency(&self, id: i32, depends_on: i32) -> Result<()> {
+        self.batch(|store| {
+            let mut task = store.read_task(id)?;
+            if !task.depends_on.contains(&depends_on) {
+                return Err(Error::InvalidInput(format!(
+                    "Task {} doesn't depend on task {}",
+                    id, depends_on
+                )));
+            }
+            task.depends_on
+                .retain(|&dependency| dependency != depends_on);
+            store.update_task(id, &task)
+        })
+    }
+
+    /// Returns the dependencies of a task that aren't done yet, by id.
+    pub fn read_blockers(&self, id: i32) -> Result<Vec<Task>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM Tasks
+            WHERE id IN (SELECT depends_on FROM TaskDependencies WHERE task_id = ?1)
+            AND status = 'Undone'
+            ORDER BY id",
+            TASK_COLUMNS
+        ))?;
+        let tasks = stmt
+            .query_map([id], TaskStore::task_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(tasks)
+    }
+
+    /// Checks that `depends_on` exists and doesn't depend on the task `id`,
+    /// directly or through other tasks.
+    fn check_dependency(&self, id: i32, depends_on: i32) -> Result<()> {
+        self.read_task(depends_on)?;
+
+        if depends_on == id {
+            return Err(Error::InvalidInput(format!(
+                "Task {} can't depend on itself",
+                id
+            )));
+        }
+        let is_cycle: bool = self.conn.query_row(
+            "WITH RECURSIVE Dependencies (task_id) AS (
+                SELECT depends_on FROM TaskDependencies WHERE task_id = ?1
+                UNION
+                SELECT TaskDependencies.depends_on FROM TaskDependencies
+                JOIN Dependencies ON TaskDependencies.task_id = Dependencies.task_id
+            )
+            SELECT EXISTS (SELECT 1 FROM Dependencies WHERE task_id = ?2)",
+            [depends_on, id],
+            |row| row.get(0),
+        )?;
+        if is_cycle {
+            return Err(Error::InvalidInput(format!(
+                "Task {} can't depend on task {}, which already depends on it",
+                id, depends_on
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Removes the task `id` from the dependencies of every task depending on
+    /// it.
+    fn detach_dependents(&self, id: i32) -> Result<()> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM Tasks
+            WHERE id IN (SELECT task_id FROM TaskDependencies WHERE depends_on = ?1)",
+            TASK_COLUMNS
+        ))?;
+        let dependents = stmt
+            .query_map([id], TaskStore::task_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        for mut task in dependents {
+            task.depends_on.retain(|&dependency| dependency != id);
+            self.update_task(task.id, &task)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds an empty project and returns its id.
+    pub fn create_project(&self, name: &str) -> Result<i32> {
+        let name = parse_project_name(name)?;
+        self.atomically(|| {
+            if self.find_project(name)?.is_some() {
+                return Err(Error::InvalidInput(format!(
+                    "Project '{}' already exists",
+                    name
+                )));
+            }
+
+            self.conn
+                .execute("INSERT INTO Projects (name) VALUES (?1)", [name])?;
+            Ok(self.conn.last_insert_rowid() as i32)
+        })
+    }
+
+    /// Returns every project with the number of its tasks in each status,
+    /// sorted by name.
+    pub fn read_projects(&self) -> Result<Vec<Project>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("{} ORDER BY Projects.name", PROJECT_QUERY))?;
+        let projects = stmt
+            .query_map([], TaskStore::project_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(projects)
+    }
+
+    /// Returns the project with the given name.
+    pub fn read_project(&self, name: &str) -> Result<Project> {
+        self.find_project(name)?
+            .ok_or_else(|| Error::InvalidInput(format!("No project named '{}'", name.trim())))
+    }
+
+    /// Renames a project. Its tasks stay in it.
+    pub fn rename_project(&self, name: &str, new_name: &str) -> Result<()> {
+        let new_name = parse_project_name(new_name)?;
+        self.atomically(|| self.write_project_name(name, new_name))
+    }
+
+    fn write_project_name(&self, name: &str, new_name: &str) -> Result<()> {
+        let project = self.read_project(name)?;
+        if self
+            .find_project(new_name)?
+            .is_some_and(|other| other.id != project.id)
+        {
+            return Err(Error::InvalidInput(format!(
+                "Project '{}' already exists",
+                new_name
+            )));
+        }
+
+        self.conn.execute(
+            "UPDATE Projects SET name = ?1 WHERE id = ?2",
+            params![new_name, project.id],
+        )?;
+        Ok(())
+    }
+
+    /// Removes an empty project. Tasks brought back by `undo` afterwards no
+    /// longer belong to any project.
+    pub fn delete_project(&self, name: &str) -> Result<()> {
+        self.atomically(|| {
+            let project = self.read_project(name)?;
+            if project.total() > 0 {
+                return Err(Error::InvalidInput(format!(
+                    "Project '{}' still has {} task(s), move or remove them first",
+                    project.name,
+                    project.total()
+                )));
+            }
+
+            self.conn
+                .execute("DELETE FROM Projects WHERE id = ?1", [project.id])?;
+            // The removed project was the active one
+            if self.active_project()?.is_none() {
+                self.write_setting(ACTIVE_PROJECT, None)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the project that new tasks are added to and that listings
+    /// show by default, if one is set.
+    pub fn active_project(&self) -> Result<Option<Project>> {
+        let id: i32 = match self.read_setting(ACTIVE_PROJECT)?.map(|id| id.parse()) {
+            Some(Ok(id)) => id,
+            _ => return Ok(None),
+        };
+
+        let project = self
+            .conn
+            .query_row(
+                &format!("{} HAVING Projects.id = ?1", PROJECT_QUERY),
+                [id],
+                TaskStore::project_from_row,
+            )
+            .optional()?;
+        Ok(project)
+    }
+
+    /// Makes the project with the given name the active one, or clears the
+    /// active project.
+    pub fn set_active_project(&self, name: Option<&str>) -> Result<()> {
+        self.atomically(|| {
+            let id = match name {
+                Some(name) => Some(self.read_project(name)?.id.to_string()),
+                None => None,
+            };
+            self.write_setting(ACTIVE_PROJECT, id.as_deref())?;
+            Ok(())
+        })
+    }
+
+    fn find_project(&self, name: &str) -> Result<Option<Project>> {
+        let project = self
+            .conn
+            .query_row(
+                &format!("{} HAVING Projects.name = ?1", PROJECT_QUERY),
+                [name.trim()],
+                TaskStore::project_from_row,
+            )
+            .optional()?;
+        Ok(project)
+    }
+
+    fn project_from_row(row: &Row) -> rusqlite::Result<Project> {
+        Ok(Project {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            undone: row.get(2)?,
+            done: row.get(3)?,
+            archived: row.get(4)?,
+        })
+    }
+
+    /// Checks that the project a task is put in exists.
+    fn check_project(&self, project_id: Option<i32>) -> Result<()> {
+        let project_id = match project_id {
+            Some(project_id) => project_id,
+            None => return Ok(()),
+        };
+
+        let exists: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM Projects WHERE id = ?1)",
+            [project_id],
+            |row| row.get(0),
+        )?;
+        match exists {
+            true => Ok(()),
+            false => Err(Error::InvalidInput(format!(
+                "Project {} doesn't exist",
+                project_id
+            ))),
+        }
+    }
+
+    fn read_setting(&self, name: &str) -> rusqlite::Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT value FROM Settings WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Stores a setting, or removes it if `value` is `None`.
+    fn write_setting(&self, name: &str, value: Option<&str>) -> rusqlite::Result<()> {
+        match value {
+            Some(value) => self.conn.execute(
+                "INSERT INTO Settings (name, value) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+                [name, value],
+            )?,
+            None => self
+                .conn
+                .execute("DELETE FROM Settings WHERE name = ?1", [name])?,
+        };
+        Ok(())
+    }
+
+    /// Renames a tag on every task carrying it, as a single undoable step.
+    /// Returns the number of tasks that were changed.
+    pub fn rename_tag(&self, old: &str, new: &str) -> Result<usize> {
+        self.batch(|store| {
+            let tagged: Vec<Task> = store
+                .read_tasks()?
+                .into_iter()
+                .filter(|task| task.tags.iter().any(|tag| tag == old))
+                .collect();
+
+            for mut task in tagged.clone() {
+                task.tags.retain(|tag| tag != old && tag != new);
+                task.tags.push(new.to_string());
+                store.update_task(task.id, &task)?;
+            }
+
+            Ok(tagged.len())
+        })
+    }
+
+    /// Reverts the most recent step of the history and returns the changes
+    /// it made. Does nothing if there is nothing to undo.
+    pub fn undo(&self) -> Result<Vec<HistoryEntry>> {
+        // Checked in the same transaction, another process may empty the
+        // history in the meantime
+        self.atomically(|| match self.count_steps("UndoHistory")? {
+            0 => Ok(Vec::new()),
+            _ => self.undo_steps(1),
+        })
+    }
+
+    /// Reverts the `steps` most recent steps of the history at once and
+    /// returns the changes made, in the order they were made. Fails without
+    /// changing anything if there are fewer steps to undo.
+    pub fn undo_steps(&self, steps: usize) -> Result<Vec<HistoryEntry>> {
+        self.atomically(|| {
+            let available = self.count_steps("UndoHistory")?;
+            if steps > available {
+                return Err(Error::InvalidInput(format!(
+                    "Can't undo {} steps, only {} can be undone",
+                    steps, available
+                )));
+            }
+
+            let mut changes = Vec::new();
+            for _ in 0..steps {
+                let Some((batch, entries)) = self.last_history_batch("UndoHistory")? else {
+                    break;
+                };
+                // Apply the opposite of the recorded operations, newest first
+                for entry in entries.iter().rev() {
+                    changes.push(self.apply(entry.reverted())?);
+                }
+
+                // If the undo operation is successful, move the batch to the redo history
+                self.move_history_batch(batch, "UndoHistory", "RedoHistory")?;
+            }
+
+            Ok(changes)
+        })
+    }
+
+    /// Reverts the step with the given id and every step made after it, see
+    /// [`TaskStore::read_history`].
+    pub fn undo_to(&self, id: i64) -> Result<Vec<HistoryEntry>> {
+        let steps: usize = self.conn.query_row(
+            "SELECT COUNT(DISTINCT batch) FROM UndoHistory WHERE id >= (SELECT MIN(id) FROM UndoHistory WHERE batch = ?1)",
+            [id],
+            |row| row.get(0),
+        )?;
+        if steps == 0 {
+            return Err(Error::InvalidInput(format!(
+                "Step {} isn't in the history of changes that can be undone",
+                id
+            )));
+        }
+        self.undo_steps(steps)
+    }
+
+    /// Reapplies the most recently undone step and returns the changes it
+    /// made. Does nothing if there is nothing to redo.
+    pub fn redo(&self) -> Result<Vec<HistoryEntry>> {
+        // Checked in the same transaction, like in `undo`
+        self.atomically(|| match self.count_steps("RedoHistory")? {
+            0 => Ok(Vec::new()),
+            _ => self.redo_steps(1),
+        })
+    }
+
+    /// Reapplies the `steps` most recently undone steps at once and returns
+    /// the changes made. Fails without changing anything if there are fewer
+    /// steps to redo.
+    pub fn redo_steps(&self, steps: usize) -> Result<Vec<HistoryEntry>> {
+        self.atomically(|| {
+            let available = self.count_steps("RedoHistory")?;
+            if steps > available {
+                return Err(Error::InvalidInput(format!(
+                    "Can't redo {} steps, only {} can be redone",
+                    steps, available
+                )));
+            }
+
+            let mut changes = Vec::new();
+            for _ in 0..steps {
+                let Some((batch, entries)) = self.last_history_batch("RedoHistory")? else {
+                    break;
+                };
+                // Apply the recorded operations again, oldest first
+                for entry in entries {
+                    changes.push(self.apply(entry)?);
+                }
+
+                // If the redo operation is successful, move the batch back to the undo history
+                self.move_history_batch(batch, "RedoHistory", "UndoHistory")?;
+            }
+
+            Ok(changes)
+        })
+    }
+
+    /// Switches to a branch of the history, see [`TaskStore::read_branches`]:
+    /// undoes or redoes steps until the one the branch continues from, then
+    /// reapplies `steps` of the branch. The steps that could be redone before
+    /// become a branch of their own. Returns every change made, in order.
+    pub fn redo_branch(&self, id: i64, steps: usize) -> Result<Vec<HistoryEntry>> {
+        self.atomically(|| {
+            let fork: Option<i64> = self
+                .conn
+                .query_row("SELECT fork FROM Branches WHERE id = ?1", [id], |row| {
+                    row.get(0)
+                })
+                .optional()?
+                .ok_or_else(|| Error::InvalidInput(format!("No history branch {}", id)))?;
+
+            let mut changes = match fork {
+                Some(fork) if self.has_batch("RedoHistory", fork)? => {
+                    let later: usize = self.conn.query_row(
+                        "SELECT COUNT(DISTINCT batch) FROM RedoHistory WHERE id >= (SELECT MIN(id) FROM RedoHistory WHERE batch = ?1)",
+                        [fork],
+                        |row| row.get(0),
+                    )?;
+                    self.redo_steps(later)?
+                }
+                Some(fork) if !self.has_batch("UndoHistory", fork)? => {
+                    return Err(Error::InvalidInput(format!(
+                        "Branch {} continues from step {} of another branch, switch to that one first",
+                        id, fork
+                    )));
+                }
+                _ => {
+                    let later: usize = self.conn.query_row(
+                        "SELECT COUNT(DISTINCT batch) FROM UndoHistory WHERE id > COALESCE((SELECT MAX(id) FROM UndoHistory WHERE batch = ?1), 0)",
+                        [fork],
+                        |row| row.get(0),
+                    )?;
+                    self.undo_steps(later)?
+                }
+            };
+
+            self.abandon_redo_history()?;
+            self.conn.execute(
+                "INSERT INTO RedoHistory (batch, operation, created_at, task_id, before_snapshot, after_snapshot) SELECT batch, operation, created_at, task_id, before_snapshot, after_snapshot FROM BranchHistory WHERE branch = ?1 ORDER BY id",
+                [id],
+            )?;
+            self.remove_branch(id)?;
+
+            changes.extend(self.redo_steps(steps)?);
+            Ok(changes)
+        })
+    }
+
+    /// Returns the steps of the history, oldest first, followed by the steps
+    /// that were undone, the next one to redo first.
+    pub fn read_history(&self) -> Result<Vec<HistoryStep>> {
+        let mut steps = self.read_steps("UndoHistory", "ASC", "1", [], false)?;
+        steps.extend(self.read_steps("RedoHistory", "DESC", "1", [], true)?);
+        Ok(steps)
+    }
+
+    /// Returns what the tasks and projects are called, to describe the changes
+    /// of the history with. Removed tasks are named after their last snapshot.
+    pub fn read_history_names(&self) -> Result<HistoryNames> {
+        let mut tasks: HashMap<i32, String> = self
+            .read_tasks()?
+            .iter()
+            .map(|task| (task.id, task.label()))
+            .collect();
+        let mut stmt = self.conn.prepare(
+            "SELECT task_id, uuid, MAX(id) FROM TaskSnapshots
+            WHERE uuid IS NOT NULL AND task_id NOT IN (SELECT id FROM Tasks)
+            GROUP BY task_id",
+        )?;
+        let removed = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (id, uuid) in removed {
+            tasks.insert(id, uuid.chars().take(8).collect());
+        }
+        let projects = self
+            .read_projects()?
+            .into_iter()
+            .map(|project| (project.id, project.name))
+            .collect();
+
+        Ok(HistoryNames { tasks, projects })
+    }
+
+    /// Returns the branches of the history, oldest first. Each one holds steps
+    /// that were undone and then left behind by a new change, the one to redo
+    /// first first.
+    pub fn read_branches(&self) -> Result<Vec<HistoryBranch>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, fork, created_at FROM Branches ORDER BY id")?;
+        let branches = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Option<i64>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut result = Vec::new();
+        for (id, fork, created_at) in branches {
+            result.push(HistoryBranch {
+                id,
+                fork,
+                created_at: created_at.as_deref().and_then(parse_timestamp),
+                steps: self.read_steps("BranchHistory", "DESC", "branch = ?1", [id], true)?,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Forgets the oldest steps of the undo history so that at most `steps`
+    /// of them can be undone. Returns the number of steps removed.
+    pub fn limit_history(&self, steps: usize) -> Result<usize> {
+        // Only looking first, so that nothing is locked while the history is
+        // within the limit
+        if self.last_step_beyond(steps)?.is_none() {
+            return Ok(0);
+        }
+
+        // Looked up again in the transaction, another process may have
+        // changed the history in the meantime
+        self.atomically(|| {
+            let removed = match self.last_step_beyond(steps)? {
+                Some(last) => self.forget_steps(last)?,
+                None => 0,
+            };
+            if removed > 0 {
+                self.remove_unused_snapshots()?;
+            }
+
+            Ok(removed)
+        })
+    }
+
+    /// The id of the last row of the undo history that has to go for at most
+    /// `steps` steps to be left.
+    fn last_step_beyond(&self, steps: usize) -> rusqlite::Result<Option<i64>> {
+        self.conn.query_row(
+            "SELECT MAX(id) FROM UndoHistory WHERE batch NOT IN (
+                SELECT batch FROM UndoHistory GROUP BY batch ORDER BY MAX(id) DESC LIMIT ?1
+            )",
+            [steps as i64],
+            |row| row.get(0),
+        )
+    }
+
+    /// Forgets the steps of the undo history made before `time`, with every
+    /// step before them, and the branches left behind before it. Steps that
+    /// were undone are kept. Returns the number of steps removed.
+    pub fn forget_history_before(&self, time: NaiveDateTime) -> Result<usize> {
+        // Like in `limit_history`, nothing is locked when nothing is old enough
+        if self.last_step_before(time)?.is_none() && self.branches_before(time)?.is_empty() {
+            return Ok(0);
+        }
+
+        self.atomically(|| {
+            let removed = match self.last_step_before(time)? {
+                Some(last) => self.forget_steps(last)?,
+                None => 0,
+            };
+            for id in self.branches_before(time)? {
+                self.remove_branch(id)?;
+            }
+            self.remove_unreachable_branches(false)?;
+            self.remove_unused_snapshots()?;
+
+            Ok(removed)
+        })
+    }
+
+    /// The id of the last row of the latest undo history step made before
+    /// `time`.
+    fn last_step_before(&self, time: NaiveDateTime) -> rusqlite::Result<Option<i64>> {
+        // Timestamps compare as text, so the index finds the old rows, which
+        // are few when the history is pruned regularly. SQLite would rather
+        // walk back from the last id. Steps are contiguous, the last old row
+        // ends the step unless the step also has newer ones.
+        self.conn.query_row(
+            "SELECT MAX(id) FROM UndoHistory INDEXED BY UndoHistoryByTime
+            WHERE created_at < ?1 AND batch NOT IN (
+                SELECT batch FROM UndoHistory WHERE created_at >= ?1
+            )",
+            [local_timestamp(time)],
+            |row| row.get(0),
+        )
+    }
+
+    /// The ids of the history branches made before `time`.
+    fn branches_before(&self, time: NaiveDateTime) -> rusqlite::Result<Vec<i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM Branches WHERE created_at < ?1")?;
+        let branches = stmt
+            .query_map([local_timestamp(time)], |row| row.get(0))?
+            .collect();
+        branches
+    }
+
+    /// The size of the database in bytes.
+    pub fn database_size(&self) -> Result<u64> {
+        Ok(self.pragma_pages("page_count")?)
+    }
+
+    /// The bytes of the database that are free after data was removed, which
+    /// [`TaskStore::vacuum`] gives back.
+    pub fn unused_space(&self) -> Result<u64> {
+        Ok(self.pragma_pages("freelist_count")?)
+    }
+
+    fn pragma_pages(&self, pragma: &str) -> rusqlite::Result<u64> {
+        let pages: u64 = self
+            .conn
+            .pragma_query_value(None, pragma, |row| row.get(0))?;
+        let page_size: u64 = self
+            .conn
+            .pragma_query_value(None, "page_size", |row| row.get(0))?;
+        Ok(pages * page_size)
+    }
+
+    /// Rebuilds the database file without its unused space, and returns the
+    /// number of bytes it shrank by.
+    pub fn vacuum(&self) -> Result<u64> {
+        let before = self.database_size()?;
+        self.conn.execute_batch("VACUUM")?;
+        // The file only shrinks once the write-ahead log is written back
+        self.conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(before.saturating_sub(self.database_size()?))
+    }
+
+    /// Brings the task of a history entry to the state the entry leaves it
+    /// in, and returns the entry with the number the task got. It keeps its
+    /// old number unless another task took it in the meantime.
+    fn apply(&self, mut entry: HistoryEntry) -> Result<HistoryEntry> {
+        if entry.operation == Operation::Delete {
+            self.remove_task(entry.task_id)?;
+            return Ok(entry);
+        }
+
+        let mut task = entry.snapshot_after()?.clone();
+        self.assign_index(&mut task)?;
+        match entry.operation {
+            Operation::Create => self.insert_task(&task)?,
+            _ => self.write_task(&task)?,
+        }
+        entry.after = Some(task);
+        Ok(entry)
+    }
+
+    fn insert_task(&self, task: &Task) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO Tasks (id, text, status, due_date, priority, created_at, recurrence, parent_id, project_id, uuid, display_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                task.id,
+                task.text,
+                task.status.to_string(),
+                task.due_date,
+                task.priority.map(|priority| priority.to_string()),
+                task.created_at,
+                task.recurrence.as_ref().map(|recurrence| recurrence.to_string()),
+                task.parent_id,
+                task.project_id,
+                task.uuid,
+                task.index
+            ],
+        )?;
+
+        self.write_tags(task)?;
+        self.write_dependencies(task)
+    }
+
+    fn write_task(&self, task: &Task) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE Tasks SET text = ?1, status = ?2, due_date = ?3, priority = ?4, created_at = ?5, recurrence = ?6, parent_id = ?7, project_id = ?8, uuid = ?9, display_index = ?10 WHERE id = ?11",
+            params![
+                task.text,
+                task.status.to_string(),
+                task.due_date,
+                task.priority.map(|priority| priority.to_string()),
+                task.created_at,
+                task.recurrence.as_ref().map(|recurrence| recurrence.to_string()),
+                task.parent_id,
+                task.project_id,
+                task.uuid,
+                task.index,
+                task.id
+            ],
+        )?;
+
+        self.write_tags(task)?;
+        self.write_dependencies(task)
+    }
+
+    fn remove_task(&self, id: i32) -> rusqlite::Result<()> {
+        self.conn.execute("DELETE FROM Tasks WHERE id = ?1", [id])?;
+        self.conn
+            .execute("DELETE FROM TaskTags WHERE task_id = ?1", [id])?;
+        self.conn
+            .execute("DELETE FROM TaskDependencies WHERE task_id = ?1", [id])?;
+        self.remove_unused_tags()
+    }
+
+    /// Replaces the tags of a task with the ones in `task.tags`.
+    fn write_tags(&self, task: &Task) -> rusqlite::Result<()> {
+        self.conn
+            .execute("DELETE FROM TaskTags WHERE task_id = ?1", [task.id])?;
+
+        for tag in &task.tags {
+            self.conn
+                .execute("INSERT OR IGNORE INTO Tags (name) VALUES (?1)", [tag])?;
+            self.conn.execute(
+                "INSERT OR IGNORE INTO TaskTags (task_id, tag_id) SELECT ?1, id FROM Tags WHERE name = ?2",
+                params![task.id, tag],
+            )?;
+        }
+
+        self.remove_unused_tags()
+    }
+
+    /// Replaces the dependencies of a task with the ones in `task.depends_on`.
+    fn write_dependencies(&self, task: &Task) -> rusqlite::Result<()> {
+        self.conn
+            .execute("DELETE FROM TaskDependencies WHERE task_id = ?1", [task.id])?;
+
+        for &dependency in &task.depends_on {
+            self.conn.execute(
+                "INSERT OR IGNORE INTO TaskDependencies (task_id, depends_on) VALUES (?1, ?2)",
+                [task.id, dependency],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_unused_tags(&self) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "DELETE FROM Tags WHERE id NOT IN (SELECT tag_id FROM TaskTags)",
+            (),
+        )?;
+
+        Ok(())
+    }
+
+    fn next_batch(&self) -> rusqlite::Result<i64> {
+        self.conn.query_row(
+            "SELECT COALESCE(MAX(batch), 0) + 1 FROM (SELECT batch FROM UndoHistory UNION ALL SELECT batch FROM RedoHistory UNION ALL SELECT batch FROM BranchHistory)",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    fn push_to_undo_history(
+        &self,
+        operation: Operation,
+        task_id: i32,
+        before: Option<&Task>,
+        after: Option<&Task>,
+    ) -> rusqlite::Result<()> {
+        // A new change makes the undone steps a branch, they can't be redone
+        // on top of it
+        self.abandon_redo_history()?;
+
+        // Changes made outside of a batch are a step of their own
+        let batch = match self.batch.get() {
+            Some(batch) => batch,
+            None => self.next_batch()?,
+        };
+
+        let before_snapshot = match before {
+            Some(task) => Some(self.insert_snapshot(task)?),
+            None => None,
+        };
+        let after_snapshot = match after {
+            Some(task) => Some(self.insert_snapshot(task)?),
+            None => None,
+        };
+
+        self.conn.execute(
+            "INSERT INTO UndoHistory (batch, operation, created_at, task_id, before_snapshot, after_snapshot) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                batch,
+                operation.to_string(),
+                to_timestamp(chrono::Utc::now()),
+                task_id,
+                before_snapshot,
+                after_snapshot
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Moves the steps that could be redone to a new branch of the history,
+    /// continuing from the most recent step.
+    fn abandon_redo_history(&self) -> rusqlite::Result<()> {
+        if self.count_steps("RedoHistory")? == 0 {
+            return Ok(());
+        }
+
+        let fork: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT batch FROM UndoHistory ORDER BY id DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        self.conn.execute(
+            "INSERT INTO Branches (fork, created_at) VALUES (?1, ?2)",
+            params![fork, to_timestamp(chrono::Utc::now())],
+        )?;
+        self.conn.execute(
+            "INSERT INTO BranchHistory (branch, batch, operation, created_at, task_id, before_snapshot, after_snapshot) SELECT ?1, batch, operation, created_at, task_id, before_snapshot, after_snapshot FROM RedoHistory ORDER BY id",
+            [self.conn.last_insert_rowid()],
+        )?;
+        self.conn.execute("DELETE FROM RedoHistory", ())?;
+
+        Ok(())
+    }
+
+    /// Removes the undo history up to and including the row `last`, and
+    /// returns the number of steps removed.
+    fn forget_steps(&self, last: i64) -> rusqlite::Result<usize> {
+        let removed: usize = self.conn.query_row(
+            "SELECT COUNT(DISTINCT batch) FROM UndoHistory WHERE id <= ?1",
+            [last],
+            |row| row.get(0),
+        )?;
+        self.conn
+            .execute("DELETE FROM UndoHistory WHERE id <= ?1", [last])?;
+        if removed > 0 {
+            self.remove_unreachable_branches(true)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes the branches continuing from a forgotten step, or from the
+    /// beginning of the history if it moved, and the branches of their steps.
+    fn remove_unreachable_branches(&self, moved_start: bool) -> rusqlite::Result<()> {
+        loop {
+            let unreachable: Vec<i64> = self
+                .conn
+                .prepare(
+                    "SELECT id FROM Branches WHERE (fork IS NULL AND ?1) OR fork NOT IN (
+                        SELECT batch FROM UndoHistory
+                        UNION SELECT batch FROM RedoHistory
+                        UNION SELECT batch FROM BranchHistory
+                    )",
+                )?
+                .query_map([moved_start], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            if unreachable.is_empty() {
+                return Ok(());
+            }
+            for branch in unreachable {
+                self.remove_branch(branch)?;
+            }
+        }
+    }
+
+    fn remove_unused_snapshots(&self) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "DELETE FROM TaskSnapshots WHERE id NOT IN (
+                SELECT before_snapshot FROM UndoHistory WHERE before_snapshot IS NOT NULL
+                UNION SELECT after_snapshot FROM UndoHistory WHERE after_snapshot IS NOT NULL
+                UNION SELECT before_snapshot FROM RedoHistory WHERE before_snapshot IS NOT NULL
+                UNION SELECT after_snapshot FROM RedoHistory WHERE after_snapshot IS NOT NULL
+                UNION SELECT before_snapshot FROM BranchHistory WHERE before_snapshot IS NOT NULL
+                UNION SELECT after_snapshot FROM BranchHistory WHERE after_snapshot IS NOT NULL
+            )",
+            (),
+        )?;
+
+        Ok(())
+    }
+
+    fn remove_branch(&self, id: i64) -> rusqlite::Result<()> {
+        self.conn
+            .execute("DELETE FROM BranchHistory WHERE branch = ?1", [id])?;
+        self.conn
+            .execute("DELETE FROM Branches WHERE id = ?1", [id])?;
+
+        Ok(())
+    }
+
+    /// Stores a task as it is at the moment and returns the id of the
+    /// snapshot. An identical snapshot, e.g. the one of the previous change,
+    /// is shared instead of stored again.
+    fn insert_snapshot(&self, task: &Task) -> rusqlite::Result<i64> {
+        let columns = params![
+            task.id,
+            task.text,
+            task.status.to_string(),
+            TaskStore::tags_to_column(&task.tags),
+            task.due_date,
+            task.priority.map(|priority| priority.to_string()),
+            task.created_at,
+            task.recurrence
+                .as_ref()
+                .map(|recurrence| recurrence.to_string()),
+            task.parent_id,
+            TaskStore::ids_to_column(&task.depends_on),
+            task.project_id,
+            task.uuid,
+            task.index
+        ];
+        let existing = self
+            .conn
+            .query_row(
+                "SELECT id FROM TaskSnapshots WHERE task_id = ?1 AND text = ?2 AND status = ?3 AND tags IS ?4 AND due_date IS ?5 AND priority IS ?6 AND created_at IS ?7 AND recurrence IS ?8 AND parent_id IS ?9 AND depends_on IS ?10 AND project_id IS ?11 AND uuid IS ?12 AND display_index IS ?13 ORDER BY id DESC LIMIT 1",
+                columns,
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = existing {
+            return Ok(id);
+        }
+
+        self.conn.execute(
+            "INSERT INTO TaskSnapshots (task_id, text, status, tags, due_date, priority, created_at, recurrence, parent_id, depends_on, project_id, uuid, display_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            columns,
+        )?;
+
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    fn read_snapshot(&self, id: Option<i64>) -> Result<Option<Task>> {
+        let id = match id {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        self.conn
+            .query_row(
+                "SELECT task_id, text, status, tags, due_date, priority, created_at, recurrence, parent_id, depends_on, project_id, uuid, display_index FROM TaskSnapshots WHERE id = ?1",
+                [id],
+                TaskStore::task_from_row,
+            )
+            .optional()?
+            .map(Some)
+            .ok_or_else(|| Error::CorruptedHistory(format!("snapshot {} is missing", id)))
+    }
+
+    /// Reads the steps of a history table whose rows match `condition`, in
+    /// the given order of their first rows.
+    fn read_steps<P: rusqlite::Params>(
+        &self,
+        table: &str,
+        order: &str,
+        condition: &str,
+        params: P,
+        undone: bool,
+    ) -> Result<Vec<HistoryStep>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT batch, MAX(created_at) FROM {} WHERE {} GROUP BY batch ORDER BY MIN(id) {}",
+            table, condition, order
+        ))?;
+        let batches = stmt
+            .query_map(params, |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut steps = Vec::new();
+        for (batch, created_at) in batches {
+            steps.push(HistoryStep {
+                id: batch,
+                created_at: created_at.as_deref().and_then(parse_timestamp),
+                undone,
+                entries: self.read_history_batch(table, batch)?,
+            });
+        }
+        Ok(steps)
+    }
+
+    /// Reads the entries of the most recent batch of a history table, oldest
+    /// first.
+    fn last_history_batch(&self, table: &str) -> Result<Option<(i64, Vec<HistoryEntry>)>> {
+        let batch: Option<i64> = self
+            .conn
+            .query_row(
+                &format!("SELECT batch FROM {} ORDER BY id DESC LIMIT 1", table),
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match batch {
+            Some(batch) => Ok(Some((batch, self.read_history_batch(table, batch)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the entries of a batch of a history table, oldest first.
+    fn read_history_batch(&self, table: &str, batch: i64) -> Result<Vec<HistoryEntry>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, operation, task_id, before_snapshot, after_snapshot FROM {} WHERE batch = ?1 ORDER BY id",
+            table
+        ))?;
+        let rows = stmt
+            .query_map([batch], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i32>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut entries = Vec::new();
+        for (id, operation, task_id, before_snapshot, after_snapshot) in rows {
+            entries.push(HistoryEntry {
+                id,
+                operation: operation.parse().map_err(|_| {
+                    Error::CorruptedHistory(format!(
+                        "unknown operation '{}' in {} entry {}",
+                        operation, table, id
+                    ))
+                })?,
+                task_id,
+                before: self.read_snapshot(before_snapshot)?,
+                after: self.read_snapshot(after_snapshot)?,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// The number of steps in a history table.
+    fn count_steps(&self, table: &str) -> rusqlite::Result<usize> {
+        self.conn.query_row(
+            &format!("SELECT COUNT(DISTINCT batch) FROM {}", table),
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    /// Whether a history table holds the step with the given batch.
+    fn has_batch(&self, table: &str, batch: i64) -> rusqlite::Result<bool> {
+        self.conn.query_row(
+            &format!("SELECT EXISTS (SELECT 1 FROM {} WHERE batch = ?1)", table),
+            [batch],
+            |row| row.get(0),
+        )
+    }
+
+    /// Moves a step between history tables. It keeps the time it was made,
+    /// which retention by age goes by.
+    fn move_history_batch(&self, batch: i64, from: &str, to: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            &format!(
+                "INSERT INTO {} (batch, operation, created_at, task_id, before_snapshot, after_snapshot) SELECT batch, operation, created_at, task_id, before_snapshot, after_snapshot FROM {} WHERE batch = ?1 ORDER BY id",
+                to, from
+            ),
+            [batch],
+        )?;
+        self.conn
+            .execute(&format!("DELETE FROM {} WHERE batch = ?1", from), [batch])?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,574 @@
+use list_rs::due::{describe_due, DueState};
+use list_rs::task::{split_text_and_tags, DisplayStyle, Markers};
+use list_rs::{HistoryEntry, Priority, Task, TaskStatus, TaskStore};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::Frame;
+
+const HELP: &str = "j/k move  space done  A archive  a add  e edit  x delete  / search  h archived  u undo  r redo  q quit";
+
+/// What the keyboard currently controls.
+#[derive(Debug, PartialEq, Clone)]
+enum Mode {
+    Normal,
+    Adding(String),
+    Editing(i32, String),
+    Searching,
+}
+
+/// State of the interactive mode, independent of the terminal so that it can
+/// be driven by key events and drawn on any backend.
+pub struct App<'a> {
+    store: &'a TaskStore,
+    /// Only its date format is used, markers are always ASCII.
+    style: DisplayStyle,
+    tasks: Vec<Task>,
+    /// Indices into `tasks` of the tasks matching the current filters
+    visible: Vec<usize>,
+    list_state: ListState,
+    mode: Mode,
+    search: String,
+    show_archived: bool,
+    message: Option<String>,
+    pub should_quit: bool,
+}
+
+impl<'a> App<'a> {
+    pub fn new(store: &'a TaskStore, style: DisplayStyle) -> Self {
+        let mut app = App {
+            store,
+            style,
+            tasks: Vec::new(),
+            visible: Vec::new(),
+            list_state: ListState::default(),
+            mode: Mode::Normal,
+            search: String::new(),
+            show_archived: false,
+            message: None,
+            should_quit: false,
+        };
+        app.reload(None);
+        app
+    }
+
+    /// Re-reads the tasks from the database, keeping the task with
+    /// `select_id` (or the current position) selected.
+    fn reload(&mut self, select_id: Option<i32>) {
+        let previous = self.list_state.selected().unwrap_or(0);
+
+        match self.store.read_tasks() {
+            Ok(tasks) => self.tasks = tasks,
+            Err(e) => self.message = Some(format!("Error: {}", e)),
+        }
+        self.tasks.sort_by_key(|task| task.created_at);
+        self.refilter();
+
+        let index = select_id
+            .and_then(|id| {
+                self.visible
+                    .iter()
+                    .position(|&index| self.tasks[index].id == id)
+            })
+            .unwrap_or(previous);
+        self.select(index);
+    }
+
+    fn refilter(&mut self) {
+        let search = self.search.to_lowercase();
+        self.visible = self
+            .tasks
+            .iter()
+            .enumerate()
+            .filter(|(_, task)| self.show_archived || task.status != TaskStatus::Archived)
+            .filter(|(_, task)| task.text.to_lowercase().contains(&search))
+            .map(|(index, _)| index)
+            .collect();
+        self.select(self.list_state.selected().unwrap_or(0));
+    }
+
+    fn select(&mut self, index: usize) {
+        if self.visible.is_empty() {
+            self.list_state.select(None);
+        } else {
+            self.list_state
+                .select(Some(index.min(self.visible.len() - 1)));
+        }
+    }
+
+    fn selected_task(&self) -> Option<&Task> {
+        self.list_state
+            .selected()
+            .and_then(|index| self.visible.get(index))
+            .map(|&index| &self.tasks[index])
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        match self.mode.clone() {
+            Mode::Normal => self.handle_normal_key(key),
+            Mode::Searching => match key.code {
+                KeyCode::Esc => {
+                    self.search.clear();
+                    self.mode = Mode::Normal;
+                    self.refilter();
+                }
+                KeyCode::Enter => self.mode = Mode::Normal,
+                KeyCode::Backspace => {
+                    self.search.pop();
+                    self.refilter();
+                }
+                KeyCode::Char(c) => {
+                    self.search.push(c);
+                    self.refilter();
+                }
+                _ => {}
+            },
+            Mode::Adding(mut input) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Enter => {
+                    self.mode = Mode::Normal;
+                    self.add_task(&input);
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.mode = Mode::Adding(input);
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.mode = Mode::Adding(input);
+                }
+                _ => {}
+            },
+            Mode::Editing(id, mut input) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Enter => {
+                    self.mode = Mode::Normal;
+                    self.edit_task(id, &input);
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.mode = Mode::Editing(id, input);
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.mode = Mode::Editing(id, input);
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn handle_normal_key(&mut self, key: KeyEvent) {
+        self.message = None;
+        let selected = self.list_state.selected().unwrap_or(0);
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.should_quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.should_quit = true
+            }
+            KeyCode::Char('j') | KeyCode::Down => self.select(selected + 1),
+            KeyCode::Char('k') | KeyCode::Up => self.select(selected.saturating_sub(1)),
+            KeyCode::Char('g') | KeyCode::Home => self.select(0),
+            KeyCode::Char('G') | KeyCode::End => self.select(usize::MAX),
+            KeyCode::PageDown => self.select(selected + 10),
+            KeyCode::PageUp => self.select(selected.saturating_sub(10)),
+            KeyCode::Char(' ') => self.toggle_status(TaskStatus::Done),
+            KeyCode::Char('A') => self.toggle_status(TaskStatus::Archived),
+            KeyCode::Char('a') => self.mode = Mode::Adding(String::new()),
+            KeyCode::Char('e') => {
+                if let Some(task) = self.selected_task() {
+                    self.mode = Mode::Editing(task.id, task.text.clone());
+                }
+            }
+            KeyCode::Char('x') | KeyCode::Delete => self.delete_task(),
+            KeyCode::Char('/') => self.mode = Mode::Searching,
+            KeyCode::Char('h') => {
+                self.show_archived = !self.show_archived;
+                self.refilter();
+            }
+            KeyCode::Char('u') => {
+                let result = self.store.undo();
+                self.report_changes(result, "Nothing to undo");
+                self.reload(None);
+            }
+            KeyCode::Char('r') => {
+                let result = self.store.redo();
+                self.report_changes(result, "Nothing to redo");
+                self.reload(None);
+            }
+            _ => {}
+        }
+    }
+
+    fn report<T>(&mut self, result: list_rs::Result<T>, success: &str) {
+        self.message = Some(match result {
+            Ok(_) => success.to_string(),
+            Err(e) => format!("Error: {}", e),
+        });
+    }
+
+    /// Shows the changes made by undo or redo, or `nothing` if there were none.
+    fn report_changes(&mut self, result: list_rs::Result<Vec<HistoryEntry>>, nothing: &str) {
+        let result = result.and_then(|changes| Ok((changes, self.store.read_history_names()?)));
+        self.message = Some(match result {
+            Ok((changes, _)) if changes.is_empty() => nothing.to_string(),
+            Ok((changes, names)) => {
+                let changes: Vec<String> = changes
+                    .iter()
+                    .map(|change| change.describe(&names))
+                    .collect();
+                changes.join("; ")
+            }
+            Err(e) => format!("Error: {}", e),
+        });
+    }
+
+    /// Sets the selected task to `status`, or back to Undone if it already has it.
+    fn toggle_status(&mut self, status: TaskStatus) {
+        if let Some(task) = self.selected_task() {
+            let mut task = task.clone();
+            task.status = if task.status == status {
+                TaskStatus::Undone
+            } else {
+                status
+            };
+
+            // Recurring tasks add their next occurrence when they are done
+            let result = match task.status {
+                TaskStatus::Done => self.store.complete_task(task.id).map(|_| ()),
+                _ => self.store.update_task(task.id, &task),
+            };
+            self.report(
+                result,
+                &format!("Task {} set to {}", task.label(), task.status),
+            );
+            self.reload(Some(task.id));
+        }
+    }
+
+    fn add_task(&mut self, input: &str) {
+        let words: Vec<String> = input.split_whitespace().map(String::from).collect();
+        let (text, tags) = split_text_and_tags(&words);
+        if text.is_empty() {
+            self.message = Some(String::from("Task text can't be empty"));
+            return;
+        }
+
+        match self
+            .store
+            .create_task(Task::new(1, &text, TaskStatus::Undone, tags, None))
+        {
+            Ok(id) => {
+                self.message = Some(String::from("Task Added"));
+                self.reload(Some(id));
+            }
+            Err(e) => self.message = Some(format!("Error creating task {}", e)),
+        }
+    }
+
+    fn edit_task(&mut self, id: i32, input: &str) {
+        let text = input.trim();
+        if text.is_empty() {
+            self.message = Some(String::from("Task text can't be empty"));
+            return;
+        }
+
+        let store = self.store;
+        let mut label = id.to_string();
+        let result = store.read_task(id).and_then(|mut task| {
+            label = task.label();
+            task.text = text.to_string();
+            store.update_task(id, &task)
+        });
+        self.report(result, &format!("Task {} updated", label));
+        self.reload(Some(id));
+    }
+
+    fn delete_task(&mut self) {
+        if let Some(task) = self.selected_task() {
+            let (id, label) = (task.id, task.label());
+            let result = self.store.delete_task(id);
+            self.report(result, &format!("Task {} removed", label));
+            self.reload(None);
+        }
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, status_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        let items: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|&index| ListItem::new(task_line(&self.tasks[index], &self.style.date_format)))
+            .collect();
+        let title = if self.show_archived {
+            " list-rs (all) "
+        } else {
+            " list-rs "
+        };
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        let (prompt, input) = match &self.mode {
+            Mode::Adding(input) => ("Add: ", Some(input.as_str())),
+            Mode::Editing(_, input) => ("Edit: ", Some(input.as_str())),
+            Mode::Searching => ("/", Some(self.search.as_str())),
+            Mode::Normal => ("", None),
+        };
+        let status = match input {
+            Some(input) => {
+                frame.set_cursor_position(Position::new(
+                    status_area.x + (prompt.len() + input.chars().count()) as u16,
+                    status_area.y,
+                ));
+                Line::from(vec![Span::from(prompt).bold(), Span::from(input)])
+            }
+            None => match &self.message {
+                Some(message) => Line::from(message.as_str()),
+                None if !self.search.is_empty() => Line::from(format!(
+                    "Filter: {} (/ to change, Esc in search to clear)",
+                    self.search
+                )),
+                None => Line::from(HELP).dim(),
+            },
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+}
+
+/// Renders a task like its `Display` impl, using plain ASCII markers so it
+/// works on any terminal.
+fn task_line(task: &Task, date_format: &str) -> Line<'static> {
+    let marker = Markers::Ascii.marker(task.status);
+    let text_style = match task.status {
+        TaskStatus::Done => Style::default()
+            .fg(Color::Blue)
+            .add_modifier(Modifier::CROSSED_OUT),
+        TaskStatus::Archived => Style::default().add_modifier(Modifier::DIM),
+        TaskStatus::Undone => Style::default().fg(Color::Blue),
+    };
+
+    let mut spans = vec![
+        Span::from(format!("{}) ", task.label())).bold(),
+        Span::from(format!("{} ", marker)),
+    ];
+    if let Some(priority) = task.priority {
+        let style = match (task.status, priority) {
+            (TaskStatus::Undone, Priority::High) => Style::default().red().bold(),
+            (TaskStatus::Undone, Priority::Medium) => Style::default().yellow(),
+            (TaskStatus::Undone, Priority::Low) => Style::default().cyan(),
+            _ => Style::default().dim(),
+        };
+        let marker = format!("({}) ", &priority.to_string()[..1]);
+        spans.push(Span::styled(marker, style));
+    }
+    spans.push(Span::styled(task.text.clone(), text_style));
+    for tag in &task.tags {
+        spans.push(Span::from(format!(" +{}", tag)).magenta());
+    }
+    if let Some(due_date) = task.due_date {
+        let (description, state) =
+            describe_due(due_date, chrono::Local::now().naive_local(), date_format);
+        let style = match (task.status, state) {
+            (TaskStatus::Undone, DueState::Overdue) => Style::default().red().bold(),
+            (TaskStatus::Undone, DueState::Today) => Style::default().yellow(),
+            (TaskStatus::Undone, DueState::Upcoming) => Style::default().green(),
+            _ => Style::default().dim(),
+        };
+        spans.push(Span::styled(format!(" ({})", description), style));
+    }
+
+    Line::from(spans)
+}
+
+/// Runs the interactive mode until the user quits.
+pub fn run(store: &TaskStore, style: DisplayStyle) -> std::io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let mut app = App::new(store, style);
+
+    let result = loop {
+        if let Err(e) = terminal.draw(|frame| app.draw(frame)) {
+            break Err(e);
+        }
+        match event::read() {
+            Ok(Event::Key(key)) => app.handle_key(key),
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+        if app.should_quit {
+            break Ok(());
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::App;
+    use list_rs::task::DisplayStyle;
+    use list_rs::{Task, TaskStatus, TaskStore};
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::{KeyCode, KeyEvent};
+    use ratatui::Terminal;
+
+    fn setup() -> TaskStore {
+        let store = TaskStore::open_in_memory().unwrap();
+        for text in ["Watch Oppenheimer", "Go to the gym", "Meditate"] {
+            store
+                .create_task(Task::new(1, text, TaskStatus::Undone, vec![], None))
+                .unwrap();
+        }
+        store
+    }
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            app.handle_key(KeyEvent::from(KeyCode::Char(c)));
+        }
+    }
+
+    fn press_code(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::from(code));
+    }
+
+    fn render(app: &mut App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(60, 8)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn tasks_should_be_rendered() {
+        let store = setup();
+        let mut app = App::new(&store, DisplayStyle::default());
+
+        let screen = render(&mut app);
+
+        assert!(screen.contains("> 1) [ ] Watch Oppenheimer"));
+        assert!(screen.contains("2) [ ] Go to the gym"));
+        assert!(screen.contains("3) [ ] Meditate"));
+    }
+
+    #[test]
+    fn space_should_toggle_done() {
+        let store = setup();
+        let mut app = App::new(&store, DisplayStyle::default());
+
+        press(&mut app, "j ");
+        let task = store.read_task(2).unwrap();
+        assert_eq!(TaskStatus::Done, task.status);
+        assert!(render(&mut app).contains(&format!("{}) [x] Go to the gym", &task.uuid[..8])));
+
+        press(&mut app, " ");
+        let task = store.read_task(2).unwrap();
+        assert_eq!(TaskStatus::Undone, task.status);
+        assert_eq!(Some(2), task.index);
+    }
+
+    #[test]
+    fn archived_tasks_should_be_hidden_until_toggled() {
+        let store = setup();
+        let mut app = App::new(&store, DisplayStyle::default());
+
+        press(&mut app, "A");
+        let task = store.read_task(1).unwrap();
+        assert_eq!(TaskStatus::Archived, task.status);
+        assert!(!render(&mut app).contains("Watch Oppenheimer"));
+
+        press(&mut app, "h");
+        assert!(render(&mut app).contains(&format!("{}) [a] Watch Oppenheimer", &task.uuid[..8])));
+    }
+
+    #[test]
+    fn adding_and_editing_should_work() {
+        let store = setup();
+        let mut app = App::new(&store, DisplayStyle::default());
+
+        press(&mut app, "aRead a book +fun");
+        press_code(&mut app, KeyCode::Enter);
+        let task = store.read_task(4).unwrap();
+        assert_eq!("Read a book", task.text);
+        assert_eq!(vec![String::from("fun")], task.tags);
+
+        press(&mut app, "e");
+        for _ in 0..4 {
+            press_code(&mut app, KeyCode::Backspace);
+        }
+        press(&mut app, "poem");
+        press_code(&mut app, KeyCode::Enter);
+        assert_eq!("Read a poem", store.read_task(4).unwrap().text);
+    }
+
+    #[test]
+    fn escape_should_cancel_input() {
+        let store = setup();
+        let mut app = App::new(&store, DisplayStyle::default());
+
+        press(&mut app, "aNever added");
+        press_code(&mut app, KeyCode::Esc);
+
+        assert_eq!(3, store.read_tasks().unwrap().len());
+        assert!(!app.should_quit);
+    }
+
+    #[test]
+    fn search_should_filter_as_you_type() {
+        let store = setup();
+        let mut app = App::new(&store, DisplayStyle::default());
+
+        press(&mut app, "/gym");
+        let screen = render(&mut app);
+        assert!(screen.contains("Go to the gym"));
+        assert!(!screen.contains("Meditate"));
+
+        press_code(&mut app, KeyCode::Esc);
+        assert!(render(&mut app).contains("Meditate"));
+    }
+
+    #[test]
+    fn delete_undo_and_redo_should_work() {
+        let store = setup();
+        let mut app = App::new(&store, DisplayStyle::default());
+
+        press(&mut app, "Gx");
+        assert_eq!(2, store.read_tasks().unwrap().len());
+
+        press(&mut app, "u");
+        assert_eq!(3, store.read_tasks().unwrap().len());
+        assert!(render(&mut app).contains("3) [ ] Meditate"));
+
+        press(&mut app, "r");
+        assert_eq!(2, store.read_tasks().unwrap().len());
+    }
+
+    #[test]
+    fn q_should_quit() {
+        let store = setup();
+        let mut app = App::new(&store, DisplayStyle::default());
+
+        press(&mut app, "q");
+
+        assert!(app.should_quit);
+    }
+}
@@ -0,0 +1,1628 @@
+use list_rs::search::quote_words;
+use list_rs::{Error, Priority, Recurrence, Task, TaskStatus, TaskStore};
+use rusqlite::Connection;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+fn setup_single_task() -> (TaskStore, Task) {
+    let store = TaskStore::open_in_memory().unwrap();
+    let expected = Task {
+        index: Some(1),
+        ..Task::new(1, "", TaskStatus::Undone, vec![], None)
+    };
+    (store, expected)
+}
+
+fn setup_multiple_tasks() -> (TaskStore, Vec<Task>) {
+    let store = TaskStore::open_in_memory().unwrap();
+
+    let tasks = vec![
+        Task {
+            index: Some(1),
+            ..Task::new(
+                1,
+                "Buy fruits, vegetables, and bread.",
+                TaskStatus::Undone,
+                vec![],
+                None,
+            )
+        },
+        Task {
+            index: Some(2),
+            ..Task::new(
+                2,
+                "Change oil and check tire pressure.",
+                TaskStatus::Undone,
+                vec![],
+                None,
+            )
+        },
+        Task {
+            index: Some(3),
+            ..Task::new(
+                3,
+                "Read chapter 5 of the history book.",
+                TaskStatus::Undone,
+                vec![],
+                None,
+            )
+        },
+        Task {
+            index: Some(4),
+            ..Task::new(
+                4,
+                "30 minutes of cardio and weight lifting.",
+                TaskStatus::Undone,
+                vec![],
+                None,
+            )
+        },
+        Task {
+            index: Some(5),
+            ..Task::new(
+                5,
+                "Try out the new pasta recipe.",
+                TaskStatus::Undone,
+                vec![],
+                None,
+            )
+        },
+    ];
+
+    (store, tasks)
+}
+
+#[test]
+fn create_task_should_work() {
+    let (store, expected) = setup_single_task();
+    store.create_task(expected.clone()).unwrap();
+
+    let tasks = store.read_tasks().unwrap();
+    let actual = tasks[0].clone();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn delete_task_should_work() {
+    let (store, expected) = setup_single_task();
+    store.create_task(expected.clone()).unwrap();
+
+    store.delete_task(1).unwrap();
+    let tasks = store.read_tasks().unwrap();
+
+    assert_eq!(0, tasks.len());
+}
+
+#[test]
+fn create_multiple_tasks_should_work() {
+    let (store, expected) = setup_multiple_tasks();
+
+    for task in &expected {
+        store.create_task(task.clone()).unwrap();
+    }
+
+    let actual = store.read_tasks().unwrap();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn update_task_should_work() {
+    let (store, mut expected) = setup_multiple_tasks();
+
+    for task in &expected {
+        store.create_task(task.clone()).unwrap();
+    }
+
+    store.update_task(1, &Task::default()).unwrap();
+
+    let actual = store.read_tasks().unwrap();
+    expected[0] = Task {
+        uuid: expected[0].uuid.clone(),
+        index: Some(1),
+        ..Task::default()
+    };
+
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn undo_create_should_work() {
+    let (store, expected) = setup_single_task();
+
+    store.create_task(expected.clone()).unwrap();
+    store.undo().unwrap();
+
+    let actual = store.read_tasks().unwrap();
+    let expected: Vec<Task> = vec![];
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn undo_delete_should_work() {
+    let (store, expected) = setup_single_task();
+
+    store.create_task(expected.clone()).unwrap();
+    store.delete_task(1).unwrap();
+    store.undo().unwrap();
+
+    let actual = store.read_tasks().unwrap();
+
+    assert_eq!(vec![expected], actual);
+}
+
+#[test]
+fn undo_update_should_work() {
+    let (store, expected) = setup_single_task();
+    store.create_task(expected.clone()).unwrap();
+
+    store
+        .update_task(
+            1,
+            &Task::new(1234, "An updated task", TaskStatus::Undone, vec![], None),
+        )
+        .unwrap();
+
+    store.undo().unwrap();
+
+    let actual = store.read_tasks().unwrap();
+
+    assert_eq!(vec![expected], actual);
+}
+
+#[test]
+fn redo_create_should_work() {
+    let (store, expected) = setup_single_task();
+
+    store.create_task(expected.clone()).unwrap();
+    store.undo().unwrap();
+    store.redo().unwrap();
+
+    let actual = store.read_tasks().unwrap();
+
+    assert_eq!(vec![expected], actual);
+}
+
+#[test]
+fn redo_update_should_work() {
+    let (store, expected) = setup_single_task();
+    store.create_task(expected.clone()).unwrap();
+
+    let now = chrono::Local::now().naive_local();
+
+    store
+        .update_task(
+            1,
+            &Task::new_with_created_at(
+                1,
+                "An updated task",
+                TaskStatus::Archived,
+                vec![],
+                None,
+                now,
+            ),
+        )
+        .unwrap();
+
+    store.undo().unwrap();
+    store.redo().unwrap();
+
+    let actual = store.read_tasks().unwrap();
+
+    assert_eq!(
+        vec![Task {
+            uuid: expected.uuid,
+            ..Task::new_with_created_at(
+                1,
+                "An updated task",
+                TaskStatus::Archived,
+                vec![],
+                None,
+                now
+            )
+        }],
+        actual
+    );
+}
+
+#[test]
+fn redo_delete_should_work() {
+    let (store, expected) = setup_single_task();
+
+    store.create_task(expected.clone()).unwrap();
+    store.delete_task(1).unwrap();
+    store.undo().unwrap();
+    store.redo().unwrap();
+
+    let actual = store.read_tasks().unwrap();
+
+    assert_eq!(0, actual.len());
+}
+
+#[test]
+fn should_keep_undo_history_when_performing_redo() {
+    let (store, expected) = setup_single_task();
+
+    for _ in 0..10 {
+        store.create_task(expected.clone()).unwrap();
+    }
+
+    for _ in 0..10 {
+        store.undo().unwrap();
+    }
+
+    for _ in 0..10 {
+        store.redo().unwrap();
+    }
+
+    let actual = store.read_tasks().unwrap();
+
+    assert_eq!(10, actual.len());
+}
+
+#[test]
+fn undo_and_redo_should_work_with_quotes() {
+    let (store, _) = setup_single_task();
+    let expected = Task {
+        index: Some(1),
+        ..Task::new(1, "Call Bob's dentist", TaskStatus::Undone, vec![], None)
+    };
+
+    store.create_task(expected.clone()).unwrap();
+    store
+        .update_task(
+            1,
+            &Task::new(
+                1,
+                "Don't forget the 'quotes'",
+                TaskStatus::Done,
+                vec![],
+                None,
+            ),
+        )
+        .unwrap();
+    store.delete_task(1).unwrap();
+
+    store.undo().unwrap();
+    store.undo().unwrap();
+    assert_eq!(vec![expected], store.read_tasks().unwrap());
+
+    store.redo().unwrap();
+    assert_eq!(
+        "Don't forget the 'quotes'",
+        store.read_tasks().unwrap()[0].text
+    );
+}
+
+#[test]
+fn legacy_history_should_be_migrated() {
+    let file = NamedTempFile::new().unwrap();
+    let conn = Connection::open(file.path()).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE Tasks (id INTEGER PRIMARY KEY AUTOINCREMENT, text TEXT NOT NULL, status TEXT NOT NULL, tag TEXT, due_date TEXT, created_at TEXT);
+         CREATE TABLE UndoHistory (id INTEGER PRIMARY KEY AUTOINCREMENT, command TEXT NOT NULL, created_at TEXT, task_id INTEGER, task_text TEXT NOT NULL, task_status TEXT NOT NULL, task_tag TEXT, task_due_date TEXT, task_created_at TEXT);
+         CREATE TABLE RedoHistory (id INTEGER PRIMARY KEY AUTOINCREMENT, redo_command TEXT NOT NULL, undo_command TEXT NOT NULL, created_at TEXT, task_id INTEGER, task_text TEXT NOT NULL, task_status TEXT NOT NULL, task_tag TEXT, task_due_date TEXT, task_created_at TEXT);
+         INSERT INTO Tasks VALUES (1, 'Watch Oppenheimer', 'Done', NULL, NULL, '2023-08-01 10:00:00');
+         INSERT INTO UndoHistory VALUES (1, 'DELETE FROM Tasks WHERE id = (SELECT MAX(id) FROM Tasks)', '2023-08-01 10:00:00', 1, 'Watch Oppenheimer', 'Undone', NULL, NULL, '2023-08-01 10:00:00');
+         INSERT INTO UndoHistory VALUES (2, 'UPDATE Tasks SET text = ''Watch Oppenheimer'', status = ''Undone'', tag = NULL, due_date = NULL, created_at = ''2023-08-01 10:00:00'' WHERE id = 1', '2023-08-01 11:00:00', 1, 'Watch Oppenheimer', 'Done', NULL, NULL, '2023-08-01 10:00:00');
+         INSERT INTO RedoHistory VALUES (1, 'DELETE FROM Tasks WHERE id = 2', 'INSERT INTO Tasks (id, text, status, tag, due_date, created_at) VALUES (2, ''Meditate'', ''Undone'', NULL, NULL, ''2023-08-01 12:00:00'')', '2023-08-01 12:00:00', 2, 'Meditate', 'Undone', NULL, NULL, '2023-08-01 12:00:00');
+         INSERT INTO Tasks VALUES (2, 'Meditate', 'Undone', NULL, NULL, '2023-08-01 12:00:00');",
+    )
+    .unwrap();
+
+    drop(conn);
+    let store = TaskStore::open(file.path().to_str().unwrap()).unwrap();
+
+    store.redo().unwrap();
+    assert_eq!(1, store.read_tasks().unwrap().len());
+
+    // Reverts the redone delete, then the status update
+    store.undo().unwrap();
+    store.undo().unwrap();
+    let tasks = store.read_tasks().unwrap();
+    assert_eq!(2, tasks.len());
+    assert_eq!(TaskStatus::Undone, tasks[0].status);
+
+    store.undo().unwrap();
+    let tasks = store.read_tasks().unwrap();
+    assert_eq!(1, tasks.len());
+    assert_eq!("Meditate", tasks[0].text);
+}
+
+#[test]
+fn tags_should_be_stored_and_counted() {
+    let store = TaskStore::open_in_memory().unwrap();
+    let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect();
+
+    store
+        .create_task(Task::new(
+            1,
+            "Write report",
+            TaskStatus::Undone,
+            tags(&["work", "urgent"]),
+            None,
+        ))
+        .unwrap();
+    store
+        .create_task(Task::new(
+            2,
+            "Call Bob",
+            TaskStatus::Undone,
+            tags(&["work"]),
+            None,
+        ))
+        .unwrap();
+
+    assert_eq!(
+        vec![String::from("urgent"), String::from("work")],
+        store.read_task(1).unwrap().tags
+    );
+    assert_eq!(
+        vec![(String::from("urgent"), 1), (String::from("work"), 2)],
+        store.read_tags().unwrap()
+    );
+
+    store.delete_task(1).unwrap();
+    assert_eq!(vec![(String::from("work"), 1)], store.read_tags().unwrap());
+
+    store.undo().unwrap();
+    assert_eq!(2, store.read_tags().unwrap().len());
+}
+
+#[test]
+fn rename_tag_should_be_a_single_undo_step() {
+    let store = TaskStore::open_in_memory().unwrap();
+
+    for i in 1..=3 {
+        store
+            .create_task(Task::new(
+                i,
+                "Task",
+                TaskStatus::Undone,
+                vec![String::from("work")],
+                None,
+            ))
+            .unwrap();
+    }
+
+    assert_eq!(3, store.rename_tag("work", "job").unwrap());
+    assert_eq!(vec![(String::from("job"), 3)], store.read_tags().unwrap());
+
+    store.undo().unwrap();
+    assert_eq!(vec![(String::from("work"), 3)], store.read_tags().unwrap());
+    assert_eq!(3, store.read_tasks().unwrap().len());
+
+    store.redo().unwrap();
+    assert_eq!(vec![(String::from("job"), 3)], store.read_tags().unwrap());
+}
+
+#[test]
+fn batch_should_be_a_single_undo_step() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in &tasks {
+        store.create_task(task.clone()).unwrap();
+    }
+
+    store
+        .batch(|store| {
+            for id in [1, 3] {
+                let mut task = store.read_task(id)?;
+                task.status = TaskStatus::Done;
+                store.update_task(id, &task)?;
+            }
+            store.delete_task(5)
+        })
+        .unwrap();
+    assert_eq!(4, store.read_tasks().unwrap().len());
+
+    store.undo().unwrap();
+    assert_eq!(tasks, store.read_tasks().unwrap());
+
+    store.redo().unwrap();
+    assert_eq!(TaskStatus::Done, store.read_task(3).unwrap().status);
+    assert_eq!(4, store.read_tasks().unwrap().len());
+}
+
+#[test]
+fn failed_batch_should_change_nothing() {
+    let (store, expected) = setup_single_task();
+
+    let result: list_rs::Result<()> = store.batch(|store| {
+        store.create_task(expected.clone())?;
+        store.delete_task(42)
+    });
+
+    assert!(result.is_err());
+    assert_eq!(0, store.read_tasks().unwrap().len());
+    store.undo().unwrap();
+    assert_eq!(0, store.read_tasks().unwrap().len());
+}
+
+#[test]
+fn due_date_should_be_stored_and_restored() {
+    let (store, _) = setup_single_task();
+    let due_date = chrono::NaiveDate::from_ymd_opt(2023, 8, 25)
+        .unwrap()
+        .and_hms_opt(23, 59, 59);
+    let expected = Task {
+        index: Some(1),
+        ..Task::new(1, "Pay rent", TaskStatus::Undone, vec![], due_date)
+    };
+
+    store.create_task(expected.clone()).unwrap();
+    assert_eq!(expected, store.read_task(1).unwrap());
+
+    let mut updated = expected.clone();
+    updated.due_date = None;
+    store.update_task(1, &updated).unwrap();
+    store.undo().unwrap();
+
+    assert_eq!(due_date, store.read_task(1).unwrap().due_date);
+}
+
+#[test]
+fn missing_task_should_be_not_found() {
+    let (store, expected) = setup_single_task();
+
+    assert!(matches!(store.read_task(42), Err(Error::NotFound(42))));
+    assert!(matches!(
+        store.update_task(42, &expected),
+        Err(Error::NotFound(42))
+    ));
+    assert!(matches!(store.delete_task(42), Err(Error::NotFound(42))));
+}
+
+#[test]
+fn unknown_history_operation_should_be_corrupted_history() {
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_str().unwrap();
+    let (_, expected) = setup_single_task();
+
+    let store = TaskStore::open(path).unwrap();
+    store.create_task(expected.clone()).unwrap();
+    store.delete_task(1).unwrap();
+    drop(store);
+
+    let conn = Connection::open(path).unwrap();
+    conn.execute("UPDATE UndoHistory SET operation = 'Rename'", [])
+        .unwrap();
+    drop(conn);
+
+    let store = TaskStore::open(path).unwrap();
+    assert!(matches!(store.undo(), Err(Error::CorruptedHistory(_))));
+    assert_eq!(0, store.read_tasks().unwrap().len());
+}
+
+#[test]
+fn priority_should_be_stored_and_restored() {
+    let (store, expected) = setup_single_task();
+    let expected = Task {
+        priority: Some(Priority::High),
+        ..expected
+    };
+
+    store.create_task(expected.clone()).unwrap();
+    assert_eq!(expected, store.read_task(1).unwrap());
+
+    let mut updated = expected.clone();
+    updated.priority = Some(Priority::Low);
+    store.update_task(1, &updated).unwrap();
+    assert_eq!(Some(Priority::Low), store.read_task(1).unwrap().priority);
+
+    store.undo().unwrap();
+    assert_eq!(Some(Priority::High), store.read_task(1).unwrap().priority);
+}
+
+#[test]
+fn query_should_filter_tasks() {
+    let store = TaskStore::open_in_memory().unwrap();
+    let now = chrono::NaiveDate::from_ymd_opt(2023, 8, 16)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    let due = |day| {
+        chrono::NaiveDate::from_ymd_opt(2023, 8, day)
+            .unwrap()
+            .and_hms_opt(23, 59, 59)
+    };
+    let tasks = vec![
+        Task::new(
+            1,
+            "Write the weekly report",
+            TaskStatus::Undone,
+            vec![String::from("work")],
+            due(17),
+        ),
+        Task::new(2, "Call mum", TaskStatus::Undone, vec![], due(25)),
+        Task {
+            priority: Some(Priority::High),
+            ..Task::new(3, "Fix 100% of bugs", TaskStatus::Done, vec![], None)
+        },
+        Task::new(
+            4,
+            "Plan the report template",
+            TaskStatus::Undone,
+            vec![String::from("work")],
+            None,
+        ),
+    ];
+    for task in tasks {
+        store.create_task(task).unwrap();
+    }
+
+    let query_ids = |query: &str| {
+        let expr = list_rs::query::Expr::parse(query, now).unwrap();
+        let mut ids: Vec<i32> = store
+            .query(&expr)
+            .unwrap()
+            .iter()
+            .map(|task| task.id)
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(
+        vec![1, 4],
+        query_ids("status:undone and (tag:work or due<friday) and not tag:none")
+    );
+    assert_eq!(vec![2], query_ids("status:undone due>friday"));
+    assert_eq!(vec![1, 4], query_ids("text~\"REPORT\""));
+    assert_eq!(vec![3], query_ids("text~100%"));
+    assert_eq!(vec![1], query_ids("due:thursday"));
+    assert_eq!(vec![3, 4], query_ids("due:none"));
+    assert_eq!(vec![1, 2, 4], query_ids("not priority>=low"));
+    assert_eq!(vec![2, 3], query_ids("not tag:work"));
+    assert_eq!(vec![2, 4], query_ids("id>1 and id<=4 and not status:done"));
+}
+
+fn search_ids(store: &TaskStore, terms: &str) -> Vec<i32> {
+    store
+        .search(terms, None)
+        .unwrap()
+        .iter()
+        .map(|found| found.task.id)
+        .collect()
+}
+
+#[test]
+fn search_should_support_phrases_prefixes_and_operators() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in tasks {
+        store.create_task(task).unwrap();
+    }
+
+    assert_eq!(vec![3], search_ids(&store, "history"));
+    assert_eq!(vec![3], search_ids(&store, "\"history book\""));
+    assert!(search_ids(&store, "\"book history\"").is_empty());
+    assert_eq!(vec![1], search_ids(&store, "veg*"));
+    assert_eq!(vec![2, 4], {
+        let mut ids = search_ids(&store, "oil OR cardio");
+        ids.sort();
+        ids
+    });
+    assert_eq!(vec![4], search_ids(&store, "and NOT oil NOT fruits"));
+
+    let expr = list_rs::query::Expr::parse("id>2", chrono::Local::now().naive_local()).unwrap();
+    let found = store.search("and", Some(&expr)).unwrap();
+    assert_eq!(vec![4], found.iter().map(|f| f.task.id).collect::<Vec<_>>());
+}
+
+#[test]
+fn search_should_rank_and_highlight_matches() {
+    let store = TaskStore::open_in_memory().unwrap();
+    for text in [
+        "Report the broken printer",
+        "Write the report, then proofread the report",
+    ] {
+        store
+            .create_task(Task::new(1, text, TaskStatus::Undone, vec![], None))
+            .unwrap();
+    }
+
+    let found = store.search("report", None).unwrap();
+
+    assert_eq!(
+        vec![2, 1],
+        found.iter().map(|f| f.task.id).collect::<Vec<_>>()
+    );
+    assert!(found[0].score > found[1].score);
+    assert_eq!(
+        "Write the [report], then proofread the [report]",
+        found[0].marked("[", "]")
+    );
+}
+
+#[test]
+fn search_index_should_follow_changes() {
+    let (store, mut expected) = setup_single_task();
+    expected.text = String::from("Buy milk");
+    store.create_task(expected.clone()).unwrap();
+
+    expected.text = String::from("Buy bread");
+    store.update_task(1, &expected).unwrap();
+    assert!(search_ids(&store, "milk").is_empty());
+    assert_eq!(vec![1], search_ids(&store, "bread"));
+
+    store.undo().unwrap();
+    assert_eq!(vec![1], search_ids(&store, "milk"));
+
+    store.delete_task(1).unwrap();
+    assert!(search_ids(&store, "milk").is_empty());
+    store.undo().unwrap();
+    assert_eq!(vec![1], search_ids(&store, "milk"));
+}
+
+#[test]
+fn invalid_search_should_be_invalid_input() {
+    let (store, _) = setup_single_task();
+
+    assert!(matches!(
+        store.search("\"unterminated", None),
+        Err(Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        store.search("colour:red", None),
+        Err(Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn fuzzy_search_should_rank_by_similarity() {
+    let store = TaskStore::open_in_memory().unwrap();
+    for text in ["Watch Oppenheimer", "Open the window", "Call mum"] {
+        store
+            .create_task(Task::new(1, text, TaskStatus::Undone, vec![], None))
+            .unwrap();
+    }
+
+    let found = store.fuzzy_search("opeheimer", None).unwrap();
+    assert_eq!(vec![1], found.iter().map(|f| f.task.id).collect::<Vec<_>>());
+    assert_eq!("Watch [Op]p[e]n[heimer]", found[0].marked("[", "]"));
+
+    let found = store.fuzzy_search("ope", None).unwrap();
+    assert_eq!(
+        vec![2, 1],
+        found.iter().map(|f| f.task.id).collect::<Vec<_>>()
+    );
+
+    let expr = list_rs::query::Expr::parse("id:1", chrono::Local::now().naive_local()).unwrap();
+    let found = store.fuzzy_search("ope", Some(&expr)).unwrap();
+    assert_eq!(vec![1], found.iter().map(|f| f.task.id).collect::<Vec<_>>());
+}
+
+#[test]
+fn completing_recurring_task_should_add_next_occurrence() {
+    let (store, expected) = setup_single_task();
+    let today = chrono::Local::now().date_naive();
+    let expected = Task {
+        text: String::from("Take vitamins"),
+        due_date: today.and_hms_opt(23, 59, 59),
+        recurrence: Some(Recurrence::Daily),
+        ..expected
+    };
+    store.create_task(expected.clone()).unwrap();
+
+    let next_id = store.complete_task(1).unwrap().unwrap();
+
+    let done = store.read_task(1).unwrap();
+    assert_eq!(TaskStatus::Done, done.status);
+    assert_eq!(None, done.recurrence);
+    let next = store.read_task(next_id).unwrap();
+    assert_eq!("Take vitamins", next.text);
+    assert_eq!(TaskStatus::Undone, next.status);
+    assert_eq!(Some(Recurrence::Daily), next.recurrence);
+    assert_eq!(
+        (today + chrono::Duration::days(1)).and_hms_opt(23, 59, 59),
+        next.due_date
+    );
+
+    // Completing and the new occurrence are a single undo step
+    store.undo().unwrap();
+    assert_eq!(vec![expected], store.read_tasks().unwrap());
+}
+
+#[test]
+fn completing_task_without_recurrence_should_add_nothing() {
+    let (store, expected) = setup_single_task();
+    store.create_task(expected).unwrap();
+
+    assert_eq!(None, store.complete_task(1).unwrap());
+    assert_eq!(1, store.read_tasks().unwrap().len());
+    assert!(matches!(store.complete_task(2), Err(Error::NotFound(2))));
+}
+
+/// A project with two subtasks, the first of which has a subtask of its own.
+fn setup_subtasks(store: &TaskStore) {
+    let task = |text: &str, parent_id| Task {
+        parent_id,
+        ..Task::new(1, text, TaskStatus::Undone, vec![], None)
+    };
+    store.create_task(task("Move house", None)).unwrap();
+    store.create_task(task("Pack", Some(1))).unwrap();
+    store.create_task(task("Book a van", Some(1))).unwrap();
+    store.create_task(task("Buy boxes", Some(2))).unwrap();
+}
+
+#[test]
+fn subtree_should_list_parents_before_children() {
+    let store = TaskStore::open_in_memory().unwrap();
+    setup_subtasks(&store);
+
+    let ids = |tasks: Vec<Task>| tasks.iter().map(|task| task.id).collect::<Vec<_>>();
+    assert_eq!(vec![2, 3, 4], ids(store.read_subtree(1).unwrap()));
+    assert_eq!(vec![4], ids(store.read_subtree(2).unwrap()));
+    assert!(store.read_subtree(4).unwrap().is_empty());
+    assert_eq!(Some(2), store.read_task(4).unwrap().parent_id);
+}
+
+#[test]
+fn parents_should_exist_and_not_form_cycles() {
+    let store = TaskStore::open_in_memory().unwrap();
+    setup_subtasks(&store);
+
+    let orphan = Task {
+        parent_id: Some(9),
+        ..Task::new(1, "Orphan", TaskStatus::Undone, vec![], None)
+    };
+    assert!(matches!(store.create_task(orphan), Err(Error::NotFound(9))));
+
+    let mut project = store.read_task(1).unwrap();
+    project.parent_id = Some(4);
+    assert!(matches!(
+        store.update_task(1, &project),
+        Err(Error::InvalidInput(_))
+    ));
+    project.parent_id = Some(1);
+    assert!(matches!(
+        store.update_task(1, &project),
+        Err(Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn deleting_subtree_should_be_undone_at_once() {
+    let store = TaskStore::open_in_memory().unwrap();
+    setup_subtasks(&store);
+    let before = store.read_tasks().unwrap();
+
+    assert!(matches!(store.delete_task(1), Err(Error::InvalidInput(_))));
+    assert_eq!(vec![1, 2, 3, 4], store.delete_subtree(1).unwrap());
+    assert!(store.read_tasks().unwrap().is_empty());
+
+    store.undo().unwrap();
+    assert_eq!(before, store.read_tasks().unwrap());
+
+    store.redo().unwrap();
+    assert!(store.read_tasks().unwrap().is_empty());
+}
+
+#[test]
+fn dependencies_should_not_form_cycles() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in tasks {
+        store.create_task(task).unwrap();
+    }
+
+    store.add_dependency(3, 2).unwrap();
+    store.add_dependency(2, 1).unwrap();
+    assert_eq!(vec![2], store.read_task(3).unwrap().depends_on);
+
+    assert!(matches!(
+        store.add_dependency(1, 3),
+        Err(Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        store.add_dependency(1, 1),
+        Err(Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        store.add_dependency(1, 9),
+        Err(Error::NotFound(9))
+    ));
+    assert!(matches!(
+        store.remove_dependency(1, 2),
+        Err(Error::InvalidInput(_))
+    ));
+    assert!(store.read_task(1).unwrap().depends_on.is_empty());
+}
+
+#[test]
+fn blocked_tasks_should_wait_for_undone_dependencies() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in tasks {
+        store.create_task(task).unwrap();
+    }
+    store.add_dependency(3, 1).unwrap();
+    store.add_dependency(3, 2).unwrap();
+
+    let now = chrono::Local::now().naive_local();
+    let ids = |query: &str| {
+        let mut ids: Vec<i32> = store
+            .query(&list_rs::query::Expr::parse(query, now).unwrap())
+            .unwrap()
+            .iter()
+            .map(|task| task.id)
+            .collect();
+        ids.sort();
+        ids
+    };
+    assert_eq!(vec![3], ids("blocked:yes"));
+    assert_eq!(
+        vec![1, 2],
+        store
+            .read_blockers(3)
+            .unwrap()
+            .iter()
+            .map(|task| task.id)
+            .collect::<Vec<_>>()
+    );
+
+    store.complete_task(1).unwrap();
+    assert_eq!(vec![3], ids("blocked:yes"));
+    store.complete_task(2).unwrap();
+    assert!(ids("blocked:yes").is_empty());
+    assert_eq!(vec![3, 4, 5], ids("status:undone blocked:no"));
+}
+
+#[test]
+fn dependencies_should_be_undone_and_redone() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in tasks {
+        store.create_task(task).unwrap();
+    }
+
+    store.add_dependency(3, 1).unwrap();
+    store.undo().unwrap();
+    assert!(store.read_task(3).unwrap().depends_on.is_empty());
+    store.redo().unwrap();
+    assert_eq!(vec![1], store.read_task(3).unwrap().depends_on);
+
+    // Removing a task drops it from the dependencies in the same step
+    store.delete_task(1).unwrap();
+    assert!(store.read_task(3).unwrap().depends_on.is_empty());
+    store.undo().unwrap();
+    assert_eq!(vec![1], store.read_task(3).unwrap().depends_on);
+    assert_eq!(
+        vec![1],
+        store
+            .read_blockers(3)
+            .unwrap()
+            .iter()
+            .map(|task| task.id)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn projects_should_count_their_tasks_by_status() {
+    let store = TaskStore::open_in_memory().unwrap();
+    let work = store.create_project("Work").unwrap();
+    store.create_project(" Home ").unwrap();
+    let task = |text: &str, status| Task {
+        project_id: Some(work),
+        ..Task::new(1, text, status, vec![], None)
+    };
+    store
+        .create_task(task("Write report", TaskStatus::Undone))
+        .unwrap();
+    store
+        .create_task(task("Send invoice", TaskStatus::Done))
+        .unwrap();
+    store
+        .create_task(Task::new(1, "Loose", TaskStatus::Undone, vec![], None))
+        .unwrap();
+
+    let projects = store.read_projects().unwrap();
+    assert_eq!(
+        vec!["Home", "Work"],
+        projects
+            .iter()
+            .map(|project| project.name.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        (1, 1, 0),
+        (projects[1].undone, projects[1].done, projects[1].archived)
+    );
+    assert_eq!(0, projects[0].total());
+
+    let now = chrono::Local::now().naive_local();
+    let query = list_rs::query::Expr::parse("project:Work", now).unwrap();
+    assert_eq!(2, store.query(&query).unwrap().len());
+    let query = list_rs::query::Expr::parse("project:none", now).unwrap();
+    assert_eq!(
+        vec![3],
+        store
+            .query(&query)
+            .unwrap()
+            .iter()
+            .map(|task| task.id)
+            .collect::<Vec<_>>()
+    );
+
+    let orphan = Task {
+        project_id: Some(9),
+        ..Task::new(1, "Orphan", TaskStatus::Undone, vec![], None)
+    };
+    assert!(matches!(
+        store.create_task(orphan),
+        Err(Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn projects_should_be_renamed_used_and_removed() {
+    let store = TaskStore::open_in_memory().unwrap();
+    let work = store.create_project("Work").unwrap();
+    assert!(matches!(
+        store.create_project("Work"),
+        Err(Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        store.read_project("Play"),
+        Err(Error::InvalidInput(_))
+    ));
+
+    store.set_active_project(Some("Work")).unwrap();
+    store.rename_project("Work", "Office").unwrap();
+    assert_eq!("Office", store.active_project().unwrap().unwrap().name);
+
+    let id = store
+        .create_task(Task {
+            project_id: Some(work),
+            ..Task::new(1, "Write report", TaskStatus::Undone, vec![], None)
+        })
+        .unwrap();
+    assert!(matches!(
+        store.delete_project("Office"),
+        Err(Error::InvalidInput(_))
+    ));
+
+    // Tasks brought back after their project was removed have none
+    store.delete_task(id).unwrap();
+    store.delete_project("Office").unwrap();
+    assert_eq!(None, store.active_project().unwrap());
+    store.undo().unwrap();
+    assert_eq!(None, store.read_task(id).unwrap().project_id);
+}
+
+#[test]
+fn limited_history_should_forget_oldest_steps() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in &tasks {
+        store.create_task(task.clone()).unwrap();
+    }
+    store.undo().unwrap();
+
+    // Two of the four steps left to undo are kept, the one to redo stays too
+    assert_eq!(2, store.limit_history(2).unwrap());
+    assert_eq!(0, store.limit_history(2).unwrap());
+
+    store.redo().unwrap();
+    for _ in 0..4 {
+        store.undo().unwrap();
+    }
+    let actual = store.read_tasks().unwrap();
+    assert_eq!(tasks[..2].to_vec(), actual);
+}
+
+#[test]
+fn history_should_list_steps_and_undo_several_at_once() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in &tasks[..3] {
+        store.create_task(task.clone()).unwrap();
+    }
+    let mut task = store.read_task(2).unwrap();
+    task.status = TaskStatus::Done;
+    store.update_task(2, &task).unwrap();
+    store.undo().unwrap();
+
+    let history = store.read_history().unwrap();
+    let steps: Vec<(bool, usize)> = history
+        .iter()
+        .map(|step| (step.undone, step.entries.len()))
+        .collect();
+    assert_eq!(vec![(false, 1), (false, 1), (false, 1), (true, 1)], steps);
+    assert!(history.iter().all(|step| step.created_at.is_some()));
+    assert_eq!(
+        "Changed task 2 \"Change oil and check tire pressure.\": status Undone → Done",
+        history[3].entries[0].describe(&store.read_history_names().unwrap())
+    );
+
+    // Undoing more steps than recorded changes nothing
+    assert!(matches!(store.undo_steps(4), Err(Error::InvalidInput(_))));
+    assert_eq!(3, store.read_tasks().unwrap().len());
+
+    let changes = store.undo_to(history[1].id).unwrap();
+    let names = store.read_history_names().unwrap();
+    let descriptions: Vec<String> = changes
+        .iter()
+        .map(|change| change.describe(&names))
+        .collect();
+    assert_eq!(
+        vec![
+            "Removed task 3 \"Read chapter 5 of the history book.\"",
+            "Removed task 2 \"Change oil and check tire pressure.\"",
+        ],
+        descriptions
+    );
+    assert_eq!(1, store.read_tasks().unwrap().len());
+    assert!(matches!(
+        store.undo_to(history[1].id),
+        Err(Error::InvalidInput(_))
+    ));
+
+    assert_eq!(2, store.redo_steps(2).unwrap().len());
+    assert_eq!(3, store.read_tasks().unwrap().len());
+}
+
+/// Opens a store in a temporary file where statements matching `event`, e.g.
+/// `INSERT ON UndoHistory`, fail.
+fn setup_failing_store(file: &NamedTempFile, event: &str) -> TaskStore {
+    let store = TaskStore::open(file.path()).unwrap();
+    let conn = Connection::open(file.path()).unwrap();
+    conn.execute_batch(&format!(
+        "CREATE TRIGGER InjectedFailure BEFORE {} BEGIN SELECT RAISE(ABORT, 'injected failure'); END;",
+        event
+    ))
+    .unwrap();
+    store
+}
+
+#[test]
+fn failing_history_should_not_leave_changes_behind() {
+    let file = NamedTempFile::new().unwrap();
+    let store = setup_failing_store(&file, "INSERT ON UndoHistory");
+    let (_, expected) = setup_single_task();
+
+    // The task is inserted before the history entry fails
+    assert!(matches!(
+        store.create_task(expected.clone()),
+        Err(Error::Storage(_))
+    ));
+    assert_eq!(0, store.read_tasks().unwrap().len());
+    assert!(store.read_history().unwrap().is_empty());
+
+    let conn = Connection::open(file.path()).unwrap();
+    let snapshots: i64 = conn
+        .query_row("SELECT COUNT(*) FROM TaskSnapshots", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(0, snapshots);
+}
+
+#[test]
+fn failing_undo_should_keep_task_and_history() {
+    let file = NamedTempFile::new().unwrap();
+    let store = TaskStore::open(file.path()).unwrap();
+    let (_, expected) = setup_single_task();
+    store.create_task(expected.clone()).unwrap();
+    drop(store);
+
+    // The task is removed before the step moves to the redo history
+    let store = setup_failing_store(&file, "INSERT ON RedoHistory");
+    assert!(matches!(store.undo(), Err(Error::Storage(_))));
+    assert_eq!(vec![expected], store.read_tasks().unwrap());
+    let history = store.read_history().unwrap();
+    assert_eq!(1, history.len());
+    assert!(!history[0].undone);
+}
+
+#[test]
+fn failing_step_should_roll_back_the_whole_batch() {
+    let file = NamedTempFile::new().unwrap();
+    let store = setup_failing_store(
+        &file,
+        "UPDATE ON Tasks WHEN NEW.status = 'Done' AND OLD.id = 3",
+    );
+    let (_, tasks) = setup_multiple_tasks();
+    for task in &tasks {
+        store.create_task(task.clone()).unwrap();
+    }
+
+    let result = store.batch(|store| {
+        for id in 1..=5 {
+            store.complete_task(id)?;
+        }
+        Ok(())
+    });
+    assert!(matches!(result, Err(Error::Storage(_))));
+    let tasks = store.read_tasks().unwrap();
+    assert!(tasks.iter().all(|task| task.status == TaskStatus::Undone));
+    assert_eq!(5, store.read_history().unwrap().len());
+}
+
+#[test]
+fn failing_nested_batch_should_only_roll_back_itself() {
+    let file = NamedTempFile::new().unwrap();
+    let store = setup_failing_store(&file, "INSERT ON Tasks WHEN NEW.text = 'Fail'");
+
+    store
+        .batch(|store| {
+            store.create_task(Task::new(1, "Keep", TaskStatus::Undone, vec![], None))?;
+            let nested = store.batch(|store| {
+                store.create_task(Task::new(1, "Drop", TaskStatus::Undone, vec![], None))?;
+                store.create_task(Task::new(1, "Fail", TaskStatus::Undone, vec![], None))
+            });
+            assert!(nested.is_err());
+            Ok(())
+        })
+        .unwrap();
+
+    let texts: Vec<String> = store
+        .read_tasks()
+        .unwrap()
+        .into_iter()
+        .map(|task| task.text)
+        .collect();
+    assert_eq!(vec!["Keep"], texts);
+
+    // The outer batch is still a single step
+    store.undo().unwrap();
+    assert!(store.read_tasks().unwrap().is_empty());
+    assert!(store.read_history().unwrap().iter().all(|step| step.undone));
+}
+
+#[test]
+fn numbers_of_finished_tasks_should_be_reused() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in tasks.iter().take(3) {
+        store.create_task(task.clone()).unwrap();
+    }
+
+    store.complete_task(2).unwrap();
+    assert_eq!(None, store.read_task(2).unwrap().index);
+
+    let id = store.create_task(tasks[3].clone()).unwrap();
+    assert_eq!(4, id);
+    assert_eq!(Some(2), store.read_task(id).unwrap().index);
+    assert_eq!(id, store.find_task("2").unwrap().id);
+
+    store.update_task(2, &tasks[1]).unwrap();
+    assert_eq!(Some(4), store.read_task(2).unwrap().index);
+}
+
+#[test]
+fn numbers_should_be_restored_by_undo_and_redo() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in tasks.iter().take(2) {
+        store.create_task(task.clone()).unwrap();
+    }
+
+    store.delete_task(1).unwrap();
+    let id = store.create_task(tasks[2].clone()).unwrap();
+    assert_eq!(Some(1), store.read_task(id).unwrap().index);
+
+    store.undo_steps(2).unwrap();
+    let task = store.read_task(1).unwrap();
+    assert_eq!((Some(1), tasks[0].uuid.clone()), (task.index, task.uuid));
+
+    store.redo_steps(2).unwrap();
+    assert_eq!(Some(1), store.read_task(id).unwrap().index);
+    assert_eq!(tasks[2].uuid, store.find_task("1").unwrap().uuid);
+
+    store.complete_task(2).unwrap();
+    let changes = store.undo().unwrap();
+    assert_eq!(Some(2), store.read_task(2).unwrap().index);
+    assert_eq!(
+        "Changed task 2 \"Change oil and check tire pressure.\": status Done → Undone",
+        changes[0].describe(&store.read_history_names().unwrap())
+    );
+}
+
+#[test]
+fn tasks_should_be_found_by_uuid_prefix() {
+    let store = TaskStore::open_in_memory().unwrap();
+    for uuid in [
+        "aaaa1111-0000-4000-8000-000000000000",
+        "aaaa2222-0000-4000-8000-000000000000",
+    ] {
+        let task = Task {
+            uuid: uuid.to_string(),
+            ..Task::new(1, "Task", TaskStatus::Archived, vec![], None)
+        };
+        store.create_task(task).unwrap();
+    }
+
+    assert_eq!(2, store.find_task("AAAA22").unwrap().id);
+    assert_eq!(
+        1,
+        store
+            .find_task("aaaa1111-0000-4000-8000-000000000000")
+            .unwrap()
+            .id
+    );
+    assert!(matches!(
+        store.find_task("aaaa"),
+        Err(Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        store.find_task("bbbb"),
+        Err(Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        store.find_task("milk"),
+        Err(Error::InvalidInput(_))
+    ));
+    assert!(matches!(store.find_task("1"), Err(Error::NotFound(1))));
+
+    // A uuid can't be taken twice
+    let task = Task {
+        uuid: String::from("aaaa1111-0000-4000-8000-000000000000"),
+        ..Task::new(1, "Task", TaskStatus::Undone, vec![], None)
+    };
+    let id = store.create_task(task).unwrap();
+    assert_ne!(
+        "aaaa1111-0000-4000-8000-000000000000",
+        store.read_task(id).unwrap().uuid
+    );
+}
+
+#[test]
+fn parallel_writers_should_not_lose_changes() {
+    const WRITERS: usize = 8;
+    const TASKS: usize = 25;
+    let file = NamedTempFile::new().unwrap();
+
+    // Every writer opens its own store, so even the schema is created by
+    // racing connections
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| {
+            let path = file.path().to_path_buf();
+            std::thread::spawn(move || {
+                let store = TaskStore::open(&path).unwrap();
+                for n in 0..TASKS {
+                    let text = format!("Task {} of writer {}", n, writer);
+                    let id = store
+                        .create_task(Task::new(1, &text, TaskStatus::Undone, vec![], None))
+                        .unwrap();
+                    if n % 2 == 0 {
+                        store.complete_task(id).unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let store = TaskStore::open(file.path()).unwrap();
+    let tasks = store.read_tasks().unwrap();
+    assert_eq!(WRITERS * TASKS, tasks.len());
+    let done = tasks
+        .iter()
+        .filter(|task| task.status == TaskStatus::Done)
+        .count();
+    assert_eq!(WRITERS * TASKS.div_ceil(2), done);
+    assert_eq!(
+        WRITERS * (TASKS + TASKS.div_ceil(2)),
+        store.read_history().unwrap().len()
+    );
+
+    let conn = Connection::open(file.path()).unwrap();
+    let (rows, batches): (usize, usize) = conn
+        .query_row(
+            "SELECT COUNT(*), COUNT(DISTINCT batch) FROM UndoHistory",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    // One row per change, each in a step of its own
+    assert_eq!(WRITERS * (TASKS + TASKS.div_ceil(2)), rows);
+    assert_eq!(rows, batches);
+    let journal_mode: String = conn
+        .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!("wal", journal_mode);
+}
+
+#[test]
+fn new_change_should_branch_the_redo_history() {
+    let store = TaskStore::open_in_memory().unwrap();
+    for text in ["A", "B", "C"] {
+        store
+            .create_task(Task::new(1, text, TaskStatus::Undone, vec![], None))
+            .unwrap();
+    }
+    store.undo().unwrap();
+    store
+        .create_task(Task::new(1, "D", TaskStatus::Undone, vec![], None))
+        .unwrap();
+
+    // C can't be redone on top of D
+    assert!(store.redo().unwrap().is_empty());
+    assert!(store
+        .read_history()
+        .unwrap()
+        .iter()
+        .all(|step| !step.undone));
+    let branches = store.read_branches().unwrap();
+    assert_eq!(1, branches.len());
+    assert_eq!(Some(2), branches[0].fork);
+    assert_eq!(
+        vec![3],
+        branches[0]
+            .steps
+            .iter()
+            .map(|step| step.id)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        Some(String::from("C")),
+        branches[0].steps[0].entries[0]
+            .after
+            .as_ref()
+            .map(|task| task.text.clone())
+    );
+}
+
+#[test]
+fn redo_branch_should_switch_to_the_branch() {
+    let store = TaskStore::open_in_memory().unwrap();
+    for text in ["A", "B", "C"] {
+        store
+            .create_task(Task::new(1, text, TaskStatus::Undone, vec![], None))
+            .unwrap();
+    }
+    store.undo_steps(2).unwrap();
+    store
+        .create_task(Task::new(1, "D", TaskStatus::Undone, vec![], None))
+        .unwrap();
+
+    // D is undone and left behind, B and C are redone
+    let changes = store.redo_branch(1, 2).unwrap();
+    assert_eq!(3, changes.len());
+    let texts: Vec<String> = store
+        .read_tasks()
+        .unwrap()
+        .into_iter()
+        .map(|task| task.text)
+        .collect();
+    assert_eq!(vec!["A", "B", "C"], texts);
+    let branches = store.read_branches().unwrap();
+    assert_eq!(1, branches.len());
+    assert_eq!(Some(1), branches[0].fork);
+    assert_eq!(
+        vec![4],
+        branches[0]
+            .steps
+            .iter()
+            .map(|step| step.id)
+            .collect::<Vec<_>>()
+    );
+
+    // E is made after B, then B and E are left behind for F
+    store.undo().unwrap();
+    store
+        .create_task(Task::new(1, "E", TaskStatus::Undone, vec![], None))
+        .unwrap();
+    store.undo_steps(2).unwrap();
+    store
+        .create_task(Task::new(1, "F", TaskStatus::Undone, vec![], None))
+        .unwrap();
+    let branches = store.read_branches().unwrap();
+    assert_eq!(
+        vec![(2, Some(1)), (3, Some(2)), (4, Some(1))],
+        branches
+            .iter()
+            .map(|branch| (branch.id, branch.fork))
+            .collect::<Vec<_>>()
+    );
+
+    // Branch 3 continues from B, which is only in branch 4
+    assert!(matches!(
+        store.redo_branch(3, 1),
+        Err(Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        store.redo_branch(9, 1),
+        Err(Error::InvalidInput(_))
+    ));
+    store.redo_branch(4, 1).unwrap();
+    store.redo_branch(3, 1).unwrap();
+    let texts: Vec<String> = store
+        .read_tasks()
+        .unwrap()
+        .into_iter()
+        .map(|task| task.text)
+        .collect();
+    assert_eq!(vec!["A", "B", "C"], texts);
+}
+
+#[test]
+fn limited_history_should_drop_unreachable_branches() {
+    let store = TaskStore::open_in_memory().unwrap();
+    for text in ["A", "B", "C"] {
+        store
+            .create_task(Task::new(1, text, TaskStatus::Undone, vec![], None))
+            .unwrap();
+    }
+    store.undo().unwrap();
+    store
+        .create_task(Task::new(1, "D", TaskStatus::Undone, vec![], None))
+        .unwrap();
+
+    store.limit_history(2).unwrap();
+    assert_eq!(1, store.read_branches().unwrap().len());
+    store.limit_history(1).unwrap();
+    assert!(store.read_branches().unwrap().is_empty());
+}
+
+#[test]
+fn old_history_should_be_forgotten() {
+    let (store, tasks) = setup_multiple_tasks();
+    for task in &tasks {
+        store.create_task(task.clone()).unwrap();
+    }
+    store.undo_steps(2).unwrap();
+    store
+        .create_task(Task::new(1, "New", TaskStatus::Undone, vec![], None))
+        .unwrap();
+
+    let now = chrono::Local::now().naive_local();
+    assert_eq!(
+        0,
+        store
+            .forget_history_before(now - chrono::Duration::days(1))
+            .unwrap()
+    );
+    assert_eq!(4, store.read_history().unwrap().len());
+    assert_eq!(1, store.read_branches().unwrap().len());
+
+    // Every step and the branch left behind are older than a minute from now
+    assert_eq!(
+        4,
+        store
+            .forget_history_before(now + chrono::Duration::minutes(1))
+            .unwrap()
+    );
+    assert!(store.read_history().unwrap().is_empty());
+    assert!(store.read_branches().unwrap().is_empty());
+    assert_eq!(4, store.read_tasks().unwrap().len());
+}
+
+#[test]
+fn pruned_history_should_give_space_back() {
+    let file = NamedTempFile::new().unwrap();
+    let store = TaskStore::open(file.path()).unwrap();
+    let mut task = Task::new(1, &"x".repeat(1000), TaskStatus::Undone, vec![], None);
+    let id = store.create_task(task.clone()).unwrap();
+    for status in [TaskStatus::Done, TaskStatus::Undone].repeat(50) {
+        task.status = status;
+        store.update_task(id, &task).unwrap();
+    }
+
+    // Snapshots of the task being done and undone again are shared
+    let conn = Connection::open(file.path()).unwrap();
+    let snapshots: i64 = conn
+        .query_row("SELECT COUNT(*) FROM TaskSnapshots", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(2, snapshots);
+
+    store.limit_history(0).unwrap();
+    let snapshots: i64 = conn
+        .query_row("SELECT COUNT(*) FROM TaskSnapshots", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(0, snapshots);
+    assert!(store.unused_space().unwrap() > 0);
+    let size = store.database_size().unwrap();
+    assert!(store.vacuum().unwrap() > 0);
+    assert!(store.database_size().unwrap() < size);
+    assert_eq!(0, store.unused_space().unwrap());
+}
+
+#[test]
+fn panicking_batch_should_not_leave_its_step_open() {
+    let store = TaskStore::open_in_memory().unwrap();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        store.batch(|store| -> list_rs::Result<()> {
+            store.create_task(Task::new(1, "Lost", TaskStatus::Undone, vec![], None))?;
+            panic!("batch failed");
+        })
+    }));
+    assert!(result.is_err());
+
+    for text in ["A", "B"] {
+        store
+            .create_task(Task::new(1, text, TaskStatus::Undone, vec![], None))
+            .unwrap();
+    }
+    assert_eq!(2, store.read_tasks().unwrap().len());
+    assert_eq!(2, store.read_history().unwrap().len());
+}
+
+#[test]
+fn history_within_limits_should_be_kept_without_locking() {
+    let file = NamedTempFile::new().unwrap();
+    let writer = TaskStore::open(file.path()).unwrap();
+    let pruner = TaskStore::open_with_timeout(file.path(), Duration::ZERO).unwrap();
+    for text in ["A", "B"] {
+        writer
+            .create_task(Task::new(1, text, TaskStatus::Undone, vec![], None))
+            .unwrap();
+    }
+
+    let now = chrono::Local::now().naive_local();
+    writer
+        .batch(|_| {
+            // The writer holds the lock, pruning would fail right away
+            assert_eq!(0, pruner.limit_history(2).unwrap());
+            assert_eq!(
+                0,
+                pruner
+                    .forget_history_before(now - chrono::Duration::days(1))
+                    .unwrap()
+            );
+            assert!(pruner.limit_history(1).is_err());
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(1, pruner.limit_history(1).unwrap());
+}
+
+#[test]
+fn history_names_should_include_removed_tasks_and_projects() {
+    let (store, tasks) = setup_multiple_tasks();
+    let project = store.create_project("Home").unwrap();
+    let parent = store.create_task(tasks[0].clone()).unwrap();
+    let removed = store.create_task(tasks[1].clone()).unwrap();
+    let uuid = store.read_task(removed).unwrap().uuid;
+    store.delete_task(removed).unwrap();
+
+    let names = store.read_history_names().unwrap();
+    assert_eq!(Some(&String::from("1")), names.tasks.get(&parent));
+    assert_eq!(
+        Some(&uuid[..8]),
+        names.tasks.get(&removed).map(String::as_str)
+    );
+    assert_eq!(Some(&String::from("Home")), names.projects.get(&project));
+}
+
+#[test]
+fn redone_steps_should_keep_their_age() {
+    let store = TaskStore::open_in_memory().unwrap();
+    store
+        .create_task(Task::new(1, "Old", TaskStatus::Undone, vec![], None))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    let cutoff = chrono::Local::now().naive_local();
+    std::thread::sleep(Duration::from_millis(20));
+    store.undo().unwrap();
+    store.redo().unwrap();
+
+    assert_eq!(1, store.forget_history_before(cutoff).unwrap());
+    assert!(store.read_history().unwrap().is_empty());
+}
+
+#[test]
+fn quoted_words_with_punctuation_should_be_found() {
+    let store = TaskStore::open_in_memory().unwrap();
+    for text in [
+        "Fix Bob's bike",
+        "Learn c++ templates",
+        "Answer the e-mail, what? \"now\"",
+    ] {
+        store
+            .create_task(Task::new(1, text, TaskStatus::Undone, vec![], None))
+            .unwrap();
+    }
+
+    for (words, expected) in [
+        ("Bob's", vec![1]),
+        ("c++", vec![2]),
+        ("e-mail", vec![3]),
+        ("what?", vec![3]),
+        ("\"now\"", vec![3]),
+        ("colour:red", vec![]),
+        ("temp*", vec![2]),
+    ] {
+        assert_eq!(
+            expected,
+            search_ids(&store, &quote_words(words)),
+            "{}",
+            words
+        );
+    }
+}